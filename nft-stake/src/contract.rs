@@ -1,27 +1,54 @@
 use crate::error::ContractError;
 use crate::msg::{
-    EstimatedReward, ExecuteMsg, HandleReceiveMsg, History, InstantiateMsg, MyStakedInfoResponse,
-    QueryMsg, RewardsContractInfo, Staked, StakedInfoResponse, StakingWeight, UserStakingWeight,
+    AdminLogEntry, AdminLogResponse, AdminsResponse, ConfigChange, EstimatedReward, ExecuteMsg,
+    HandleReceiveMsg, History, HistoryAction, HistoryFilter, Incentive, InstantiateMsg, MigrateMsg,
+    MyStakedInfoResponse, MyVestingResponse, Operator, OperatorScope, PauseFlags, PauseOperation,
+    PauseStatus, PendingConfigChange, PendingConfigChangesResponse, PendingDividend, QueryMsg,
+    Raffle, RafflePrize, RafflesResponse, ReceiveNftMsg, RecoverAction, RewardMode,
+    RewardsContractInfo, Role, RoleAssignment, SponsorList, Staked, StakedInfoResponse, StakedNft,
+    StakedNftInfo, StakedNftsResponse, StakerEntry, StakerInfoResponse, StakersResponse,
+    UserStakingWeight, VestedReward, VestingConfig, VestingInfoResponse, VestingSchedule,
+    VestingTotal, ViewingKeyResponse, VotingPowerResponse,
 };
-use crate::rand::sha_256;
+use crate::rand::{sha_256, Prng};
 use crate::state::{
-    State, ADMIN_VIEWING_KEY_ITEM, CONFIG_ITEM, HISTORY_STORE, PREFIX_REVOKED_PERMITS,
-    STAKED_NFTS_STORE, STAKED_STORE,
+    total_weight_checkpoint_store, user_checkpoint_store, user_history_store,
+    user_weight_checkpoint_store, Checkpoint, DividendPool, LegacyHistory, Migration, State,
+    UserDividend, ADMIN_LOG_STORE, ADMIN_VIEWING_KEYS_ITEM, ADMIN_VIEWING_KEY_ITEM,
+    BENEFICIARY_ALLOW_LIST_ITEM, CONFIG_CHANGES_STORE, CONFIG_CHANGE_ID_ITEM, CONFIG_ITEM,
+    DIVIDEND_POOLS_ITEM, INCENTIVES_ITEM, INCENTIVE_ID_ITEM, LEGACY_HISTORY_STORE, MIGRATION_ITEM,
+    OPERATORS_STORE, PAUSE_ITEM, PENDING_OWNER_ITEM, PREFIX_REVOKED_PERMITS, PRNG_SEED_ITEM,
+    RAFFLES_STORE, RAFFLE_NFT_PRIZES_STORE, RAFFLE_TOKEN_PRIZES_STORE, ROLES_STORE,
+    SPONSOR_LIST_ITEM, STAKED_NFTS_STORE, STAKED_NFT_INFO_STORE, STAKED_STORE, TIMELOCK_ITEM,
+    TOTAL_CHECKPOINTS_STORE, USER_DIVIDENDS_STORE, USER_VESTING_STORE, USER_VIEWING_KEYS_STORE,
+    VESTING_ITEM, VESTING_TOTALS_ITEM,
 };
+use base64::{engine::general_purpose::STANDARD, Engine};
 use cosmwasm_std::{
-    entry_point, from_binary, to_binary, Addr, Binary, CanonicalAddr, CosmosMsg, Decimal, Deps,
-    DepsMut, Env, MessageInfo, Response, StdError, StdResult, Uint128,
+    entry_point, from_binary, to_binary, Addr, Api, Binary, CanonicalAddr, CosmosMsg, Decimal,
+    Deps, DepsMut, Env, MessageInfo, Response, StdError, StdResult, Storage, Uint128,
 };
 use secret_toolkit::{
     permit::{validate, Permit, RevokedPermits},
     snip20::{balance_query, set_viewing_key_msg, transfer_msg, Balance},
     snip721::{
-        batch_transfer_nft_msg, nft_dossier_query, register_receive_nft_msg, NftDossier, Transfer,
-        ViewerInfo,
+        batch_transfer_nft_msg, nft_dossier_query, register_receive_nft_msg, tokens_query,
+        NftDossier, Transfer, ViewerInfo,
     },
+    storage::AppendStore,
 };
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashSet;
 
 pub const BLOCK_SIZE: usize = 256;
+pub const VIEWING_KEY_PREFIX: &str = "api_key_";
+pub const MAX_ADMIN_VIEWING_KEYS: usize = 10;
+pub const DEFAULT_TIMELOCK_DELAY: u64 = 172800;
+pub const DIVIDEND_SCALE: u128 = 1_000_000_000_000;
+pub const NFT_PAGE_SIZE: u32 = 100;
+pub const DEFAULT_RETURN_LIMIT: u32 = 30;
+pub const MAX_RETURN_LIMIT: u32 = 100;
+pub const MAX_ACTIVE_INCENTIVES: usize = 10;
 ///  Add function to get balance
 
 #[entry_point]
@@ -31,8 +58,10 @@ pub fn instantiate(
     info: MessageInfo,
     msg: InstantiateMsg,
 ) -> Result<Response, StdError> {
-    let prng_seed: Vec<u8> = sha_256(base64::encode(msg.entropy).as_bytes()).to_vec();
-    let viewing_key = base64::encode(&prng_seed);
+    let mut seed_entropy = msg.entropy.as_bytes().to_vec();
+    seed_entropy.extend_from_slice(info.sender.as_bytes());
+    PRNG_SEED_ITEM.save(deps.storage, &new_prng_seed(&_env, &seed_entropy))?;
+    let viewing_key = new_viewing_key(deps.storage, &_env, &_env.contract.address, &msg.entropy)?;
 
     // create initial state
     let state = State {
@@ -48,6 +77,10 @@ pub fn instantiate(
 
     //Save Contract state
     CONFIG_ITEM.save(deps.storage, &state)?;
+    TIMELOCK_ITEM.save(
+        deps.storage,
+        &msg.timelock_delay.unwrap_or(DEFAULT_TIMELOCK_DELAY),
+    )?;
 
     let mut response_msgs: Vec<CosmosMsg> = Vec::new();
 
@@ -86,8 +119,217 @@ pub fn instantiate(
     Ok(Response::new().add_messages(response_msgs))
 }
 
+#[entry_point]
+pub fn migrate(deps: DepsMut, env: Env, _msg: MigrateMsg) -> StdResult<Response> {
+    if PRNG_SEED_ITEM.may_load(deps.storage)?.is_none() {
+        PRNG_SEED_ITEM.save(deps.storage, &new_prng_seed(&env, b"migrate"))?;
+    }
+    if let Some(admin_key) = ADMIN_VIEWING_KEY_ITEM.may_load(deps.storage)? {
+        ADMIN_VIEWING_KEYS_ITEM.save(deps.storage, &vec![admin_key])?;
+        ADMIN_VIEWING_KEY_ITEM.remove(deps.storage);
+    }
+    let time = env.block.time.seconds();
+    seed_total_checkpoints(deps.storage, time)?;
+    // stakers are migrated in pages by MigrateHistory
+    MIGRATION_ITEM.save(deps.storage, &Migration { time, cursor: 0 })?;
+    Ok(Response::default())
+}
+
+/// Checkpoints start with the first migration that has them, so times before it
+/// read as nothing staked. Stakers are seeded by `MigrateHistory`.
+fn seed_total_checkpoints(storage: &mut dyn Storage, time: u64) -> StdResult<()> {
+    let state = CONFIG_ITEM.load(storage)?;
+    if TOTAL_CHECKPOINTS_STORE.get_len(storage)? == 0 {
+        write_checkpoint(
+            storage,
+            &TOTAL_CHECKPOINTS_STORE,
+            time,
+            state.total_staked_amount,
+        )?;
+    }
+    for weight in state.staking_weights.iter().flatten() {
+        let total_store = total_weight_checkpoint_store(&weight.weight_trait_type);
+        if total_store.get_len(storage)? == 0 {
+            write_checkpoint(storage, &total_store, time, weight.amount)?;
+        }
+    }
+    Ok(())
+}
+
+/// Checkpoints a staker's amount and weights as of the migration, skipping any
+/// that already have checkpoints from staking since.
+fn seed_user_checkpoints(
+    storage: &mut dyn Storage,
+    state: &State,
+    staker_raw: &CanonicalAddr,
+    staked: &Staked,
+    time: u64,
+) -> StdResult<()> {
+    let store = user_checkpoint_store(staker_raw);
+    if !staked.staked_amount.is_zero() && store.get_len(storage)? == 0 {
+        write_checkpoint(storage, &store, time, staked.staked_amount)?;
+    }
+    for weight in state.staking_weights.iter().flatten() {
+        let amount = user_weight_amount(
+            staked.staking_weights.as_deref().unwrap_or_default(),
+            &weight.weight_trait_type,
+        );
+        let store = user_weight_checkpoint_store(staker_raw, &weight.weight_trait_type);
+        if !amount.is_zero() && store.get_len(storage)? == 0 {
+            write_checkpoint(storage, &store, time, amount)?;
+        }
+    }
+    Ok(())
+}
+
+/// Earlier versions wrote history under the staker's human address but read it
+/// under the canonical one. Moves those entries over, converting their string
+/// actions to `HistoryAction`.
+fn migrate_user_history(
+    storage: &mut dyn Storage,
+    api: &dyn Api,
+    staker_raw: &CanonicalAddr,
+) -> StdResult<()> {
+    let staker = api.addr_humanize(staker_raw)?;
+    let legacy_store = LEGACY_HISTORY_STORE.add_suffix(staker.as_bytes());
+    let legacy_history = legacy_store
+        .iter(storage)?
+        .collect::<StdResult<Vec<LegacyHistory>>>()?;
+
+    let history_store = user_history_store(staker_raw);
+    for legacy in legacy_history.iter() {
+        let action = match legacy.action.as_str() {
+            "stake" => HistoryAction::Stake,
+            "withdraw" => HistoryAction::Withdraw,
+            "claim" => HistoryAction::Claim,
+            "eject" => HistoryAction::Eject,
+            "penalty" => HistoryAction::Penalty,
+            other => {
+                return Err(StdError::generic_err(format!(
+                    "Unknown history action {}",
+                    other
+                )))
+            }
+        };
+        let history: History = {
+            History {
+                amount: legacy.amount,
+                date: legacy.date,
+                action,
+                reward_token: None,
+                token_ids: None,
+            }
+        };
+        history_store.push(storage, &history)?;
+    }
+    for _ in legacy_history.iter() {
+        legacy_store.pop(storage)?;
+    }
+
+    Ok(())
+}
+
+/// Migrates the next `limit` stakers, starting at `start` if given or else where
+/// the last call stopped.
+pub fn try_migrate_history(
+    deps: DepsMut,
+    sender: &Addr,
+    start: Option<u32>,
+    limit: Option<u32>,
+) -> Result<Response, ContractError> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+    if sender.clone() != state.owner {
+        return Err(ContractError::CustomError {
+            val: "You don't have the permissions to execute this command".to_string(),
+        });
+    }
+
+    let mut migration = MIGRATION_ITEM
+        .may_load(deps.storage)?
+        .ok_or_else(|| StdError::generic_err("There is no migration to continue"))?;
+    let cursor = start.unwrap_or(migration.cursor);
+    if cursor >= STAKED_STORE.get_len(deps.storage)? {
+        return Err(ContractError::CustomError {
+            val: "All stakers have already been migrated".to_string(),
+        });
+    }
+
+    let limit = limit.unwrap_or(DEFAULT_RETURN_LIMIT).min(MAX_RETURN_LIMIT);
+    let stakers = STAKED_STORE
+        .iter(deps.storage)?
+        .skip(cursor as usize)
+        .take(limit as usize)
+        .collect::<StdResult<Vec<(CanonicalAddr, Staked)>>>()?;
+    for (staker_raw, staked) in stakers.iter() {
+        migrate_user_history(deps.storage, deps.api, staker_raw)?;
+        seed_user_checkpoints(deps.storage, &state, staker_raw, staked, migration.time)?;
+    }
+
+    migration.cursor = cursor + stakers.len() as u32;
+    MIGRATION_ITEM.save(deps.storage, &migration)?;
+    Ok(Response::new().add_attribute("cursor", migration.cursor.to_string()))
+}
+
 #[entry_point]
 pub fn execute(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: ExecuteMsg,
+) -> Result<Response, ContractError> {
+    let is_owner = CONFIG_ITEM.load(deps.storage)?.owner == info.sender;
+    let admin_action = get_admin_action(&msg, is_owner)?;
+    let response = handle_execute(deps.branch(), env.clone(), info.clone(), msg)?;
+
+    if let Some((action, params)) = admin_action {
+        let entry = AdminLogEntry {
+            actor: info.sender,
+            action: action.to_string(),
+            params,
+            date: env.block.time.seconds(),
+        };
+        ADMIN_LOG_STORE.push(deps.storage, &entry)?;
+    }
+
+    Ok(response)
+}
+
+/// Returns the action name and JSON parameters to record in the admin log,
+/// or `None` if `msg` isn't an admin action. Viewing keys only count when the owner creates them.
+fn get_admin_action(msg: &ExecuteMsg, is_owner: bool) -> StdResult<Option<(&'static str, String)>> {
+    let action = match msg {
+        ExecuteMsg::UpdateRewardContract { .. } => "update_reward_contract",
+        ExecuteMsg::RemoveRewards { .. } => "remove_rewards",
+        ExecuteMsg::CreateViewingKey { .. } if is_owner => "create_viewing_key",
+        ExecuteMsg::RevokeAdminViewingKeys { .. } => "revoke_admin_viewing_keys",
+        ExecuteMsg::SetActiveState { .. } => "set_active_state",
+        ExecuteMsg::SetPauseState { .. } => "set_pause_state",
+        ExecuteMsg::Eject { .. } => "eject",
+        ExecuteMsg::RecoverNfts { .. } => "recover_nfts",
+        ExecuteMsg::MigrateHistory { .. } => "migrate_history",
+        ExecuteMsg::ProposeOwner { .. } => "propose_owner",
+        ExecuteMsg::AcceptOwner { .. } => "accept_owner",
+        ExecuteMsg::GrantRole { .. } => "grant_role",
+        ExecuteMsg::RevokeRole { .. } => "revoke_role",
+        ExecuteMsg::SetTimelockDelay { .. } => "set_timelock_delay",
+        ExecuteMsg::ExecuteConfigChange { .. } => "execute_config_change",
+        ExecuteMsg::CancelConfigChange { .. } => "cancel_config_change",
+        ExecuteMsg::RunRaffle { .. } => "run_raffle",
+        ExecuteMsg::SetSponsorList { .. } => "set_sponsor_list",
+        ExecuteMsg::SetBeneficiaryAllowList { .. } => "set_beneficiary_allow_list",
+        ExecuteMsg::SetVesting { .. } => "set_vesting",
+        _ => return Ok(None),
+    };
+    let params = match msg {
+        // never write the key itself to the log
+        ExecuteMsg::SetViewingKey { .. } | ExecuteMsg::CreateViewingKey { .. } => String::new(),
+        _ => serde_json::to_string(msg).map_err(|e| StdError::generic_err(e.to_string()))?,
+    };
+
+    Ok(Some((action, params)))
+}
+
+fn handle_execute(
     deps: DepsMut,
     _env: Env,
     info: MessageInfo,
@@ -97,9 +339,12 @@ pub fn execute(
         ExecuteMsg::RevokePermit { permit_name } => {
             try_revoke_permit(deps, &info.sender, &permit_name)
         }
-        ExecuteMsg::UpdateRewardContract { contracts } => {
-            try_update_reward_contract(deps, &info.sender, contracts)
-        }
+        ExecuteMsg::UpdateRewardContract { contracts } => try_queue_config_change(
+            deps,
+            _env,
+            &info.sender,
+            ConfigChange::UpdateRewardContract { contracts },
+        ),
         ExecuteMsg::RemoveRewards {} => try_remove_rewards(deps, &info.sender),
         ExecuteMsg::BatchReceiveNft {
             from,
@@ -117,12 +362,87 @@ pub fn execute(
             try_withdraw_with_quantity(deps, _env, &info.sender, quantity)
         }
         ExecuteMsg::WithdrawFundsNoReward {} => try_withdraw_no_reward(deps, _env, &info.sender),
-        ExecuteMsg::ClaimRewards {} => try_claim_rewards(deps, _env, &info.sender),
+        ExecuteMsg::ClaimRewards {} => try_claim_rewards(deps, _env, &info.sender, &info.sender),
+        ExecuteMsg::SetOperator { operator } => {
+            try_set_operator(deps, _env, &info.sender, operator)
+        }
+        ExecuteMsg::RevokeOperator {} => try_revoke_operator(deps, &info.sender),
+        ExecuteMsg::ClaimRewardsFor { staker } => {
+            try_claim_rewards_for(deps, _env, &info.sender, staker)
+        }
+        ExecuteMsg::ClaimDividends {} => try_claim_dividends(deps, _env, &info.sender),
+        ExecuteMsg::WithdrawVested {} => try_withdraw_vested(deps, _env, &info.sender),
+        ExecuteMsg::TransferPosition {
+            recipient,
+            token_ids,
+        } => try_transfer_position(deps, _env, &info.sender, recipient, token_ids),
         ExecuteMsg::SetViewingKey { key } => try_set_viewing_key(deps, _env, &info.sender, key),
+        ExecuteMsg::CreateViewingKey { entropy } => {
+            try_create_viewing_key(deps, _env, &info.sender, entropy)
+        }
+        ExecuteMsg::RevokeAdminViewingKeys {} => try_revoke_admin_viewing_keys(deps, &info.sender),
         ExecuteMsg::SetActiveState { is_active } => {
             try_set_active_state(deps, _env, &info.sender, is_active)
         }
-        ExecuteMsg::Eject { staker } => try_eject(deps, _env, &info.sender, &staker),
+        ExecuteMsg::SetPauseState {
+            operation,
+            is_paused,
+            resume_time,
+            reason,
+        } => try_set_pause_state(
+            deps,
+            _env,
+            &info.sender,
+            operation,
+            is_paused,
+            resume_time,
+            reason,
+        ),
+        ExecuteMsg::Eject {
+            staker,
+            stakers,
+            forfeit_reason,
+        } => {
+            let stakers = staker
+                .into_iter()
+                .chain(stakers.unwrap_or_default())
+                .collect();
+            try_eject(deps, _env, &info.sender, stakers, forfeit_reason)
+        }
+        ExecuteMsg::MigrateHistory { start, limit } => {
+            try_migrate_history(deps, &info.sender, start, limit)
+        }
+        ExecuteMsg::RecoverNfts { token_ids, action } => {
+            try_recover_nfts(deps, _env, &info.sender, token_ids, action)
+        }
+        ExecuteMsg::ProposeOwner { new_owner } => try_propose_owner(deps, &info.sender, new_owner),
+        ExecuteMsg::AcceptOwner {} => try_accept_owner(deps, &info.sender),
+        ExecuteMsg::GrantRole { address, role } => {
+            try_set_role(deps, &info.sender, address, role, true)
+        }
+        ExecuteMsg::RevokeRole { address, role } => {
+            try_set_role(deps, &info.sender, address, role, false)
+        }
+        ExecuteMsg::SetTimelockDelay { delay } => try_queue_config_change(
+            deps,
+            _env,
+            &info.sender,
+            ConfigChange::SetTimelockDelay { delay },
+        ),
+        ExecuteMsg::SetVesting { vesting } => try_set_vesting(deps, _env, &info.sender, vesting),
+        ExecuteMsg::ExecuteConfigChange { id } => {
+            try_execute_config_change(deps, _env, &info.sender, id)
+        }
+        ExecuteMsg::CancelConfigChange { id } => try_cancel_config_change(deps, &info.sender, id),
+        ExecuteMsg::SetBeneficiaryAllowList { allowed } => {
+            try_set_beneficiary_allow_list(deps, &info.sender, allowed)
+        }
+        ExecuteMsg::SetSponsorList { list } => try_set_sponsor_list(deps, &info.sender, list),
+        ExecuteMsg::RefundIncentive { id } => try_refund_incentive(deps, _env, &info.sender, id),
+        ExecuteMsg::RunRaffle {
+            prize,
+            weight_trait_type,
+        } => try_run_raffle(deps, _env, &info.sender, prize, weight_trait_type),
     }
 }
 fn receive(
@@ -140,7 +460,29 @@ fn receive(
 
     if let Some(bin_msg) = msg {
         match from_binary(&bin_msg)? {
-            HandleReceiveMsg::ReceiveRewards {} => {
+            HandleReceiveMsg::SponsorIncentive { start, end } => {
+                let current_time = _env.block.time.seconds();
+                check_not_paused(deps.storage, PauseOperation::RewardFunding, current_time)?;
+                add_incentive(
+                    deps.storage,
+                    info_sender,
+                    from,
+                    amount,
+                    start,
+                    end,
+                    current_time,
+                )?
+            }
+            HandleReceiveMsg::RafflePrize {} => {
+                check_role(deps.as_ref(), &state, from, Role::RewardManager)?;
+                deposit_raffle_tokens(deps.storage, &state, info_sender, amount)?
+            }
+            HandleReceiveMsg::ReceiveRewards { mode } => {
+                check_not_paused(
+                    deps.storage,
+                    PauseOperation::RewardFunding,
+                    _env.block.time.seconds(),
+                )?;
                 let reward_contract_index = state
                     .reward_contracts
                     .iter()
@@ -151,10 +493,39 @@ fn receive(
                             + &" Address is not correct reward snip contract".to_string(),
                     });
                 }
+                let total_staked_amount = state.total_staked_amount;
                 let reward_contract = &mut state.reward_contracts[reward_contract_index.unwrap()];
-                reward_contract.total_rewards += amount;
+                match mode.unwrap_or(RewardMode::Stream) {
+                    RewardMode::Stream => reward_contract.total_rewards += amount,
+                    RewardMode::Dividend => add_dividend(
+                        deps.storage,
+                        total_staked_amount,
+                        &reward_contract.address,
+                        &reward_contract.code_hash,
+                        amount,
+                    )?,
+                }
 
                 CONFIG_ITEM.save(deps.storage, &state)?;
+
+                let deposit_history: History = {
+                    History {
+                        amount,
+                        date: _env.block.time.seconds(),
+                        action: HistoryAction::RewardDeposit,
+                        reward_token: Some(info_sender.clone()),
+                        token_ids: None,
+                    }
+                };
+                // deposits go to the admin log rather than the depositor's own history
+                let entry = AdminLogEntry {
+                    actor: from.clone(),
+                    action: "reward_deposit".to_string(),
+                    params: serde_json::to_string(&deposit_history)
+                        .map_err(|e| StdError::generic_err(e.to_string()))?,
+                    date: _env.block.time.seconds(),
+                };
+                ADMIN_LOG_STORE.push(deps.storage, &entry)?;
             }
         }
     } else {
@@ -174,15 +545,22 @@ fn try_batch_receive(
     msg: Option<Binary>,
 ) -> Result<Response, ContractError> {
     deps.api.debug(&format!("Receive received"));
-    let mut response_msgs: Vec<CosmosMsg> = Vec::new();
-    let mut state = CONFIG_ITEM.load(deps.storage)?;
+    let state = CONFIG_ITEM.load(deps.storage)?;
+    // anything that isn't a `ReceiveNftMsg`, like a memo, is a plain stake for `from`
+    let receive_msg: Option<ReceiveNftMsg> = msg.and_then(|bin_msg| from_binary(&bin_msg).ok());
+    if let Some(ReceiveNftMsg::RafflePrize {}) = receive_msg {
+        check_role(deps.as_ref(), &state, from, Role::RewardManager)?;
+        deposit_raffle_nfts(deps.storage, &state, sender, token_ids)?;
+        return Ok(Response::default());
+    }
 
     if !state.is_active {
         return Err(ContractError::CustomError {
             val: "You cannot perform this action right now".to_string(),
         });
     }
-    let history_store = HISTORY_STORE.add_suffix(from.to_string().as_bytes());
+    let current_time = _env.block.time.seconds();
+    check_not_paused(deps.storage, PauseOperation::Staking, current_time)?;
 
     if sender != &state.staking_contract.address {
         return Err(ContractError::CustomError {
@@ -190,6 +568,68 @@ fn try_batch_receive(
         });
     }
 
+    let beneficiary = match receive_msg {
+        Some(ReceiveNftMsg::ReceiveStake { beneficiary }) => beneficiary,
+        _ => None,
+    };
+    let beneficiary = get_beneficiary(deps.as_ref(), from, beneficiary)?;
+    stake_nfts(deps, _env, state, &beneficiary, token_ids)
+}
+
+/// The address a stake sent by `from` is credited to. Once the owner sets an allow
+/// list, only addresses on it can stake on behalf of others.
+fn get_beneficiary(
+    deps: Deps,
+    from: &Addr,
+    beneficiary: Option<Addr>,
+) -> Result<Addr, ContractError> {
+    let beneficiary = match beneficiary {
+        Some(beneficiary) => deps.api.addr_validate(beneficiary.as_str())?,
+        None => return Ok(from.clone()),
+    };
+    if beneficiary != *from {
+        if let Some(allowed) = BENEFICIARY_ALLOW_LIST_ITEM.may_load(deps.storage)? {
+            if !allowed.contains(from) {
+                return Err(ContractError::CustomError {
+                    val: "You aren't allowed to stake on behalf of others".to_string(),
+                });
+            }
+        }
+    }
+    Ok(beneficiary)
+}
+
+pub fn try_set_beneficiary_allow_list(
+    deps: DepsMut,
+    sender: &Addr,
+    allowed: Option<Vec<Addr>>,
+) -> Result<Response, ContractError> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+    if sender.clone() != state.owner {
+        return Err(ContractError::CustomError {
+            val: "You don't have the permissions to execute this command".to_string(),
+        });
+    }
+
+    match allowed {
+        Some(allowed) => BENEFICIARY_ALLOW_LIST_ITEM.save(deps.storage, &allowed)?,
+        None => BENEFICIARY_ALLOW_LIST_ITEM.remove(deps.storage),
+    }
+    Ok(Response::default())
+}
+
+/// Credits `token_ids` as staked by `from`, claiming any pending rewards first.
+/// The caller is responsible for making sure the contract actually holds the tokens.
+fn stake_nfts(
+    deps: DepsMut,
+    _env: Env,
+    mut state: State,
+    from: &Addr,
+    token_ids: Vec<String>,
+) -> Result<Response, ContractError> {
+    let mut response_msgs: Vec<CosmosMsg> = Vec::new();
+    let history_store = user_history_store(&deps.api.addr_canonicalize(from.as_str())?);
+
     let current_time = _env.block.time.seconds();
     let mut staked = STAKED_STORE
         .get(
@@ -210,6 +650,7 @@ fn try_batch_receive(
         .unwrap_or(Vec::new());
 
     for id in token_ids.iter() {
+        let mut nft_weights: Vec<UserStakingWeight> = Vec::new();
         if state.trait_restriction.is_some() || state.staking_weights.is_some() {
             let meta: NftDossier = nft_dossier_query(
                 deps.querier,
@@ -267,6 +708,10 @@ fn try_batch_receive(
                     let weight_amount =
                         Uint128::from(weight_trait.unwrap().value.parse::<u32>().unwrap());
                     weight.amount += weight_amount;
+                    nft_weights.push(UserStakingWeight {
+                        amount: weight_amount,
+                        weight_trait_type: weight.weight_trait_type.to_string(),
+                    });
                     let user_staking_weight = staked
                         .staking_weights
                         .as_ref()
@@ -288,6 +733,11 @@ fn try_batch_receive(
                 staked.staking_weights = Some(new_user_weights);
             }
         }
+        let nft_info = StakedNftInfo {
+            staked_date: current_time,
+            weights: nft_weights,
+        };
+        STAKED_NFT_INFO_STORE.insert(deps.storage, id, &nft_info)?;
         staked_nfts.push(id.to_string());
     }
 
@@ -309,24 +759,33 @@ fn try_batch_receive(
                 History {
                     amount: rewards.estimated_rewards,
                     date: current_time,
-                    action: "claim".to_string(),
+                    action: HistoryAction::Claim,
+                    reward_token: Some(reward_contract.address.clone()),
+                    token_ids: None,
                 }
             };
 
             history_store.push(deps.storage, &claim_history)?;
-            response_msgs.push(transfer_msg(
-                from.to_string(),
+            response_msgs.extend(reward_msg(
+                deps.storage,
+                &deps.api.addr_canonicalize(from.as_str())?,
+                from,
                 rewards.estimated_rewards,
-                None,
-                None,
-                BLOCK_SIZE,
-                reward_contract.code_hash.to_string(),
-                reward_contract.address.to_string(),
+                &reward_contract.address,
+                &reward_contract.code_hash,
+                current_time,
             )?);
             reward_contract.total_rewards -= rewards.estimated_rewards;
         }
     }
 
+    settle_dividends(
+        deps.storage,
+        &deps.api.addr_canonicalize(from.as_str())?,
+        staked.staked_amount,
+        state.total_staked_amount,
+        current_time,
+    )?;
     state.total_staked_amount += Uint128::from(token_ids.len() as u128);
     staked.staked_amount += Uint128::from(token_ids.len() as u128);
     staked.last_staked_date = Some(current_time);
@@ -342,12 +801,22 @@ fn try_batch_receive(
         &deps.api.addr_canonicalize(&from.to_string())?,
         &staked_nfts,
     )?;
+    checkpoint_stake(
+        deps.storage,
+        &deps.api.addr_canonicalize(from.as_str())?,
+        staked.staked_amount,
+        staked.staking_weights.as_deref().unwrap_or_default(),
+        &state,
+        current_time,
+    )?;
 
     let stake_history: History = {
         History {
             amount: Uint128::from(token_ids.len() as u128),
             date: current_time,
-            action: "stake".to_string(),
+            action: HistoryAction::Stake,
+            reward_token: None,
+            token_ids: Some(token_ids.clone()),
         }
     };
 
@@ -363,8 +832,9 @@ fn try_withdraw_with_quantity(
     quantity: Uint128,
 ) -> Result<Response, ContractError> {
     let mut state = CONFIG_ITEM.load(deps.storage)?;
-    let history_store = HISTORY_STORE.add_suffix(info_sender.to_string().as_bytes());
+    let history_store = user_history_store(&deps.api.addr_canonicalize(info_sender.as_str())?);
     let current_time = _env.block.time.seconds();
+    check_not_paused(deps.storage, PauseOperation::Withdrawing, current_time)?;
     let staked = STAKED_STORE
         .get(
             deps.storage,
@@ -421,19 +891,21 @@ fn try_withdraw_with_quantity(
                 History {
                     amount: rewards.estimated_rewards,
                     date: current_time,
-                    action: "claim".to_string(),
+                    action: HistoryAction::Claim,
+                    reward_token: Some(reward_contract.address.clone()),
+                    token_ids: None,
                 }
             };
 
             history_store.push(deps.storage, &claim_history)?;
-            response_msgs.push(transfer_msg(
-                info_sender.to_string(),
+            response_msgs.extend(reward_msg(
+                deps.storage,
+                &deps.api.addr_canonicalize(info_sender.as_str())?,
+                info_sender,
                 rewards.estimated_rewards,
-                None,
-                None,
-                BLOCK_SIZE,
-                reward_contract.code_hash.to_string(),
-                reward_contract.address.to_string(),
+                &reward_contract.address,
+                &reward_contract.code_hash,
+                current_time,
             )?);
             reward_contract.total_rewards -= rewards.estimated_rewards;
         }
@@ -441,12 +913,19 @@ fn try_withdraw_with_quantity(
     //QUANTITY LOGIC
     let staked_nfts_leftover = staked_nfts.split_off(quantity.u128() as usize);
     let staked_nfts_leftover_len = Uint128::from(staked_nfts_leftover.len() as u128);
+    settle_dividends(
+        deps.storage,
+        &deps.api.addr_canonicalize(info_sender.as_str())?,
+        staked.staked_amount,
+        state.total_staked_amount,
+        current_time,
+    )?;
     state.total_staked_amount -= quantity;
 
     let mut transfers: Vec<Transfer> = Vec::new();
     transfers.push(Transfer {
         recipient: info_sender.to_string(),
-        token_ids: staked_nfts,
+        token_ids: staked_nfts.clone(),
         memo: None,
     });
 
@@ -479,23 +958,35 @@ fn try_withdraw_with_quantity(
         &deps.api.addr_canonicalize(&info_sender.to_string())?,
         &staked_nfts_leftover,
     )?;
+    checkpoint_stake(
+        deps.storage,
+        &deps.api.addr_canonicalize(info_sender.as_str())?,
+        staked_nfts_leftover_len,
+        &[],
+        &state,
+        current_time,
+    )?;
 
     let stake_history: History = {
         History {
             amount: staked_nfts_leftover_len,
             date: current_time,
-            action: "withdraw".to_string(),
+            action: HistoryAction::Withdraw,
+            reward_token: None,
+            token_ids: Some(staked_nfts.clone()),
         }
     };
 
     history_store.push(deps.storage, &stake_history)?;
+    remove_nft_info(deps.storage, &staked_nfts)?;
     Ok(Response::new().add_messages(response_msgs))
 }
 
 fn try_withdraw(deps: DepsMut, _env: Env, info_sender: &Addr) -> Result<Response, ContractError> {
     let mut state = CONFIG_ITEM.load(deps.storage)?;
-    let history_store = HISTORY_STORE.add_suffix(info_sender.to_string().as_bytes());
+    let history_store = user_history_store(&deps.api.addr_canonicalize(info_sender.as_str())?);
     let current_time = _env.block.time.seconds();
+    check_not_paused(deps.storage, PauseOperation::Withdrawing, current_time)?;
     let mut response_msgs: Vec<CosmosMsg> = Vec::new();
 
     let staked = STAKED_STORE
@@ -559,30 +1050,39 @@ fn try_withdraw(deps: DepsMut, _env: Env, info_sender: &Addr) -> Result<Response
                 History {
                     amount: rewards.estimated_rewards,
                     date: current_time,
-                    action: "claim".to_string(),
+                    action: HistoryAction::Claim,
+                    reward_token: Some(reward_contract.address.clone()),
+                    token_ids: None,
                 }
             };
 
             history_store.push(deps.storage, &claim_history)?;
-            response_msgs.push(transfer_msg(
-                info_sender.to_string(),
+            response_msgs.extend(reward_msg(
+                deps.storage,
+                &deps.api.addr_canonicalize(info_sender.as_str())?,
+                info_sender,
                 rewards.estimated_rewards,
-                None,
-                None,
-                BLOCK_SIZE,
-                reward_contract.code_hash.to_string(),
-                reward_contract.address.to_string(),
+                &reward_contract.address,
+                &reward_contract.code_hash,
+                current_time,
             )?);
             reward_contract.total_rewards -= rewards.estimated_rewards;
         }
     }
 
+    settle_dividends(
+        deps.storage,
+        &deps.api.addr_canonicalize(info_sender.as_str())?,
+        staked.staked_amount,
+        state.total_staked_amount,
+        current_time,
+    )?;
     state.total_staked_amount -= staked.staked_amount;
 
     let mut transfers: Vec<Transfer> = Vec::new();
     transfers.push(Transfer {
         recipient: info_sender.to_string(),
-        token_ids: staked_nfts,
+        token_ids: staked_nfts.clone(),
         memo: None,
     });
 
@@ -611,16 +1111,27 @@ fn try_withdraw(deps: DepsMut, _env: Env, info_sender: &Addr) -> Result<Response
         &deps.api.addr_canonicalize(&info_sender.to_string())?,
         &Vec::new(),
     )?;
+    checkpoint_stake(
+        deps.storage,
+        &deps.api.addr_canonicalize(info_sender.as_str())?,
+        Uint128::zero(),
+        &[],
+        &state,
+        current_time,
+    )?;
 
     let stake_history: History = {
         History {
             amount: staked.staked_amount,
             date: current_time,
-            action: "withdraw".to_string(),
+            action: HistoryAction::Withdraw,
+            reward_token: None,
+            token_ids: Some(staked_nfts.clone()),
         }
     };
 
     history_store.push(deps.storage, &stake_history)?;
+    remove_nft_info(deps.storage, &staked_nfts)?;
     Ok(Response::new().add_messages(response_msgs))
 }
 
@@ -630,7 +1141,12 @@ fn try_withdraw_no_reward(
     info_sender: &Addr,
 ) -> Result<Response, ContractError> {
     let mut state = CONFIG_ITEM.load(deps.storage)?;
-    let history_store = HISTORY_STORE.add_suffix(info_sender.to_string().as_bytes());
+    let history_store = user_history_store(&deps.api.addr_canonicalize(info_sender.as_str())?);
+    check_not_paused(
+        deps.storage,
+        PauseOperation::Withdrawing,
+        _env.block.time.seconds(),
+    )?;
     let staked = STAKED_STORE
         .get(
             deps.storage,
@@ -660,7 +1176,7 @@ fn try_withdraw_no_reward(
     let mut transfers: Vec<Transfer> = Vec::new();
     transfers.push(Transfer {
         recipient: info_sender.to_string(),
-        token_ids: staked_nfts,
+        token_ids: staked_nfts.clone(),
         memo: None,
     });
 
@@ -675,6 +1191,13 @@ fn try_withdraw_no_reward(
 
     let current_time = _env.block.time.seconds();
 
+    settle_dividends(
+        deps.storage,
+        &deps.api.addr_canonicalize(info_sender.as_str())?,
+        staked.staked_amount,
+        state.total_staked_amount,
+        current_time,
+    )?;
     state.total_staked_amount -= staked.staked_amount;
     CONFIG_ITEM.save(deps.storage, &state)?;
     STAKED_STORE.insert(
@@ -693,16 +1216,27 @@ fn try_withdraw_no_reward(
         &deps.api.addr_canonicalize(&info_sender.to_string())?,
         &Vec::new(),
     )?;
+    checkpoint_stake(
+        deps.storage,
+        &deps.api.addr_canonicalize(info_sender.as_str())?,
+        Uint128::zero(),
+        &[],
+        &state,
+        current_time,
+    )?;
 
     let stake_history: History = {
         History {
             amount: staked.staked_amount,
             date: current_time,
-            action: "withdraw".to_string(),
+            action: HistoryAction::Withdraw,
+            reward_token: None,
+            token_ids: Some(staked_nfts.clone()),
         }
     };
 
     history_store.push(deps.storage, &stake_history)?;
+    remove_nft_info(deps.storage, &staked_nfts)?;
     Ok(Response::new().add_messages(response_msgs))
 }
 
@@ -710,9 +1244,10 @@ fn try_claim_rewards(
     deps: DepsMut,
     _env: Env,
     info_sender: &Addr,
+    recipient: &Addr,
 ) -> Result<Response, ContractError> {
     let mut state = CONFIG_ITEM.load(deps.storage)?;
-    let history_store = HISTORY_STORE.add_suffix(info_sender.to_string().as_bytes());
+    let history_store = user_history_store(&deps.api.addr_canonicalize(info_sender.as_str())?);
     let mut staked = STAKED_STORE
         .get(
             deps.storage,
@@ -728,6 +1263,7 @@ fn try_claim_rewards(
 
     let mut response_msgs: Vec<CosmosMsg> = Vec::new();
     let current_time = _env.block.time.seconds();
+    check_not_paused(deps.storage, PauseOperation::Claiming, current_time)?;
     let rewards_to_claim = get_estimated_rewards(&staked, &current_time, &state)?;
     for rewards in rewards_to_claim.iter() {
         let reward_contract_index = state
@@ -746,19 +1282,21 @@ fn try_claim_rewards(
                 History {
                     amount: rewards.estimated_rewards,
                     date: current_time,
-                    action: "claim".to_string(),
+                    action: HistoryAction::Claim,
+                    reward_token: Some(reward_contract.address.clone()),
+                    token_ids: None,
                 }
             };
 
             history_store.push(deps.storage, &claim_history)?;
-            response_msgs.push(transfer_msg(
-                info_sender.to_string(),
+            response_msgs.extend(reward_msg(
+                deps.storage,
+                &deps.api.addr_canonicalize(recipient.as_str())?,
+                recipient,
                 rewards.estimated_rewards,
-                None,
-                None,
-                BLOCK_SIZE,
-                reward_contract.code_hash.to_string(),
-                reward_contract.address.to_string(),
+                &reward_contract.address,
+                &reward_contract.code_hash,
+                current_time,
             )?);
             staked.last_claimed_date = Some(current_time);
             reward_contract.total_rewards -= rewards.estimated_rewards;
@@ -779,6 +1317,81 @@ fn try_claim_rewards(
     Ok(Response::new().add_messages(response_msgs))
 }
 
+pub fn try_set_operator(
+    deps: DepsMut,
+    env: Env,
+    sender: &Addr,
+    operator: Operator,
+) -> Result<Response, ContractError> {
+    let address = deps.api.addr_validate(operator.address.as_str())?;
+    if address == *sender {
+        return Err(ContractError::CustomError {
+            val: "You can't be your own operator".to_string(),
+        });
+    }
+    if operator.expires.unwrap_or(u64::MAX) <= env.block.time.seconds() {
+        return Err(ContractError::CustomError {
+            val: "The operator would already be expired".to_string(),
+        });
+    }
+    if operator.scope == OperatorScope::ClaimAndRestake {
+        return Err(ContractError::CustomError {
+            val: "Rewards can't be restaked in this pool".to_string(),
+        });
+    }
+    let recipient = match operator.recipient {
+        Some(recipient) => Some(deps.api.addr_validate(recipient.as_str())?),
+        None => None,
+    };
+
+    let operator = Operator {
+        address,
+        recipient,
+        ..operator
+    };
+    OPERATORS_STORE.insert(
+        deps.storage,
+        &deps.api.addr_canonicalize(sender.as_str())?,
+        &operator,
+    )?;
+    Ok(Response::default())
+}
+
+pub fn try_revoke_operator(deps: DepsMut, sender: &Addr) -> Result<Response, ContractError> {
+    OPERATORS_STORE.remove(deps.storage, &deps.api.addr_canonicalize(sender.as_str())?)?;
+    Ok(Response::default())
+}
+
+/// The operator `staker` granted to `sender`, as long as it hasn't expired.
+fn get_operator(
+    deps: Deps,
+    env: &Env,
+    sender: &Addr,
+    staker: &Addr,
+) -> Result<Operator, ContractError> {
+    OPERATORS_STORE
+        .get(deps.storage, &deps.api.addr_canonicalize(staker.as_str())?)
+        .filter(|x| {
+            x.address == *sender && x.expires.unwrap_or(u64::MAX) > env.block.time.seconds()
+        })
+        .ok_or_else(|| ContractError::CustomError {
+            val: "You aren't an operator for this staker".to_string(),
+        })
+}
+
+/// Claims `staker`'s rewards for them, paid to the recipient they set for the operator.
+fn try_claim_rewards_for(
+    deps: DepsMut,
+    env: Env,
+    sender: &Addr,
+    staker: Addr,
+) -> Result<Response, ContractError> {
+    let staker = deps.api.addr_validate(staker.as_str())?;
+    let operator = get_operator(deps.as_ref(), &env, sender, &staker)?;
+    let recipient = operator.recipient.unwrap_or_else(|| staker.clone());
+    try_claim_rewards(deps, env, &staker, &recipient)
+}
+
 fn try_revoke_permit(
     deps: DepsMut,
     sender: &Addr,
@@ -794,20 +1407,133 @@ fn try_revoke_permit(
     Ok(Response::default())
 }
 
-fn try_update_reward_contract(
+fn try_queue_config_change(
     deps: DepsMut,
+    env: Env,
     sender: &Addr,
-    contracts: Vec<RewardsContractInfo>,
+    change: ConfigChange,
 ) -> Result<Response, ContractError> {
-    let mut state = CONFIG_ITEM.load(deps.storage)?;
-    let mut response_msgs: Vec<CosmosMsg> = Vec::new();
+    let state = CONFIG_ITEM.load(deps.storage)?;
+    check_config_change_role(deps.as_ref(), &state, sender, &change)?;
 
-    if sender.clone() != state.owner {
+    let current_time = env.block.time.seconds();
+    let id = CONFIG_CHANGE_ID_ITEM
+        .may_load(deps.storage)?
+        .unwrap_or_default();
+    let eta = current_time + get_timelock_delay(deps.storage)?;
+    let pending_change = PendingConfigChange {
+        id,
+        change,
+        proposer: sender.clone(),
+        queued_time: current_time,
+        eta,
+    };
+    CONFIG_CHANGES_STORE.insert(deps.storage, &id, &pending_change)?;
+    CONFIG_CHANGE_ID_ITEM.save(deps.storage, &(id + 1))?;
+
+    Ok(Response::new()
+        .add_attribute("change_id", id.to_string())
+        .add_attribute("eta", eta.to_string()))
+}
+
+fn try_execute_config_change(
+    deps: DepsMut,
+    env: Env,
+    sender: &Addr,
+    id: u64,
+) -> Result<Response, ContractError> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+    let pending_change = get_pending_config_change(deps.storage, id)?;
+    check_config_change_role(deps.as_ref(), &state, sender, &pending_change.change)?;
+
+    if env.block.time.seconds() < pending_change.eta {
         return Err(ContractError::CustomError {
-            val: "You don't have the permissions to execute this command".to_string(),
+            val: format!(
+                "Config change {} is timelocked until {}",
+                id, pending_change.eta
+            ),
         });
     }
 
+    CONFIG_CHANGES_STORE.remove(deps.storage, &id)?;
+    let mut response_msgs: Vec<CosmosMsg> = Vec::new();
+    match pending_change.change {
+        ConfigChange::UpdateRewardContract { contracts } => {
+            response_msgs = update_reward_contract(deps.storage, contracts)?;
+        }
+        ConfigChange::SetTimelockDelay { delay } => {
+            TIMELOCK_ITEM.save(deps.storage, &delay)?;
+        }
+        ConfigChange::SetVesting { vesting } => match vesting {
+            Some(vesting) => VESTING_ITEM.save(deps.storage, &vesting)?,
+            None => VESTING_ITEM.remove(deps.storage),
+        },
+    }
+
+    Ok(Response::new().add_messages(response_msgs))
+}
+
+fn try_cancel_config_change(
+    deps: DepsMut,
+    sender: &Addr,
+    id: u64,
+) -> Result<Response, ContractError> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+    let pending_change = get_pending_config_change(deps.storage, id)?;
+    check_config_change_role(deps.as_ref(), &state, sender, &pending_change.change)?;
+
+    CONFIG_CHANGES_STORE.remove(deps.storage, &id)?;
+
+    Ok(Response::default())
+}
+
+fn get_pending_config_change(
+    storage: &dyn Storage,
+    id: u64,
+) -> Result<PendingConfigChange, ContractError> {
+    CONFIG_CHANGES_STORE
+        .get(storage, &id)
+        .ok_or_else(|| ContractError::CustomError {
+            val: format!("Config change {} not found", id),
+        })
+}
+
+/// Reward contract changes belong to the reward manager, the timelock delay
+/// itself can only be changed by the owner.
+fn check_config_change_role(
+    deps: Deps,
+    state: &State,
+    sender: &Addr,
+    change: &ConfigChange,
+) -> Result<(), ContractError> {
+    match change {
+        ConfigChange::UpdateRewardContract { .. } | ConfigChange::SetVesting { .. } => {
+            check_role(deps, state, sender, Role::RewardManager)
+        }
+        ConfigChange::SetTimelockDelay { .. } => {
+            if sender.clone() != state.owner {
+                return Err(ContractError::CustomError {
+                    val: "You don't have the permissions to execute this command".to_string(),
+                });
+            }
+            Ok(())
+        }
+    }
+}
+
+fn get_timelock_delay(storage: &dyn Storage) -> StdResult<u64> {
+    Ok(TIMELOCK_ITEM
+        .may_load(storage)?
+        .unwrap_or(DEFAULT_TIMELOCK_DELAY))
+}
+
+fn update_reward_contract(
+    storage: &mut dyn Storage,
+    contracts: Vec<RewardsContractInfo>,
+) -> Result<Vec<CosmosMsg>, ContractError> {
+    let mut state = CONFIG_ITEM.load(storage)?;
+    let mut response_msgs: Vec<CosmosMsg> = Vec::new();
+
     for reward_contract in state.reward_contracts.iter() {
         if reward_contract.total_rewards != Uint128::from(0u128) {
             return Err(ContractError::CustomError {
@@ -827,9 +1553,9 @@ fn try_update_reward_contract(
     }
 
     state.reward_contracts = contracts;
-    CONFIG_ITEM.save(deps.storage, &state)?;
+    CONFIG_ITEM.save(storage, &state)?;
 
-    Ok(Response::new().add_messages(response_msgs))
+    Ok(response_msgs)
 }
 
 fn try_remove_rewards(deps: DepsMut, sender: &Addr) -> Result<Response, ContractError> {
@@ -867,27 +1593,102 @@ pub fn try_set_viewing_key(
     sender: &Addr,
     key: String,
 ) -> Result<Response, ContractError> {
-    let state = CONFIG_ITEM.load(deps.storage)?;
-    let prng_seed: Vec<u8> = sha_256(base64::encode(key).as_bytes()).to_vec();
-    let viewing_key = base64::encode(&prng_seed);
+    let sender_raw = deps.api.addr_canonicalize(sender.as_str())?;
+    USER_VIEWING_KEYS_STORE.insert(deps.storage, &sender_raw, &hash_viewing_key(&key))?;
 
-    let vk: ViewerInfo = {
-        ViewerInfo {
-            address: sender.to_string(),
-            viewing_key: viewing_key,
-        }
-    };
+    Ok(Response::default())
+}
+
+pub fn try_create_viewing_key(
+    deps: DepsMut,
+    env: Env,
+    sender: &Addr,
+    entropy: String,
+) -> Result<Response, ContractError> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+    let key = new_viewing_key(deps.storage, &env, sender, &entropy)?;
+    let viewing_key = hash_viewing_key(&key);
 
+    let sender_raw = deps.api.addr_canonicalize(sender.as_str())?;
+    USER_VIEWING_KEYS_STORE.insert(deps.storage, &sender_raw, &viewing_key)?;
+
+    // keys the owner creates also unlock the admin queries, oldest key is dropped at the limit
     if sender.clone() == state.owner {
-        ADMIN_VIEWING_KEY_ITEM.save(deps.storage, &vk)?;
-    } else {
+        let mut admin_keys = ADMIN_VIEWING_KEYS_ITEM
+            .may_load(deps.storage)?
+            .unwrap_or_default();
+        if admin_keys.len() >= MAX_ADMIN_VIEWING_KEYS {
+            admin_keys.remove(0);
+        }
+        admin_keys.push(ViewerInfo {
+            address: sender.to_string(),
+            viewing_key,
+        });
+        ADMIN_VIEWING_KEYS_ITEM.save(deps.storage, &admin_keys)?;
+    }
+
+    Ok(Response::new().set_data(to_binary(&ViewingKeyResponse { key })?))
+}
+
+pub fn try_revoke_admin_viewing_keys(
+    deps: DepsMut,
+    sender: &Addr,
+) -> Result<Response, ContractError> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+
+    if sender.clone() != state.owner {
         return Err(ContractError::CustomError {
             val: "You don't have the permissions to execute this command".to_string(),
         });
     }
+
+    ADMIN_VIEWING_KEYS_ITEM.remove(deps.storage);
+
     Ok(Response::default())
 }
 
+/// Draws a viewing key from `Prng` seeded with the stored seed, then rotates the seed.
+fn new_viewing_key(
+    storage: &mut dyn Storage,
+    env: &Env,
+    sender: &Addr,
+    entropy: &str,
+) -> StdResult<String> {
+    let prng_seed = PRNG_SEED_ITEM.may_load(storage)?.unwrap_or_default();
+    let mut rng_entropy = entropy.as_bytes().to_vec();
+    rng_entropy.extend_from_slice(&env.block.time.nanos().to_be_bytes());
+    rng_entropy.extend_from_slice(sender.as_bytes());
+    if let Some(random) = &env.block.random {
+        rng_entropy.extend_from_slice(random.as_slice());
+    }
+
+    let mut rng = Prng::new(&prng_seed, &rng_entropy);
+    let key = format!(
+        "{}{}",
+        VIEWING_KEY_PREFIX,
+        STANDARD.encode(rng.rand_bytes())
+    );
+    PRNG_SEED_ITEM.save(storage, &rng.rand_bytes().to_vec())?;
+
+    Ok(key)
+}
+
+/// Derives the initial `Prng` seed from `entropy` and the block.
+fn new_prng_seed(env: &Env, entropy: &[u8]) -> Vec<u8> {
+    let mut seed_data = entropy.to_vec();
+    seed_data.extend_from_slice(&env.block.time.nanos().to_be_bytes());
+    seed_data.extend_from_slice(env.contract.address.as_bytes());
+    if let Some(random) = &env.block.random {
+        seed_data.extend_from_slice(random.as_slice());
+    }
+    sha_256(&seed_data).to_vec()
+}
+
+/// Viewing keys are only ever stored hashed.
+fn hash_viewing_key(key: &str) -> String {
+    STANDARD.encode(sha_256(STANDARD.encode(key).as_bytes()))
+}
+
 pub fn try_set_active_state(
     deps: DepsMut,
     _env: Env,
@@ -896,23 +1697,188 @@ pub fn try_set_active_state(
 ) -> Result<Response, ContractError> {
     let mut state = CONFIG_ITEM.load(deps.storage)?;
 
+    check_role(deps.as_ref(), &state, sender, Role::Pauser)?;
+    state.is_active = is_active;
+
+    CONFIG_ITEM.save(deps.storage, &state)?;
+
+    Ok(Response::default())
+}
+
+pub fn try_set_pause_state(
+    deps: DepsMut,
+    _env: Env,
+    sender: &Addr,
+    operation: PauseOperation,
+    is_paused: bool,
+    resume_time: Option<u64>,
+    reason: Option<String>,
+) -> Result<Response, ContractError> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+
+    check_role(deps.as_ref(), &state, sender, Role::Pauser)?;
+
+    let mut pause_flags = PAUSE_ITEM.may_load(deps.storage)?.unwrap_or_default();
+    let status = match operation {
+        PauseOperation::Staking => &mut pause_flags.staking,
+        PauseOperation::Claiming => &mut pause_flags.claiming,
+        PauseOperation::Withdrawing => &mut pause_flags.withdrawing,
+        PauseOperation::RewardFunding => &mut pause_flags.reward_funding,
+    };
+    *status = if is_paused {
+        PauseStatus {
+            is_paused,
+            resume_time,
+            reason,
+        }
+    } else {
+        PauseStatus::default()
+    };
+
+    PAUSE_ITEM.save(deps.storage, &pause_flags)?;
+
+    Ok(Response::default())
+}
+
+pub fn try_propose_owner(
+    deps: DepsMut,
+    sender: &Addr,
+    new_owner: Addr,
+) -> Result<Response, ContractError> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+
     if sender.clone() != state.owner {
         return Err(ContractError::CustomError {
             val: "You don't have the permissions to execute this command".to_string(),
         });
     }
-    state.is_active = is_active;
 
+    let new_owner = deps.api.addr_validate(new_owner.as_str())?;
+    PENDING_OWNER_ITEM.save(deps.storage, &new_owner)?;
+
+    Ok(Response::default())
+}
+
+pub fn try_accept_owner(deps: DepsMut, sender: &Addr) -> Result<Response, ContractError> {
+    let mut state = CONFIG_ITEM.load(deps.storage)?;
+
+    if PENDING_OWNER_ITEM.may_load(deps.storage)?.as_ref() != Some(sender) {
+        return Err(ContractError::CustomError {
+            val: "You are not the pending owner".to_string(),
+        });
+    }
+
+    state.owner = sender.clone();
     CONFIG_ITEM.save(deps.storage, &state)?;
+    PENDING_OWNER_ITEM.remove(deps.storage);
+    // the admin viewing keys were created by the previous owner
+    ADMIN_VIEWING_KEYS_ITEM.remove(deps.storage);
+
+    Ok(Response::default())
+}
+
+pub fn try_set_role(
+    deps: DepsMut,
+    sender: &Addr,
+    address: Addr,
+    role: Role,
+    grant: bool,
+) -> Result<Response, ContractError> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+
+    if sender.clone() != state.owner {
+        return Err(ContractError::CustomError {
+            val: "You don't have the permissions to execute this command".to_string(),
+        });
+    }
+
+    let address_raw = deps.api.addr_canonicalize(address.as_str())?;
+    let mut roles = ROLES_STORE
+        .get(deps.storage, &address_raw)
+        .unwrap_or_default();
+    roles.retain(|x| *x != role);
+    if grant {
+        roles.push(role);
+    }
+
+    if roles.is_empty() {
+        ROLES_STORE.remove(deps.storage, &address_raw)?;
+    } else {
+        ROLES_STORE.insert(deps.storage, &address_raw, &roles)?;
+    }
 
     Ok(Response::default())
 }
 
+/// Passes if `sender` is the owner or has been granted `role`.
+fn check_role(deps: Deps, state: &State, sender: &Addr, role: Role) -> Result<(), ContractError> {
+    if sender.clone() == state.owner {
+        return Ok(());
+    }
+
+    let sender_raw = deps.api.addr_canonicalize(sender.as_str())?;
+    let roles = ROLES_STORE
+        .get(deps.storage, &sender_raw)
+        .unwrap_or_default();
+    if !roles.contains(&role) {
+        return Err(ContractError::CustomError {
+            val: "You don't have the permissions to execute this command".to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+fn pause_status(
+    storage: &dyn Storage,
+    operation: PauseOperation,
+) -> StdResult<(&'static str, PauseStatus)> {
+    let pause_flags = PAUSE_ITEM.may_load(storage)?.unwrap_or_default();
+    Ok(match operation {
+        PauseOperation::Staking => ("Staking", pause_flags.staking),
+        PauseOperation::Claiming => ("Claiming", pause_flags.claiming),
+        PauseOperation::Withdrawing => ("Withdrawing", pause_flags.withdrawing),
+        PauseOperation::RewardFunding => ("Reward funding", pause_flags.reward_funding),
+    })
+}
+
+/// Whether `operation` is paused and its resume time, if any, hasn't been reached yet.
+fn is_paused(
+    storage: &dyn Storage,
+    operation: PauseOperation,
+    current_time: u64,
+) -> StdResult<bool> {
+    let (_, status) = pause_status(storage, operation)?;
+    Ok(status.is_paused && !matches!(status.resume_time, Some(x) if current_time >= x))
+}
+
+/// Errors if `operation` is paused and its resume time, if any, hasn't been reached yet.
+fn check_not_paused(
+    storage: &dyn Storage,
+    operation: PauseOperation,
+    current_time: u64,
+) -> Result<(), ContractError> {
+    if is_paused(storage, operation.clone(), current_time)? {
+        let (name, status) = pause_status(storage, operation)?;
+        return Err(ContractError::CustomError {
+            val: match status.reason {
+                Some(reason) => format!("{} is paused: {}", name, reason),
+                None => format!("{} is paused", name),
+            },
+        });
+    }
+
+    Ok(())
+}
+
+/// Returns the NFTs of every staker in `stakers`. Accrued rewards are paid out unless a
+/// `forfeit_reason` is given, in which case they stay in the pool and a penalty is recorded.
 pub fn try_eject(
     deps: DepsMut,
     _env: Env,
     sender: &Addr,
-    staker: &Addr,
+    stakers: Vec<Addr>,
+    forfeit_reason: Option<String>,
 ) -> Result<Response, ContractError> {
     let mut state = CONFIG_ITEM.load(deps.storage)?;
     if sender.clone() != state.owner {
@@ -921,29 +1887,117 @@ pub fn try_eject(
         });
     }
 
-    let history_store = HISTORY_STORE.add_suffix(staker.to_string().as_bytes());
-    let current_time = _env.block.time.seconds();
-    let staked_nfts = STAKED_NFTS_STORE
-        .get(
-            deps.storage,
-            &deps.api.addr_canonicalize(&staker.to_string())?,
-        )
-        .ok_or_else(|| StdError::generic_err("NFTs aren't staked"))?;
-
-    if staked_nfts.len() == 0 {
+    if stakers.is_empty() {
         return Err(ContractError::CustomError {
-            val: "There are no NFTs to withdraw".to_string(),
+            val: "No stakers were given".to_string(),
         });
     }
 
-    let staked_nfts_len = Uint128::from(staked_nfts.len() as u128);
+    let current_time = _env.block.time.seconds();
     let mut response_msgs: Vec<CosmosMsg> = Vec::new();
     let mut transfers: Vec<Transfer> = Vec::new();
-    transfers.push(Transfer {
-        recipient: staker.to_string(),
-        token_ids: staked_nfts,
-        memo: None,
-    });
+
+    for staker in stakers.iter() {
+        let staker_raw = deps.api.addr_canonicalize(staker.as_str())?;
+        let history_store = user_history_store(&staker_raw);
+        let staked = STAKED_STORE
+            .get(deps.storage, &staker_raw)
+            .ok_or_else(|| StdError::generic_err(format!("{} isn't staked", staker)))?;
+        let staked_nfts = STAKED_NFTS_STORE
+            .get(deps.storage, &staker_raw)
+            .ok_or_else(|| StdError::generic_err(format!("{} has no NFTs staked", staker)))?;
+
+        if staked_nfts.is_empty() {
+            return Err(ContractError::CustomError {
+                val: format!("{} has no NFTs to withdraw", staker),
+            });
+        }
+
+        if forfeit_reason.is_some() {
+            let forfeited_rewards = get_estimated_rewards(&staked, &current_time, &state)?;
+            for rewards in forfeited_rewards.iter() {
+                if rewards.estimated_rewards > Uint128::from(0u128) {
+                    let reward_token = state
+                        .reward_contracts
+                        .iter()
+                        .find(|x| x.name == rewards.reward_contract_name)
+                        .map(|x| x.address.clone());
+                    let penalty_history: History = {
+                        History {
+                            amount: rewards.estimated_rewards,
+                            date: current_time,
+                            action: HistoryAction::Penalty,
+                            reward_token,
+                            token_ids: None,
+                        }
+                    };
+                    history_store.push(deps.storage, &penalty_history)?;
+                }
+            }
+        } else {
+            // whatever the pool can't cover is forfeited with the position, as before
+            let (claim_msgs, _) = claim_rewards_to(
+                deps.storage,
+                &mut state,
+                &staked,
+                &staker_raw,
+                staker,
+                &history_store,
+                current_time,
+            )?;
+            response_msgs.extend(claim_msgs);
+        }
+
+        remove_user_weights(&mut state, &staked);
+        settle_dividends(
+            deps.storage,
+            &staker_raw,
+            staked.staked_amount,
+            state.total_staked_amount,
+            current_time,
+        )?;
+        state.total_staked_amount -= staked.staked_amount;
+
+        transfers.push(Transfer {
+            recipient: staker.to_string(),
+            token_ids: staked_nfts.clone(),
+            memo: None,
+        });
+
+        STAKED_STORE.insert(
+            deps.storage,
+            &staker_raw,
+            &Staked {
+                last_claimed_date: None,
+                staked_amount: Uint128::from(0u128),
+                last_staked_date: None,
+                staking_weights: Some(Vec::new()),
+            },
+        )?;
+        STAKED_NFTS_STORE.insert(deps.storage, &staker_raw, &Vec::new())?;
+        checkpoint_stake(
+            deps.storage,
+            &staker_raw,
+            Uint128::zero(),
+            &[],
+            &state,
+            current_time,
+        )?;
+
+        let eject_history: History = {
+            History {
+                amount: staked.staked_amount,
+                date: current_time,
+                action: HistoryAction::Eject,
+                reward_token: None,
+                token_ids: Some(staked_nfts.clone()),
+            }
+        };
+        history_store.push(deps.storage, &eject_history)?;
+        remove_nft_info(deps.storage, &staked_nfts)?;
+    }
+
+    CONFIG_ITEM.save(deps.storage, &state)?;
 
     let cosmos_batch_msg = batch_transfer_nft_msg(
         transfers,
@@ -954,56 +2008,383 @@ pub fn try_eject(
     )?;
     response_msgs.push(cosmos_batch_msg);
 
-    state.total_staked_amount -= staked_nfts_len;
-    CONFIG_ITEM.save(deps.storage, &state)?;
-    STAKED_STORE.insert(
-        deps.storage,
-        &deps.api.addr_canonicalize(&staker.to_string())?,
-        &Staked {
+    let mut response = Response::new().add_messages(response_msgs);
+    if let Some(reason) = forfeit_reason {
+        response = response.add_attribute("forfeit_reason", reason);
+    }
+    Ok(response)
+}
+
+/// Moves staked NFTs to `recipient` along with their weights, without restarting
+/// them. Both sides' pending rewards are paid out first so neither accrues on the
+/// other's stake. NFTs staked before their weights were recorded have to be
+/// restaked first, as there is no telling which weights to move.
+fn try_transfer_position(
+    deps: DepsMut,
+    _env: Env,
+    info_sender: &Addr,
+    recipient: Addr,
+    token_ids: Vec<String>,
+) -> Result<Response, ContractError> {
+    let mut state = CONFIG_ITEM.load(deps.storage)?;
+    if !state.is_active {
+        return Err(ContractError::CustomError {
+            val: "You cannot perform this action right now".to_string(),
+        });
+    }
+    let current_time = _env.block.time.seconds();
+    check_not_paused(deps.storage, PauseOperation::Withdrawing, current_time)?;
+
+    let recipient = deps.api.addr_validate(recipient.as_str())?;
+    if recipient == *info_sender {
+        return Err(ContractError::CustomError {
+            val: "You can't transfer a position to yourself".to_string(),
+        });
+    }
+    let sender_raw = deps.api.addr_canonicalize(info_sender.as_str())?;
+    let recipient_raw = deps.api.addr_canonicalize(recipient.as_str())?;
+    let mut staked = STAKED_STORE
+        .get(deps.storage, &sender_raw)
+        .ok_or_else(|| StdError::generic_err("You aren't staked"))?;
+    let mut staked_nfts = STAKED_NFTS_STORE
+        .get(deps.storage, &sender_raw)
+        .unwrap_or_default();
+    if token_ids.is_empty() {
+        return Err(ContractError::CustomError {
+            val: "There is nothing to transfer".to_string(),
+        });
+    }
+    let staked_count = staked_nfts.len();
+    staked_nfts.retain(|x| !token_ids.contains(x));
+    if staked_count - staked_nfts.len() != token_ids.len() {
+        return Err(ContractError::CustomError {
+            val: "You can only transfer NFTs you have staked".to_string(),
+        });
+    }
+    let mut nft_infos: Vec<StakedNftInfo> = Vec::new();
+    for token_id in token_ids.iter() {
+        nft_infos.push(
+            STAKED_NFT_INFO_STORE
+                .get(deps.storage, token_id)
+                .ok_or_else(|| ContractError::CustomError {
+                    val: "NFTs staked before their weights were recorded can't be transferred"
+                        .to_string(),
+                })?,
+        );
+    }
+    let mut recipient_staked = STAKED_STORE
+        .get(deps.storage, &recipient_raw)
+        .unwrap_or(Staked {
             last_claimed_date: None,
             staked_amount: Uint128::from(0u128),
             last_staked_date: None,
             staking_weights: Some(Vec::new()),
-        },
-    )?;
+        });
+    let mut recipient_nfts = STAKED_NFTS_STORE
+        .get(deps.storage, &recipient_raw)
+        .unwrap_or_default();
 
-    STAKED_NFTS_STORE.insert(
-        deps.storage,
-        &deps.api.addr_canonicalize(&staker.to_string())?,
-        &Vec::new(),
-    )?;
+    let mut response_msgs: Vec<CosmosMsg> = Vec::new();
+    for (staker_raw, staker, position) in [
+        (&sender_raw, info_sender, &mut staked),
+        (&recipient_raw, &recipient, &mut recipient_staked),
+    ] {
+        let (claim_msgs, all_paid) = claim_rewards_to(
+            deps.storage,
+            &mut state,
+            position,
+            staker_raw,
+            staker,
+            &user_history_store(staker_raw),
+            current_time,
+        )?;
+        // the claim date moves on below, so nothing accrued may be left unpaid
+        if !all_paid {
+            return Err(ContractError::CustomError {
+                val: "The pool can't cover the accrued rewards, so the position can't be transferred yet"
+                    .to_string(),
+            });
+        }
+        response_msgs.extend(claim_msgs);
+        settle_dividends(
+            deps.storage,
+            staker_raw,
+            position.staked_amount,
+            state.total_staked_amount,
+            current_time,
+        )?;
+        position.last_claimed_date = Some(current_time);
+    }
 
-    let stake_history: History = {
-        History {
-            amount: staked_nfts_len,
+    for nft_info in nft_infos.iter() {
+        move_user_weights(&mut staked, &mut recipient_staked, &nft_info.weights);
+    }
+    let amount = Uint128::from(token_ids.len() as u128);
+    staked.staked_amount -= amount;
+    recipient_staked.staked_amount += amount;
+    // the recipient's stake counts as staked no earlier than either side's
+    recipient_staked.last_staked_date = recipient_staked
+        .last_staked_date
+        .max(staked.last_staked_date);
+    if staked.staked_amount.is_zero() {
+        staked.last_claimed_date = None;
+        staked.last_staked_date = None;
+    }
+    recipient_nfts.extend(token_ids.iter().cloned());
+    CONFIG_ITEM.save(deps.storage, &state)?;
+
+    for (staker_raw, position, nfts, action) in [
+        (
+            &sender_raw,
+            &staked,
+            &staked_nfts,
+            HistoryAction::TransferOut,
+        ),
+        (
+            &recipient_raw,
+            &recipient_staked,
+            &recipient_nfts,
+            HistoryAction::TransferIn,
+        ),
+    ] {
+        STAKED_STORE.insert(deps.storage, staker_raw, position)?;
+        STAKED_NFTS_STORE.insert(deps.storage, staker_raw, nfts)?;
+        checkpoint_stake(
+            deps.storage,
+            staker_raw,
+            position.staked_amount,
+            position.staking_weights.as_deref().unwrap_or_default(),
+            &state,
+            current_time,
+        )?;
+        let transfer_history = History {
+            amount,
             date: current_time,
-            action: "withdraw".to_string(),
-        }
-    };
+            action,
+            reward_token: None,
+            token_ids: Some(token_ids.clone()),
+        };
+        user_history_store(staker_raw).push(deps.storage, &transfer_history)?;
+    }
 
-    history_store.push(deps.storage, &stake_history)?;
     Ok(Response::new().add_messages(response_msgs))
 }
 
-fn get_estimated_rewards(
+/// Moves the trait weights of transferred NFTs from one staker to another.
+fn move_user_weights(from: &mut Staked, to: &mut Staked, weights: &[UserStakingWeight]) {
+    for weight in weights.iter() {
+        if let Some(from_weight) = from.staking_weights.as_mut().and_then(|x| {
+            x.iter_mut()
+                .find(|x| x.weight_trait_type == weight.weight_trait_type)
+        }) {
+            from_weight.amount = from_weight.amount.saturating_sub(weight.amount);
+        }
+        let to_weights = to.staking_weights.get_or_insert_with(Vec::new);
+        match to_weights
+            .iter_mut()
+            .find(|x| x.weight_trait_type == weight.weight_trait_type)
+        {
+            Some(to_weight) => to_weight.amount += weight.amount,
+            None => to_weights.push(weight.clone()),
+        }
+    }
+}
+
+/// Pays every reward `staked` has accrued to `recipient` and records the claims.
+/// Rewards a reward contract can't currently cover are skipped, as on withdraw.
+fn claim_rewards_to(
+    storage: &mut dyn Storage,
+    state: &mut State,
     staked: &Staked,
-    current_time: &u64,
-    state: &State,
-) -> StdResult<Vec<EstimatedReward>> {
-    let mut expected_rewards: Vec<EstimatedReward> = Vec::new();
+    staker_raw: &CanonicalAddr,
+    recipient: &Addr,
+    history_store: &AppendStore<History>,
+    current_time: u64,
+) -> StdResult<(Vec<CosmosMsg>, bool)> {
+    let mut response_msgs: Vec<CosmosMsg> = Vec::new();
+    let mut all_paid = true;
+    let rewards_to_claim = get_estimated_rewards(staked, &current_time, state)?;
 
-    for reward_contract in state.reward_contracts.iter() {
-        let mut estimated_rewards = Uint128::from(0u128);
-        //estimate logic for a normal weighting
-        if state.staking_weights.is_none()
-            && staked.staked_amount > Uint128::from(0u128)
-            && state.total_staked_amount > Uint128::from(0u128)
-        {
-            let date = if staked.last_claimed_date.is_some() {
-                staked.last_claimed_date.unwrap()
-            } else {
-                staked.last_staked_date.unwrap()
-            };
+    for rewards in rewards_to_claim.iter() {
+        let reward_contract = state
+            .reward_contracts
+            .iter_mut()
+            .find(|x| x.name == rewards.reward_contract_name)
+            .ok_or_else(|| StdError::generic_err("Reward contract not found"))?;
+
+        if rewards.estimated_rewards > reward_contract.total_rewards {
+            all_paid = false;
+        } else if rewards.estimated_rewards > Uint128::from(0u128) {
+            let claim_history: History = {
+                History {
+                    amount: rewards.estimated_rewards,
+                    date: current_time,
+                    action: HistoryAction::Claim,
+                    reward_token: Some(reward_contract.address.clone()),
+                    token_ids: None,
+                }
+            };
+
+            history_store.push(storage, &claim_history)?;
+            response_msgs.extend(reward_msg(
+                storage,
+                staker_raw,
+                recipient,
+                rewards.estimated_rewards,
+                &reward_contract.address,
+                &reward_contract.code_hash,
+                current_time,
+            )?);
+            reward_contract.total_rewards -= rewards.estimated_rewards;
+        }
+    }
+
+    Ok((response_msgs, all_paid))
+}
+
+fn remove_nft_info(storage: &mut dyn Storage, token_ids: &[String]) -> StdResult<()> {
+    for token_id in token_ids.iter() {
+        STAKED_NFT_INFO_STORE.remove(storage, token_id)?;
+    }
+    Ok(())
+}
+
+/// Takes a staker's trait weights out of the pool totals so the remaining stakers'
+/// share of a weighted pool stays correct.
+fn remove_user_weights(state: &mut State, staked: &Staked) {
+    if let (Some(weights), Some(user_weights)) = (
+        state.staking_weights.as_mut(),
+        staked.staking_weights.as_ref(),
+    ) {
+        for weight in weights.iter_mut() {
+            if let Some(user_weight) = user_weights
+                .iter()
+                .find(|x| x.weight_trait_type == weight.weight_trait_type)
+            {
+                weight.amount = weight.amount.saturating_sub(user_weight.amount);
+            }
+        }
+    }
+}
+
+pub fn try_recover_nfts(
+    deps: DepsMut,
+    _env: Env,
+    sender: &Addr,
+    token_ids: Vec<String>,
+    action: RecoverAction,
+) -> Result<Response, ContractError> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+    if sender.clone() != state.owner {
+        return Err(ContractError::CustomError {
+            val: "You don't have the permissions to execute this command".to_string(),
+        });
+    }
+
+    let mut unique_ids = token_ids.clone();
+    unique_ids.sort();
+    unique_ids.dedup();
+    if token_ids.is_empty() || unique_ids.len() != token_ids.len() {
+        return Err(ContractError::CustomError {
+            val: "A list of unique token ids is required".to_string(),
+        });
+    }
+
+    let stray_nfts: HashSet<String> = get_stray_nfts(deps.as_ref(), &_env, &state)?
+        .into_iter()
+        .collect();
+    for id in token_ids.iter() {
+        if !stray_nfts.contains(id) {
+            return Err(ContractError::CustomError {
+                val: format!("Token {} is not an untracked NFT held by this contract", id),
+            });
+        }
+    }
+
+    match action {
+        RecoverAction::Return { recipient } => {
+            let recipient = deps.api.addr_validate(recipient.as_str())?;
+            let transfers: Vec<Transfer> = vec![Transfer {
+                recipient: recipient.to_string(),
+                token_ids,
+                memo: None,
+            }];
+
+            let cosmos_batch_msg = batch_transfer_nft_msg(
+                transfers,
+                None,
+                BLOCK_SIZE,
+                state.staking_contract.code_hash.clone(),
+                state.staking_contract.address.to_string(),
+            )?;
+            Ok(Response::new().add_message(cosmos_batch_msg))
+        }
+        RecoverAction::Stake { staker } => {
+            let staker = deps.api.addr_validate(staker.as_str())?;
+            stake_nfts(deps, _env, state, &staker, token_ids)
+        }
+    }
+}
+
+/// Lists the tokens the staking collection reports as owned by this contract
+/// that are not tracked in `STAKED_NFTS_STORE`.
+fn get_stray_nfts(deps: Deps, env: &Env, state: &State) -> StdResult<Vec<String>> {
+    let mut tracked_nfts: HashSet<String> = HashSet::new();
+    for item in STAKED_NFTS_STORE.iter(deps.storage)? {
+        let (_, staked_nfts) = item?;
+        tracked_nfts.extend(staked_nfts);
+    }
+
+    let mut stray_nfts: Vec<String> = Vec::new();
+    let mut start_after: Option<String> = None;
+    loop {
+        let token_list = tokens_query(
+            deps.querier,
+            env.contract.address.to_string(),
+            None,
+            state.viewing_key.clone(),
+            start_after,
+            Some(NFT_PAGE_SIZE),
+            BLOCK_SIZE,
+            state.staking_contract.code_hash.clone(),
+            state.staking_contract.address.to_string(),
+        )?;
+        let page_len = token_list.tokens.len();
+        start_after = token_list.tokens.last().cloned();
+        stray_nfts.extend(
+            token_list
+                .tokens
+                .into_iter()
+                .filter(|id| !tracked_nfts.contains(id)),
+        );
+
+        if page_len < NFT_PAGE_SIZE as usize {
+            break;
+        }
+    }
+
+    Ok(stray_nfts)
+}
+
+fn get_estimated_rewards(
+    staked: &Staked,
+    current_time: &u64,
+    state: &State,
+) -> StdResult<Vec<EstimatedReward>> {
+    let mut expected_rewards: Vec<EstimatedReward> = Vec::new();
+
+    for reward_contract in state.reward_contracts.iter() {
+        let mut estimated_rewards = Uint128::from(0u128);
+        //estimate logic for a normal weighting
+        if state.staking_weights.is_none()
+            && staked.staked_amount > Uint128::from(0u128)
+            && state.total_staked_amount > Uint128::from(0u128)
+        {
+            let date = if staked.last_claimed_date.is_some() {
+                staked.last_claimed_date.unwrap()
+            } else {
+                staked.last_staked_date.unwrap()
+            };
 
             let user_reward_percentage =
                 Decimal::from_ratio(staked.staked_amount, state.total_staked_amount);
@@ -1052,38 +2433,1049 @@ fn get_estimated_rewards(
             }
         }
 
-        let estimated_reward: EstimatedReward = {
-            EstimatedReward {
-                estimated_rewards: estimated_rewards,
-                reward_contract_name: reward_contract.name.to_string(),
-            }
-        };
-        expected_rewards.push(estimated_reward);
+        let estimated_reward: EstimatedReward = {
+            EstimatedReward {
+                estimated_rewards: estimated_rewards,
+                reward_contract_name: reward_contract.name.to_string(),
+            }
+        };
+        expected_rewards.push(estimated_reward);
+    }
+    return Ok(expected_rewards);
+}
+
+/// Raffle prizes are held next to the pool's own funds, so tokens the pool pays
+/// out in any other way can't be raffled.
+fn check_raffle_token(
+    storage: &dyn Storage,
+    state: &State,
+    token: &Addr,
+) -> Result<(), ContractError> {
+    let is_pool_token = *token == state.staking_contract.address
+        || state.reward_contracts.iter().any(|x| x.address == *token)
+        || DIVIDEND_POOLS_ITEM
+            .may_load(storage)?
+            .unwrap_or_default()
+            .iter()
+            .any(|x| x.reward_token == *token)
+        || INCENTIVES_ITEM
+            .may_load(storage)?
+            .unwrap_or_default()
+            .iter()
+            .any(|x| x.token == *token)
+        || VESTING_TOTALS_ITEM
+            .may_load(storage)?
+            .unwrap_or_default()
+            .iter()
+            .any(|x| x.reward_token == *token);
+    if is_pool_token {
+        return Err(ContractError::CustomError {
+            val: "Staked, reward, dividend, incentive and vesting tokens can't be raffled"
+                .to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn deposit_raffle_tokens(
+    storage: &mut dyn Storage,
+    state: &State,
+    token: &Addr,
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    check_raffle_token(storage, state, token)?;
+    let deposited = RAFFLE_TOKEN_PRIZES_STORE
+        .get(storage, token)
+        .unwrap_or_default();
+    RAFFLE_TOKEN_PRIZES_STORE.insert(storage, token, &(deposited + amount))?;
+    Ok(())
+}
+
+fn deposit_raffle_nfts(
+    storage: &mut dyn Storage,
+    state: &State,
+    collection: &Addr,
+    token_ids: Vec<String>,
+) -> Result<(), ContractError> {
+    check_raffle_token(storage, state, collection)?;
+    let mut deposited = RAFFLE_NFT_PRIZES_STORE
+        .get(storage, collection)
+        .unwrap_or_default();
+    deposited.extend(token_ids);
+    RAFFLE_NFT_PRIZES_STORE.insert(storage, collection, &deposited)?;
+    Ok(())
+}
+
+/// Takes `prize` out of the deposited raffle prizes, failing if it wasn't deposited.
+fn take_raffle_prize(storage: &mut dyn Storage, prize: &RafflePrize) -> Result<(), ContractError> {
+    match prize {
+        RafflePrize::Token {
+            address,
+            amount,
+            winners,
+            ..
+        } => {
+            let deposited = RAFFLE_TOKEN_PRIZES_STORE
+                .get(storage, address)
+                .unwrap_or_default();
+            let total = amount
+                .checked_mul(Uint128::from(*winners))
+                .map_err(StdError::from)?;
+            if total > deposited {
+                return Err(ContractError::CustomError {
+                    val: format!(
+                        "Only {} of the {} prize has been deposited",
+                        deposited, total
+                    ),
+                });
+            }
+            RAFFLE_TOKEN_PRIZES_STORE.insert(storage, address, &(deposited - total))?;
+        }
+        RafflePrize::Nft {
+            address, token_ids, ..
+        } => {
+            let mut deposited = RAFFLE_NFT_PRIZES_STORE
+                .get(storage, address)
+                .unwrap_or_default();
+            for token_id in token_ids.iter() {
+                let index = deposited
+                    .iter()
+                    .position(|x| x == token_id)
+                    .ok_or_else(|| ContractError::CustomError {
+                        val: format!("Token {} hasn't been deposited as a prize", token_id),
+                    })?;
+                deposited.swap_remove(index);
+            }
+            RAFFLE_NFT_PRIZES_STORE.insert(storage, address, &deposited)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn try_run_raffle(
+    deps: DepsMut,
+    env: Env,
+    sender: &Addr,
+    prize: RafflePrize,
+    weight_trait_type: Option<String>,
+) -> Result<Response, ContractError> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+    check_role(deps.as_ref(), &state, sender, Role::RewardManager)?;
+
+    let (prize_address, num_winners) = match &prize {
+        RafflePrize::Token {
+            address, winners, ..
+        } => (address, *winners as usize),
+        RafflePrize::Nft {
+            address, token_ids, ..
+        } => (address, token_ids.len()),
+    };
+    check_raffle_token(deps.storage, &state, prize_address)?;
+    take_raffle_prize(deps.storage, &prize)?;
+    if let Some(weight_trait_type) = &weight_trait_type {
+        if !state
+            .staking_weights
+            .iter()
+            .flatten()
+            .any(|x| x.weight_trait_type == *weight_trait_type)
+        {
+            return Err(ContractError::CustomError {
+                val: "Unknown weight trait type".to_string(),
+            });
+        }
+    }
+
+    let mut entries: Vec<(CanonicalAddr, u128)> = Vec::new();
+    for item in STAKED_STORE.iter(deps.storage)? {
+        let (staker_raw, staked) = item?;
+        let weight = match &weight_trait_type {
+            Some(weight_trait_type) => user_weight_amount(
+                staked.staking_weights.as_deref().unwrap_or_default(),
+                weight_trait_type,
+            ),
+            None => staked.staked_amount,
+        };
+        if !weight.is_zero() {
+            entries.push((staker_raw, weight.u128()));
+        }
+    }
+    if num_winners == 0 || entries.len() < num_winners {
+        return Err(ContractError::CustomError {
+            val: format!("Not enough stakers to draw {} winners", num_winners),
+        });
+    }
+
+    let prng_seed = PRNG_SEED_ITEM.may_load(deps.storage)?.unwrap_or_default();
+    let mut rng_entropy = env.block.time.nanos().to_be_bytes().to_vec();
+    if let Some(random) = &env.block.random {
+        rng_entropy.extend_from_slice(random.as_slice());
+    }
+    let mut rng = Prng::new(&prng_seed, &rng_entropy);
+    let winners_raw = draw_winners(&mut rng, entries, num_winners);
+    PRNG_SEED_ITEM.save(deps.storage, &rng.rand_bytes().to_vec())?;
+
+    let current_time = env.block.time.seconds();
+    let mut winners: Vec<Addr> = Vec::new();
+    let mut response_msgs: Vec<CosmosMsg> = Vec::new();
+    let mut transfers: Vec<Transfer> = Vec::new();
+    for (index, winner_raw) in winners_raw.iter().enumerate() {
+        let winner = deps.api.addr_humanize(winner_raw)?;
+        let win_history = match &prize {
+            RafflePrize::Token {
+                address,
+                code_hash,
+                amount,
+                ..
+            } => {
+                response_msgs.push(transfer_msg(
+                    winner.to_string(),
+                    *amount,
+                    None,
+                    None,
+                    BLOCK_SIZE,
+                    code_hash.to_string(),
+                    address.to_string(),
+                )?);
+                History {
+                    amount: *amount,
+                    date: current_time,
+                    action: HistoryAction::RaffleWin,
+                    reward_token: Some(address.clone()),
+                    token_ids: None,
+                }
+            }
+            RafflePrize::Nft {
+                address, token_ids, ..
+            } => {
+                transfers.push(Transfer {
+                    recipient: winner.to_string(),
+                    token_ids: vec![token_ids[index].clone()],
+                    memo: None,
+                });
+                History {
+                    amount: Uint128::from(1u128),
+                    date: current_time,
+                    action: HistoryAction::RaffleWin,
+                    reward_token: Some(address.clone()),
+                    token_ids: Some(vec![token_ids[index].clone()]),
+                }
+            }
+        };
+        user_history_store(winner_raw).push(deps.storage, &win_history)?;
+        winners.push(winner);
+    }
+    if let RafflePrize::Nft {
+        address, code_hash, ..
+    } = &prize
+    {
+        response_msgs.push(batch_transfer_nft_msg(
+            transfers,
+            None,
+            BLOCK_SIZE,
+            code_hash.to_string(),
+            address.to_string(),
+        )?);
+    }
+
+    let raffle = Raffle {
+        prize,
+        weight_trait_type,
+        winners,
+        date: current_time,
+    };
+    RAFFLES_STORE.push(deps.storage, &raffle)?;
+
+    Ok(Response::new().add_messages(response_msgs))
+}
+
+/// Draws `count` distinct stakers, each with odds proportional to their weight.
+fn draw_winners(
+    rng: &mut Prng,
+    mut entries: Vec<(CanonicalAddr, u128)>,
+    count: usize,
+) -> Vec<CanonicalAddr> {
+    let mut total: u128 = entries.iter().map(|x| x.1).sum();
+    let mut winners: Vec<CanonicalAddr> = Vec::new();
+    while winners.len() < count && total > 0 {
+        let mut bytes = [0u8; 16];
+        bytes.copy_from_slice(&rng.rand_bytes()[..16]);
+        let mut pick = u128::from_be_bytes(bytes) % total;
+        let index = entries
+            .iter()
+            .position(|(_, weight)| {
+                if pick < *weight {
+                    return true;
+                }
+                pick -= *weight;
+                false
+            })
+            .unwrap_or(entries.len() - 1);
+        let (winner_raw, weight) = entries.swap_remove(index);
+        total -= weight;
+        winners.push(winner_raw);
+    }
+    winners
+}
+
+fn try_claim_dividends(deps: DepsMut, env: Env, sender: &Addr) -> Result<Response, ContractError> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+    let current_time = env.block.time.seconds();
+    check_not_paused(deps.storage, PauseOperation::Claiming, current_time)?;
+    let sender_raw = deps.api.addr_canonicalize(sender.as_str())?;
+    let staked_amount = STAKED_STORE
+        .get(deps.storage, &sender_raw)
+        .map_or(Uint128::zero(), |x| x.staked_amount);
+
+    let response_msgs = pay_dividends(
+        deps.storage,
+        &sender_raw,
+        sender,
+        staked_amount,
+        state.total_staked_amount,
+        current_time,
+    )?;
+    if response_msgs.is_empty() {
+        return Err(ContractError::CustomError {
+            val: "There are no dividends to claim".to_string(),
+        });
+    }
+
+    Ok(Response::new().add_messages(response_msgs))
+}
+
+/// Splits `amount` between everything currently staked by bumping the reward
+/// token's per-share accumulator, so no staker has to be visited.
+fn add_dividend(
+    storage: &mut dyn Storage,
+    total_staked_amount: Uint128,
+    reward_token: &Addr,
+    code_hash: &str,
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    if total_staked_amount.is_zero() {
+        return Err(ContractError::CustomError {
+            val: "Nothing is staked to pay dividends to".to_string(),
+        });
+    }
+
+    let mut pools = DIVIDEND_POOLS_ITEM.may_load(storage)?.unwrap_or_default();
+    add_to_pool(
+        &mut pools,
+        reward_token,
+        code_hash,
+        amount,
+        total_staked_amount,
+    );
+    DIVIDEND_POOLS_ITEM.save(storage, &pools)?;
+    Ok(())
+}
+
+fn add_to_pool(
+    pools: &mut Vec<DividendPool>,
+    reward_token: &Addr,
+    code_hash: &str,
+    amount: Uint128,
+    total_staked_amount: Uint128,
+) {
+    let index = match pools.iter().position(|x| x.reward_token == *reward_token) {
+        Some(index) => index,
+        None => {
+            pools.push(DividendPool {
+                reward_token: reward_token.clone(),
+                code_hash: code_hash.to_string(),
+                acc_per_share: Uint128::zero(),
+            });
+            pools.len() - 1
+        }
+    };
+    pools[index].acc_per_share += amount.multiply_ratio(DIVIDEND_SCALE, total_staked_amount);
+}
+
+/// Dividend pools with every sponsor incentive streamed in up to `current_time`.
+/// Time with nothing staked is set aside for the sponsor to take back.
+fn get_dripped_pools(
+    storage: &dyn Storage,
+    total_staked_amount: Uint128,
+    current_time: u64,
+) -> StdResult<(Vec<DividendPool>, Vec<Incentive>)> {
+    let mut pools = DIVIDEND_POOLS_ITEM.may_load(storage)?.unwrap_or_default();
+    let mut incentives = INCENTIVES_ITEM.may_load(storage)?.unwrap_or_default();
+    for incentive in incentives.iter_mut() {
+        let from = incentive.last_update.max(incentive.start);
+        let to = current_time.min(incentive.end);
+        if to <= from {
+            continue;
+        }
+        // the last stretch takes whatever rounding left over
+        let due = if to == incentive.end {
+            incentive.amount - incentive.distributed - incentive.unused
+        } else {
+            incentive
+                .amount
+                .multiply_ratio(to - from, incentive.end - incentive.start)
+        };
+        incentive.last_update = to;
+        if total_staked_amount.is_zero() {
+            incentive.unused += due;
+        } else {
+            add_to_pool(
+                &mut pools,
+                &incentive.token,
+                &incentive.code_hash,
+                due,
+                total_staked_amount,
+            );
+            incentive.distributed += due;
+        }
+    }
+    // nothing is left to stream or refund
+    incentives.retain(|x| x.last_update < x.end || !x.unused.is_zero());
+    Ok((pools, incentives))
+}
+
+/// Has to run before the pool's total staked amount changes.
+fn drip_incentives(
+    storage: &mut dyn Storage,
+    total_staked_amount: Uint128,
+    current_time: u64,
+) -> StdResult<Vec<DividendPool>> {
+    let (pools, incentives) = get_dripped_pools(storage, total_staked_amount, current_time)?;
+    if INCENTIVES_ITEM.may_load(storage)?.is_some() {
+        DIVIDEND_POOLS_ITEM.save(storage, &pools)?;
+        INCENTIVES_ITEM.save(storage, &incentives)?;
+    }
+    Ok(pools)
+}
+
+/// A staker's dividends brought up to date for `staked_amount`. A staker without a
+/// record for a pool has been staked since before its first dividend, as every
+/// stake change settles first.
+fn get_user_dividends(
+    storage: &dyn Storage,
+    pools: &[DividendPool],
+    staker_raw: &CanonicalAddr,
+    staked_amount: Uint128,
+) -> StdResult<Vec<UserDividend>> {
+    let mut user_dividends = USER_DIVIDENDS_STORE
+        .get(storage, staker_raw)
+        .unwrap_or_default();
+    for pool in pools.iter() {
+        let index = match user_dividends
+            .iter()
+            .position(|x| x.reward_token == pool.reward_token)
+        {
+            Some(index) => index,
+            None => {
+                user_dividends.push(UserDividend {
+                    reward_token: pool.reward_token.clone(),
+                    paid_per_share: Uint128::zero(),
+                    owed: Uint128::zero(),
+                });
+                user_dividends.len() - 1
+            }
+        };
+        let user_dividend = &mut user_dividends[index];
+        user_dividend.owed += staked_amount.multiply_ratio(
+            pool.acc_per_share - user_dividend.paid_per_share,
+            DIVIDEND_SCALE,
+        );
+        user_dividend.paid_per_share = pool.acc_per_share;
+    }
+    Ok(user_dividends)
+}
+
+/// Has to run before a staker's staked amount changes.
+fn settle_dividends(
+    storage: &mut dyn Storage,
+    staker_raw: &CanonicalAddr,
+    staked_amount: Uint128,
+    total_staked_amount: Uint128,
+    current_time: u64,
+) -> StdResult<()> {
+    let pools = drip_incentives(storage, total_staked_amount, current_time)?;
+    let user_dividends = get_user_dividends(storage, &pools, staker_raw, staked_amount)?;
+    if !user_dividends.is_empty() {
+        USER_DIVIDENDS_STORE.insert(storage, staker_raw, &user_dividends)?;
+    }
+    Ok(())
+}
+
+/// Settles and pays out everything a staker is owed in dividends. Only claims pay
+/// out, stake changes just settle so a sponsor token can't block a withdrawal.
+fn pay_dividends(
+    storage: &mut dyn Storage,
+    staker_raw: &CanonicalAddr,
+    staker: &Addr,
+    staked_amount: Uint128,
+    total_staked_amount: Uint128,
+    current_time: u64,
+) -> StdResult<Vec<CosmosMsg>> {
+    let pools = drip_incentives(storage, total_staked_amount, current_time)?;
+    let mut user_dividends = get_user_dividends(storage, &pools, staker_raw, staked_amount)?;
+    let history_store = user_history_store(staker_raw);
+    let mut response_msgs: Vec<CosmosMsg> = Vec::new();
+    for user_dividend in user_dividends.iter_mut() {
+        let pool = pools
+            .iter()
+            .find(|x| x.reward_token == user_dividend.reward_token);
+        if let (Some(pool), false) = (pool, user_dividend.owed.is_zero()) {
+            response_msgs.push(transfer_msg(
+                staker.to_string(),
+                user_dividend.owed,
+                None,
+                None,
+                BLOCK_SIZE,
+                pool.code_hash.to_string(),
+                pool.reward_token.to_string(),
+            )?);
+            let dividend_history = History {
+                amount: user_dividend.owed,
+                date: current_time,
+                action: HistoryAction::Dividend,
+                reward_token: Some(pool.reward_token.clone()),
+                token_ids: None,
+            };
+            history_store.push(storage, &dividend_history)?;
+            user_dividend.owed = Uint128::zero();
+        }
+    }
+    if !user_dividends.is_empty() {
+        USER_DIVIDENDS_STORE.insert(storage, staker_raw, &user_dividends)?;
+    }
+    Ok(response_msgs)
+}
+
+pub fn try_set_sponsor_list(
+    deps: DepsMut,
+    sender: &Addr,
+    list: SponsorList,
+) -> Result<Response, ContractError> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+    check_role(deps.as_ref(), &state, sender, Role::RewardManager)?;
+    SPONSOR_LIST_ITEM.save(deps.storage, &list)?;
+    Ok(Response::default())
+}
+
+/// Anyone who isn't denied can sponsor the pool by sending an allowed SNIP-20 with a
+/// schedule, which is then streamed to stakers between `start` and `end`. The token is
+/// paid out with the code hash it was allowed with.
+fn add_incentive(
+    storage: &mut dyn Storage,
+    token: &Addr,
+    sponsor: &Addr,
+    amount: Uint128,
+    start: u64,
+    end: u64,
+    current_time: u64,
+) -> Result<(), ContractError> {
+    let list = SPONSOR_LIST_ITEM.may_load(storage)?.unwrap_or_default();
+    if list.denied.contains(sponsor) {
+        return Err(ContractError::CustomError {
+            val: "This address can't sponsor the pool".to_string(),
+        });
+    }
+    let sponsor_token = list
+        .allowed
+        .into_iter()
+        .find(|x| x.address == *token)
+        .ok_or_else(|| ContractError::CustomError {
+            val: "This token can't sponsor the pool".to_string(),
+        })?;
+    if amount.is_zero() || end <= start {
+        return Err(ContractError::CustomError {
+            val: "An incentive needs an amount and has to end after it starts".to_string(),
+        });
+    }
+    // a past start would hand out the elapsed share all at once
+    if start < current_time {
+        return Err(ContractError::CustomError {
+            val: "An incentive can't start in the past".to_string(),
+        });
+    }
+
+    let mut incentives = INCENTIVES_ITEM.may_load(storage)?.unwrap_or_default();
+    if incentives.iter().filter(|x| x.end > current_time).count() >= MAX_ACTIVE_INCENTIVES {
+        return Err(ContractError::CustomError {
+            val: format!("Max of {} active incentives allowed", MAX_ACTIVE_INCENTIVES),
+        });
+    }
+    let id = INCENTIVE_ID_ITEM.may_load(storage)?.unwrap_or_default();
+    INCENTIVE_ID_ITEM.save(storage, &(id + 1))?;
+    incentives.push(Incentive {
+        id,
+        sponsor: sponsor.clone(),
+        token: token.clone(),
+        code_hash: sponsor_token.code_hash,
+        amount,
+        start,
+        end,
+        last_update: start,
+        distributed: Uint128::zero(),
+        unused: Uint128::zero(),
+    });
+    INCENTIVES_ITEM.save(storage, &incentives)?;
+    Ok(())
+}
+
+/// Returns the part of an ended incentive that streamed while nothing was staked.
+pub fn try_refund_incentive(
+    deps: DepsMut,
+    env: Env,
+    sender: &Addr,
+    id: u64,
+) -> Result<Response, ContractError> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+    let current_time = env.block.time.seconds();
+    drip_incentives(deps.storage, state.total_staked_amount, current_time)?;
+
+    let mut incentives = INCENTIVES_ITEM.may_load(deps.storage)?.unwrap_or_default();
+    let index = incentives
+        .iter()
+        .position(|x| x.id == id)
+        .ok_or_else(|| StdError::generic_err("Incentive not found or nothing left to refund"))?;
+    if incentives[index].sponsor != *sender {
+        return Err(ContractError::CustomError {
+            val: "You don't have the permissions to execute this command".to_string(),
+        });
+    }
+    if current_time < incentives[index].end {
+        return Err(ContractError::CustomError {
+            val: "The incentive hasn't ended yet".to_string(),
+        });
+    }
+
+    let incentive = incentives.remove(index);
+    INCENTIVES_ITEM.save(deps.storage, &incentives)?;
+    let cosmos_msg = transfer_msg(
+        sender.to_string(),
+        incentive.unused,
+        None,
+        None,
+        BLOCK_SIZE,
+        incentive.code_hash,
+        incentive.token.to_string(),
+    )?;
+    Ok(Response::new().add_message(cosmos_msg))
+}
+
+fn try_set_vesting(
+    deps: DepsMut,
+    env: Env,
+    sender: &Addr,
+    vesting: Option<VestingConfig>,
+) -> Result<Response, ContractError> {
+    if let Some(vesting) = &vesting {
+        if vesting.duration == 0 || vesting.cliff > vesting.duration {
+            return Err(ContractError::CustomError {
+                val: "Vesting needs a duration and a cliff no longer than it".to_string(),
+            });
+        }
+    }
+
+    try_queue_config_change(deps, env, sender, ConfigChange::SetVesting { vesting })
+}
+
+/// Pays out claimed rewards, or locks them in a new vesting schedule while vesting is on.
+fn reward_msg(
+    storage: &mut dyn Storage,
+    staker_raw: &CanonicalAddr,
+    staker: &Addr,
+    amount: Uint128,
+    reward_token: &Addr,
+    code_hash: &str,
+    current_time: u64,
+) -> StdResult<Option<CosmosMsg>> {
+    // while claims are paused the reward is held as an already vested schedule,
+    // which WithdrawVested pays out once claims resume
+    let vesting = match VESTING_ITEM.may_load(storage)? {
+        Some(vesting) => vesting,
+        None if is_paused(storage, PauseOperation::Claiming, current_time)? => VestingConfig {
+            duration: 0,
+            cliff: 0,
+        },
+        None => {
+            return Ok(Some(transfer_msg(
+                staker.to_string(),
+                amount,
+                None,
+                None,
+                BLOCK_SIZE,
+                code_hash.to_string(),
+                reward_token.to_string(),
+            )?));
+        }
+    };
+
+    let mut schedules = USER_VESTING_STORE
+        .get(storage, staker_raw)
+        .unwrap_or_default();
+    schedules.push(VestingSchedule {
+        reward_token: reward_token.clone(),
+        code_hash: code_hash.to_string(),
+        amount,
+        released: Uint128::zero(),
+        start: current_time,
+        cliff: current_time + vesting.cliff,
+        end: current_time + vesting.duration,
+    });
+    USER_VESTING_STORE.insert(storage, staker_raw, &schedules)?;
+    update_vesting_totals(storage, reward_token, amount, Uint128::zero())?;
+    Ok(None)
+}
+
+/// Moves `released` of a reward token from locked to released after adding
+/// `locked` newly claimed rewards.
+fn update_vesting_totals(
+    storage: &mut dyn Storage,
+    reward_token: &Addr,
+    locked: Uint128,
+    released: Uint128,
+) -> StdResult<()> {
+    let mut totals = VESTING_TOTALS_ITEM.may_load(storage)?.unwrap_or_default();
+    let index = match totals.iter().position(|x| x.reward_token == *reward_token) {
+        Some(index) => index,
+        None => {
+            totals.push(VestingTotal {
+                reward_token: reward_token.clone(),
+                locked: Uint128::zero(),
+                released: Uint128::zero(),
+            });
+            totals.len() - 1
+        }
+    };
+    totals[index].locked = totals[index].locked + locked - released;
+    totals[index].released += released;
+    VESTING_TOTALS_ITEM.save(storage, &totals)
+}
+
+/// Part of `schedule` vested by `current_time`, whether released yet or not.
+fn vested_amount(schedule: &VestingSchedule, current_time: u64) -> Uint128 {
+    if current_time < schedule.cliff {
+        return Uint128::zero();
+    }
+    if current_time >= schedule.end {
+        return schedule.amount;
+    }
+    schedule
+        .amount
+        .multiply_ratio(current_time - schedule.start, schedule.end - schedule.start)
+}
+
+/// Vested amounts of `schedules` that haven't been released yet, per reward token.
+fn get_withdrawable_vesting(schedules: &[VestingSchedule], current_time: u64) -> Vec<VestedReward> {
+    let mut withdrawable: Vec<VestedReward> = Vec::new();
+    for schedule in schedules.iter() {
+        let amount = vested_amount(schedule, current_time) - schedule.released;
+        if amount.is_zero() {
+            continue;
+        }
+        match withdrawable
+            .iter_mut()
+            .find(|x| x.reward_token == schedule.reward_token)
+        {
+            Some(vested) => vested.amount += amount,
+            None => withdrawable.push(VestedReward {
+                reward_token: schedule.reward_token.clone(),
+                amount,
+            }),
+        }
+    }
+    withdrawable
+}
+
+fn try_withdraw_vested(deps: DepsMut, env: Env, sender: &Addr) -> Result<Response, ContractError> {
+    let current_time = env.block.time.seconds();
+    check_not_paused(deps.storage, PauseOperation::Claiming, current_time)?;
+    let sender_raw = deps.api.addr_canonicalize(sender.as_str())?;
+    let mut schedules = USER_VESTING_STORE
+        .get(deps.storage, &sender_raw)
+        .unwrap_or_default();
+
+    let withdrawable = get_withdrawable_vesting(&schedules, current_time);
+    if withdrawable.is_empty() {
+        return Err(ContractError::CustomError {
+            val: "There are no vested rewards to withdraw".to_string(),
+        });
+    }
+
+    let history_store = user_history_store(&sender_raw);
+    let mut response_msgs: Vec<CosmosMsg> = Vec::new();
+    for vested in withdrawable.iter() {
+        let code_hash = schedules
+            .iter()
+            .find(|x| x.reward_token == vested.reward_token)
+            .map_or(String::new(), |x| x.code_hash.clone());
+        response_msgs.push(transfer_msg(
+            sender.to_string(),
+            vested.amount,
+            None,
+            None,
+            BLOCK_SIZE,
+            code_hash,
+            vested.reward_token.to_string(),
+        )?);
+        let vested_history = History {
+            amount: vested.amount,
+            date: current_time,
+            action: HistoryAction::VestedRelease,
+            reward_token: Some(vested.reward_token.clone()),
+            token_ids: None,
+        };
+        history_store.push(deps.storage, &vested_history)?;
+        update_vesting_totals(
+            deps.storage,
+            &vested.reward_token,
+            Uint128::zero(),
+            vested.amount,
+        )?;
+    }
+
+    for schedule in schedules.iter_mut() {
+        schedule.released = vested_amount(schedule, current_time);
+    }
+    schedules.retain(|x| x.released < x.amount);
+    if schedules.is_empty() {
+        USER_VESTING_STORE.remove(deps.storage, &sender_raw)?;
+    } else {
+        USER_VESTING_STORE.insert(deps.storage, &sender_raw, &schedules)?;
+    }
+
+    Ok(Response::new().add_messages(response_msgs))
+}
+
+/// Records a staker's new amount and trait weights, and the pool totals, after a
+/// stake or withdraw.
+fn checkpoint_stake(
+    storage: &mut dyn Storage,
+    staker_raw: &CanonicalAddr,
+    staked_amount: Uint128,
+    user_weights: &[UserStakingWeight],
+    state: &State,
+    time: u64,
+) -> StdResult<()> {
+    write_checkpoint(
+        storage,
+        &user_checkpoint_store(staker_raw),
+        time,
+        staked_amount,
+    )?;
+    write_checkpoint(
+        storage,
+        &TOTAL_CHECKPOINTS_STORE,
+        time,
+        state.total_staked_amount,
+    )?;
+    for weight in state.staking_weights.iter().flatten() {
+        write_checkpoint(
+            storage,
+            &user_weight_checkpoint_store(staker_raw, &weight.weight_trait_type),
+            time,
+            user_weight_amount(user_weights, &weight.weight_trait_type),
+        )?;
+        write_checkpoint(
+            storage,
+            &total_weight_checkpoint_store(&weight.weight_trait_type),
+            time,
+            weight.amount,
+        )?;
     }
-    return Ok(expected_rewards);
+    Ok(())
+}
+
+fn user_weight_amount(user_weights: &[UserStakingWeight], weight_trait_type: &str) -> Uint128 {
+    user_weights
+        .iter()
+        .find(|x| x.weight_trait_type == weight_trait_type)
+        .map_or(Uint128::zero(), |x| x.amount)
+}
+
+/// Only the last amount within a block is kept.
+fn write_checkpoint(
+    storage: &mut dyn Storage,
+    store: &AppendStore<Checkpoint>,
+    time: u64,
+    amount: Uint128,
+) -> StdResult<()> {
+    let checkpoint = Checkpoint { time, amount };
+    let len = store.get_len(storage)?;
+    if len > 0 && store.get_at(storage, len - 1)?.time == time {
+        return store.set_at(storage, len - 1, &checkpoint);
+    }
+    store.push(storage, &checkpoint)
+}
+
+/// Binary searches for the last checkpoint at or before `time`.
+fn checkpoint_at(
+    storage: &dyn Storage,
+    store: &AppendStore<Checkpoint>,
+    time: u64,
+) -> StdResult<Uint128> {
+    let mut low = 0u32;
+    let mut high = store.get_len(storage)?;
+    while low < high {
+        let mid = (low + high) / 2;
+        if store.get_at(storage, mid)?.time <= time {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    if low == 0 {
+        return Ok(Uint128::zero());
+    }
+    Ok(store.get_at(storage, low - 1)?.amount)
 }
 
 #[entry_point]
 pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
     match msg {
         QueryMsg::GetStakedInfo {} => to_binary(&query_staked(deps)?),
-        QueryMsg::GetMyStakedInfo { permit } => to_binary(&query_my_staked(deps, _env, permit)?),
-        QueryMsg::GetNumUserHistory { permit } => {
-            to_binary(&query_num_user_history(deps, _env, permit)?)
+        QueryMsg::GetMyStakedInfo { permit, viewer } => {
+            let user_raw = get_querier(deps, permit, viewer, _env.contract.address.clone())?;
+            to_binary(&query_my_staked(deps, _env, user_raw)?)
+        }
+        QueryMsg::GetNumUserHistory { permit, viewer } => {
+            let user_raw = get_querier(deps, permit, viewer, _env.contract.address)?;
+            to_binary(&query_num_user_history(deps, user_raw)?)
+        }
+        QueryMsg::GetMyStakedNfts {
+            permit,
+            viewer,
+            start_page,
+            page_size,
+            include_info,
+        } => {
+            let user_raw = get_querier(deps, permit, viewer, _env.contract.address)?;
+            to_binary(&query_my_staked_nfts(
+                deps,
+                user_raw,
+                start_page,
+                page_size,
+                include_info.unwrap_or(false),
+            )?)
         }
         QueryMsg::GetUserHistory {
             permit,
+            viewer,
             start_page,
             page_size,
-        } => to_binary(&query_user_history(
-            deps, _env, permit, start_page, page_size,
-        )?),
+            filter,
+        } => {
+            let user_raw = get_querier(deps, permit, viewer, _env.contract.address)?;
+            to_binary(&query_user_history(
+                deps, user_raw, start_page, page_size, filter,
+            )?)
+        }
         QueryMsg::GetRewardBalance { viewer } => {
             to_binary(&query_reward_balance(deps, _env, viewer)?)
         }
         QueryMsg::GetStakedBalance { viewer } => {
             to_binary(&query_staked_balance(deps, _env, viewer)?)
         }
+        QueryMsg::GetPauseState {} => to_binary(&query_pause_state(deps)?),
+        QueryMsg::GetAdmins {} => to_binary(&query_admins(deps)?),
+        QueryMsg::GetPendingConfigChanges {} => to_binary(&query_pending_config_changes(deps)?),
+        QueryMsg::GetAdminLog {
+            viewer,
+            start_page,
+            page_size,
+        } => to_binary(&query_admin_log(deps, viewer, start_page, page_size)?),
+        QueryMsg::GetStakerInfo { viewer, staker } => {
+            to_binary(&query_staker_info(deps, _env, viewer, staker)?)
+        }
+        QueryMsg::GetStakerHistory {
+            viewer,
+            staker,
+            page,
+            page_size,
+        } => to_binary(&query_staker_history(
+            deps, viewer, staker, page, page_size,
+        )?),
+        QueryMsg::ListStakers {
+            viewer,
+            start_after,
+            limit,
+        } => to_binary(&query_list_stakers(deps, _env, viewer, start_after, limit)?),
+        QueryMsg::GetStakedAt {
+            permit,
+            viewer,
+            address,
+            time,
+        } => {
+            let staker_raw =
+                get_authorized_staker(deps, permit, viewer, address, _env.contract.address)?;
+            to_binary(&checkpoint_at(
+                deps.storage,
+                &user_checkpoint_store(&staker_raw),
+                time,
+            )?)
+        }
+        QueryMsg::GetVotingPower {
+            permit,
+            viewer,
+            address,
+            time,
+            weight_trait_type,
+        } => {
+            let staker_raw = get_authorized_staker(
+                deps,
+                permit,
+                viewer,
+                address,
+                _env.contract.address.clone(),
+            )?;
+            to_binary(&query_voting_power(
+                deps,
+                _env,
+                staker_raw,
+                time,
+                weight_trait_type,
+            )?)
+        }
+        QueryMsg::GetMyDividends { permit, viewer } => {
+            let user_raw = get_querier(deps, permit, viewer, _env.contract.address.clone())?;
+            to_binary(&query_my_dividends(deps, _env, user_raw)?)
+        }
+        QueryMsg::GetMyOperator { permit, viewer } => {
+            let user_raw = get_querier(deps, permit, viewer, _env.contract.address.clone())?;
+            to_binary(&OPERATORS_STORE.get(deps.storage, &user_raw))
+        }
+        QueryMsg::GetMyVesting { permit, viewer } => {
+            let user_raw = get_querier(deps, permit, viewer, _env.contract.address.clone())?;
+            let schedules = USER_VESTING_STORE
+                .get(deps.storage, &user_raw)
+                .unwrap_or_default();
+            to_binary(&MyVestingResponse {
+                withdrawable: get_withdrawable_vesting(&schedules, _env.block.time.seconds()),
+                schedules,
+            })
+        }
+        QueryMsg::GetVestingInfo {} => to_binary(&VestingInfoResponse {
+            vesting: VESTING_ITEM.may_load(deps.storage)?,
+            totals: VESTING_TOTALS_ITEM
+                .may_load(deps.storage)?
+                .unwrap_or_default(),
+        }),
+        QueryMsg::GetIncentives {} => to_binary(&query_incentives(deps, _env)?),
+        QueryMsg::GetBeneficiaryAllowList {} => {
+            to_binary(&BENEFICIARY_ALLOW_LIST_ITEM.may_load(deps.storage)?)
+        }
+        QueryMsg::GetSponsorList {} => to_binary(
+            &SPONSOR_LIST_ITEM
+                .may_load(deps.storage)?
+                .unwrap_or_default(),
+        ),
+        QueryMsg::GetRaffles {
+            viewer,
+            start_page,
+            page_size,
+        } => to_binary(&query_raffles(deps, viewer, start_page, page_size)?),
+        QueryMsg::GetTotalStakedAt { time } => to_binary(&checkpoint_at(
+            deps.storage,
+            &TOTAL_CHECKPOINTS_STORE,
+            time,
+        )?),
+        QueryMsg::GetStrayNfts { viewer } => to_binary(&query_stray_nfts(deps, _env, viewer)?),
     }
 }
 
@@ -1097,11 +3489,50 @@ fn query_staked(deps: Deps) -> StdResult<StakedInfoResponse> {
         trait_restriction: state.trait_restriction,
         staking_weights: state.staking_weights,
         is_active: Some(state.is_active),
+        pause_flags: Some(PAUSE_ITEM.may_load(deps.storage)?.unwrap_or_default()),
+    })
+}
+
+fn query_pause_state(deps: Deps) -> StdResult<PauseFlags> {
+    Ok(PAUSE_ITEM.may_load(deps.storage)?.unwrap_or_default())
+}
+
+fn query_admins(deps: Deps) -> StdResult<AdminsResponse> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+    let mut roles: Vec<RoleAssignment> = Vec::new();
+    for entry in ROLES_STORE.iter(deps.storage)? {
+        let (address_raw, address_roles) = entry?;
+        roles.push(RoleAssignment {
+            address: deps.api.addr_humanize(&address_raw)?,
+            roles: address_roles,
+        });
+    }
+
+    Ok(AdminsResponse {
+        owner: state.owner,
+        pending_owner: PENDING_OWNER_ITEM.may_load(deps.storage)?,
+        roles,
+    })
+}
+
+fn query_pending_config_changes(deps: Deps) -> StdResult<PendingConfigChangesResponse> {
+    let mut changes: Vec<PendingConfigChange> = Vec::new();
+    for entry in CONFIG_CHANGES_STORE.iter(deps.storage)? {
+        let (_, pending_change) = entry?;
+        changes.push(pending_change);
+    }
+
+    Ok(PendingConfigChangesResponse {
+        timelock_delay: get_timelock_delay(deps.storage)?,
+        changes,
     })
 }
 
-fn query_my_staked(deps: Deps, env: Env, permit: Permit) -> StdResult<MyStakedInfoResponse> {
-    let user_raw = get_querier(deps, permit, env.contract.address)?;
+fn query_my_staked(
+    deps: Deps,
+    env: Env,
+    user_raw: CanonicalAddr,
+) -> StdResult<MyStakedInfoResponse> {
     let state = CONFIG_ITEM.load(deps.storage)?;
 
     let staked = STAKED_STORE.get(deps.storage, &user_raw).unwrap_or(Staked {
@@ -1119,23 +3550,98 @@ fn query_my_staked(deps: Deps, env: Env, permit: Permit) -> StdResult<MyStakedIn
     })
 }
 
-fn query_num_user_history(deps: Deps, env: Env, permit: Permit) -> StdResult<u32> {
-    let user_raw = get_querier(deps, permit, env.contract.address)?;
-    let history_store = HISTORY_STORE.add_suffix(&user_raw);
+fn query_my_staked_nfts(
+    deps: Deps,
+    user_raw: CanonicalAddr,
+    start_page: u32,
+    page_size: u32,
+    include_info: bool,
+) -> StdResult<StakedNftsResponse> {
+    let staked_nfts = STAKED_NFTS_STORE
+        .get(deps.storage, &user_raw)
+        .unwrap_or_default();
+
+    let nfts = staked_nfts
+        .iter()
+        .skip((start_page as usize).saturating_mul(page_size as usize))
+        .take(page_size as usize)
+        .map(|token_id| StakedNft {
+            token_id: token_id.to_string(),
+            // NFTs staked before this was tracked have no info
+            info: if include_info {
+                STAKED_NFT_INFO_STORE.get(deps.storage, token_id)
+            } else {
+                None
+            },
+        })
+        .collect();
+
+    Ok(StakedNftsResponse {
+        nfts,
+        total: staked_nfts.len() as u32,
+    })
+}
+
+fn query_num_user_history(deps: Deps, user_raw: CanonicalAddr) -> StdResult<u32> {
+    let history_store = user_history_store(&user_raw);
     let num = history_store.get_len(deps.storage)?;
     Ok(num)
 }
 
+/// `AppendStore::paging` works out the page bounds in u32, so pages past them are
+/// returned empty instead of overflowing.
+fn page_store<T: Serialize + DeserializeOwned>(
+    storage: &dyn Storage,
+    store: &AppendStore<T>,
+    start_page: u32,
+    page_size: u32,
+) -> StdResult<Vec<T>> {
+    match start_page
+        .checked_mul(page_size)
+        .and_then(|x| x.checked_add(page_size))
+    {
+        Some(_) => store.paging(storage, start_page, page_size),
+        None => Ok(Vec::new()),
+    }
+}
+
 fn query_user_history(
     deps: Deps,
-    env: Env,
-    permit: Permit,
+    user_raw: CanonicalAddr,
     start_page: u32,
     page_size: u32,
+    filter: Option<HistoryFilter>,
 ) -> StdResult<Vec<History>> {
-    let user_raw = get_querier(deps, permit, env.contract.address)?;
-    let history_store = HISTORY_STORE.add_suffix(&user_raw);
-    let history = history_store.paging(deps.storage, start_page, page_size)?;
+    let history_store = user_history_store(&user_raw);
+    let filter = match filter {
+        Some(filter) => filter,
+        None => return page_store(deps.storage, &history_store, start_page, page_size),
+    };
+
+    let is_match = |history: &History| {
+        !matches!(&filter.action, Some(x) if *x != history.action)
+            && !matches!(filter.start_time, Some(x) if history.date < x)
+            && !matches!(filter.end_time, Some(x) if history.date > x)
+    };
+    let len = history_store.get_len(deps.storage)?;
+    let newest_first = filter.newest_first.unwrap_or(false);
+    let mut to_skip = (start_page as usize).saturating_mul(page_size as usize);
+    let mut history: Vec<History> = Vec::new();
+    for i in 0..len {
+        if history.len() >= page_size as usize {
+            break;
+        }
+        let pos = if newest_first { len - 1 - i } else { i };
+        let entry = history_store.get_at(deps.storage, pos)?;
+        if !is_match(&entry) {
+            continue;
+        }
+        if to_skip > 0 {
+            to_skip -= 1;
+            continue;
+        }
+        history.push(entry);
+    }
     Ok(history)
 }
 
@@ -1172,12 +3678,118 @@ fn query_staked_balance(deps: Deps, env: Env, viewer: ViewerInfo) -> StdResult<B
     Ok(balance.unwrap())
 }
 
-fn check_admin_key(deps: Deps, viewer: ViewerInfo) -> StdResult<()> {
-    let admin_viewing_key = ADMIN_VIEWING_KEY_ITEM.load(deps.storage)?;
-    let prng_seed: Vec<u8> = sha_256(base64::encode(viewer.viewing_key).as_bytes()).to_vec();
-    let vk = base64::encode(&prng_seed);
+fn query_stray_nfts(deps: Deps, env: Env, viewer: ViewerInfo) -> StdResult<Vec<String>> {
+    check_admin_key(deps, viewer)?;
+    let state = CONFIG_ITEM.load(deps.storage)?;
+    get_stray_nfts(deps, &env, &state)
+}
+
+fn query_admin_log(
+    deps: Deps,
+    viewer: ViewerInfo,
+    start_page: u32,
+    page_size: u32,
+) -> StdResult<AdminLogResponse> {
+    check_admin_key(deps, viewer)?;
+    let entries = page_store(deps.storage, &ADMIN_LOG_STORE, start_page, page_size)?;
+    let total = ADMIN_LOG_STORE.get_len(deps.storage)?;
+    Ok(AdminLogResponse { entries, total })
+}
+
+fn query_staker_info(
+    deps: Deps,
+    env: Env,
+    viewer: ViewerInfo,
+    staker: String,
+) -> StdResult<StakerInfoResponse> {
+    check_admin_key(deps, viewer)?;
+    let staker_raw = deps.api.addr_canonicalize(&staker)?;
+    let staked_nfts = STAKED_NFTS_STORE
+        .get(deps.storage, &staker_raw)
+        .unwrap_or_default();
+    let staked_info = query_my_staked(deps, env, staker_raw)?;
+    Ok(StakerInfoResponse {
+        staked: staked_info.staked,
+        estimated_rewards: staked_info.estimated_rewards,
+        staked_nfts,
+    })
+}
+
+fn query_staker_history(
+    deps: Deps,
+    viewer: ViewerInfo,
+    staker: String,
+    page: u32,
+    page_size: u32,
+) -> StdResult<Vec<History>> {
+    check_admin_key(deps, viewer)?;
+    let staker_raw = deps.api.addr_canonicalize(&staker)?;
+    query_user_history(deps, staker_raw, page, page_size, None)
+}
+
+/// Walks `STAKED_STORE` in index order. Staked entries are zeroed rather than removed,
+/// so an index stays a stable cursor between pages.
+fn query_list_stakers(
+    deps: Deps,
+    env: Env,
+    viewer: ViewerInfo,
+    start_after: Option<u32>,
+    limit: Option<u32>,
+) -> StdResult<StakersResponse> {
+    check_admin_key(deps, viewer)?;
+    let state = CONFIG_ITEM.load(deps.storage)?;
+    let current_time = env.block.time.seconds();
+    let limit = limit.unwrap_or(DEFAULT_RETURN_LIMIT).min(MAX_RETURN_LIMIT);
+    // nothing comes after the last u32 index
+    let start = match start_after.map_or(Some(0), |i| i.checked_add(1)) {
+        Some(start) => start,
+        None => {
+            return Ok(StakersResponse {
+                stakers: Vec::new(),
+                next: None,
+            })
+        }
+    };
+    let total = STAKED_STORE.get_len(deps.storage)?;
+
+    let mut stakers: Vec<StakerEntry> = Vec::new();
+    let mut last_index: Option<u32> = None;
+    for (index, item) in STAKED_STORE
+        .iter(deps.storage)?
+        .enumerate()
+        .skip(start as usize)
+    {
+        if stakers.len() as u32 >= limit {
+            break;
+        }
+        let (staker_raw, staked) = item?;
+        last_index = Some(index as u32);
+        if staked.staked_amount.is_zero() {
+            continue;
+        }
+        stakers.push(StakerEntry {
+            address: deps.api.addr_humanize(&staker_raw)?,
+            estimated_rewards: get_estimated_rewards(&staked, &current_time, &state)?,
+            staked,
+        });
+    }
+
+    Ok(StakersResponse {
+        stakers,
+        next: last_index.filter(|i| i + 1 < total),
+    })
+}
 
-    if vk != admin_viewing_key.viewing_key || viewer.address != admin_viewing_key.address {
+fn check_admin_key(deps: Deps, viewer: ViewerInfo) -> StdResult<()> {
+    let admin_keys = ADMIN_VIEWING_KEYS_ITEM
+        .may_load(deps.storage)?
+        .unwrap_or_default();
+    let vk = hash_viewing_key(&viewer.viewing_key);
+
+    if !admin_keys
+        .iter()
+        .any(|x| x.viewing_key == vk && x.address == viewer.address)
+    {
         return Err(StdError::generic_err(
             "Wrong viewing key for this address or viewing key not set",
         ));
@@ -1186,8 +3798,139 @@ fn check_admin_key(deps: Deps, viewer: ViewerInfo) -> StdResult<()> {
     return Ok(());
 }
 
-fn get_querier(deps: Deps, permit: Permit, contract_address: Addr) -> StdResult<CanonicalAddr> {
-    if let pmt = permit {
+/// Voting power is the staked NFT count, or a trait's staking weight when
+/// `weight_trait_type` is given, as of `time` (defaults to now).
+fn query_voting_power(
+    deps: Deps,
+    env: Env,
+    staker_raw: CanonicalAddr,
+    time: Option<u64>,
+    weight_trait_type: Option<String>,
+) -> StdResult<VotingPowerResponse> {
+    let time = get_voting_time(&env, time)?;
+    let (power, total_power) = match weight_trait_type {
+        Some(weight_trait_type) => {
+            let state = CONFIG_ITEM.load(deps.storage)?;
+            if !state
+                .staking_weights
+                .unwrap_or_default()
+                .iter()
+                .any(|x| x.weight_trait_type == weight_trait_type)
+            {
+                return Err(StdError::generic_err("Unknown weight trait type"));
+            }
+            let user_store = user_weight_checkpoint_store(&staker_raw, &weight_trait_type);
+            let total_store = total_weight_checkpoint_store(&weight_trait_type);
+            (
+                checkpoint_at(deps.storage, &user_store, time)?,
+                checkpoint_at(deps.storage, &total_store, time)?,
+            )
+        }
+        None => (
+            checkpoint_at(deps.storage, &user_checkpoint_store(&staker_raw), time)?,
+            checkpoint_at(deps.storage, &TOTAL_CHECKPOINTS_STORE, time)?,
+        ),
+    };
+    Ok(VotingPowerResponse {
+        power,
+        total_power,
+        time,
+    })
+}
+
+/// Balances after the current block aren't known yet, so only past and present
+/// times are answered.
+fn get_voting_time(env: &Env, time: Option<u64>) -> StdResult<u64> {
+    let current_time = env.block.time.seconds();
+    let time = time.unwrap_or(current_time);
+    if time > current_time {
+        return Err(StdError::generic_err(
+            "Voting power is only known up to the current block time",
+        ));
+    }
+    Ok(time)
+}
+
+fn query_my_dividends(
+    deps: Deps,
+    env: Env,
+    user_raw: CanonicalAddr,
+) -> StdResult<Vec<PendingDividend>> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+    let staked_amount = STAKED_STORE
+        .get(deps.storage, &user_raw)
+        .map_or(Uint128::zero(), |x| x.staked_amount);
+    let (pools, _) = get_dripped_pools(
+        deps.storage,
+        state.total_staked_amount,
+        env.block.time.seconds(),
+    )?;
+    let user_dividends = get_user_dividends(deps.storage, &pools, &user_raw, staked_amount)?;
+    Ok(user_dividends
+        .into_iter()
+        .map(|x| PendingDividend {
+            reward_token: x.reward_token,
+            amount: x.owed,
+        })
+        .collect())
+}
+
+fn query_incentives(deps: Deps, env: Env) -> StdResult<Vec<Incentive>> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+    let (_, incentives) = get_dripped_pools(
+        deps.storage,
+        state.total_staked_amount,
+        env.block.time.seconds(),
+    )?;
+    Ok(incentives)
+}
+
+fn query_raffles(
+    deps: Deps,
+    viewer: ViewerInfo,
+    start_page: u32,
+    page_size: u32,
+) -> StdResult<RafflesResponse> {
+    check_admin_key(deps, viewer)?;
+    let raffles = page_store(deps.storage, &RAFFLES_STORE, start_page, page_size)?;
+    let total = RAFFLES_STORE.get_len(deps.storage)?;
+    Ok(RafflesResponse { raffles, total })
+}
+
+/// A staker can read their own data with a permit or viewing key. A `Governance`
+/// role holder can read anyone's with its own, and so can an admin key.
+fn get_authorized_staker(
+    deps: Deps,
+    permit: Option<Permit>,
+    viewer: Option<ViewerInfo>,
+    address: String,
+    contract_address: Addr,
+) -> StdResult<CanonicalAddr> {
+    let staker_raw = deps.api.addr_canonicalize(&address)?;
+    if let Some(admin) = viewer.clone() {
+        if check_admin_key(deps, admin).is_ok() {
+            return Ok(staker_raw);
+        }
+    }
+    let querier_raw = get_querier(deps, permit, viewer, contract_address)?;
+    if querier_raw != staker_raw {
+        let state = CONFIG_ITEM.load(deps.storage)?;
+        let querier = deps.api.addr_humanize(&querier_raw)?;
+        if check_role(deps, &state, &querier, Role::Governance).is_err() {
+            return Err(StdError::generic_err("Not authorized to view this address"));
+        }
+    }
+    Ok(staker_raw)
+}
+
+/// Authenticates a user query with either a permit or an address and viewing key.
+fn get_querier(
+    deps: Deps,
+    permit: Option<Permit>,
+    viewer: Option<ViewerInfo>,
+    contract_address: Addr,
+) -> StdResult<CanonicalAddr> {
+    if let Some(pmt) = permit {
         let querier = deps.api.addr_canonicalize(&validate(
             deps,
             PREFIX_REVOKED_PERMITS,
@@ -1203,16 +3946,27 @@ fn get_querier(deps: Deps, permit: Permit, contract_address: Addr) -> StdResult<
         }
         return Ok(querier);
     }
+    if let Some(viewer) = viewer {
+        let querier = deps.api.addr_canonicalize(&viewer.address)?;
+        let viewing_key = USER_VIEWING_KEYS_STORE.get(deps.storage, &querier);
+        if viewing_key != Some(hash_viewing_key(&viewer.viewing_key)) {
+            return Err(StdError::generic_err(
+                "Wrong viewing key for this address or viewing key not set",
+            ));
+        }
+        return Ok(querier);
+    }
     return Err(StdError::generic_err("Unauthorized"));
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::msg::ContractInfo;
+    use crate::msg::{ContractInfo, StakingWeight};
     use cosmwasm_std::testing::*;
     use cosmwasm_std::Api;
     use cosmwasm_std::OwnedDeps;
+    use cosmwasm_std::{ContractResult, SystemResult};
     use std::any::Any;
     #[test]
     fn rewards_calc() {
@@ -1342,6 +4096,457 @@ mod tests {
             },
         }
     }
+    #[test]
+    fn config_changes_wait_for_timelock() {
+        let (init_result, mut deps) = init_helper_with_config();
+        assert!(init_result.is_ok());
+        let owner = Addr::unchecked("instantiator");
+        let mut env = mock_env();
+
+        let change = ConfigChange::SetTimelockDelay { delay: 3600 };
+        try_queue_config_change(deps.as_mut(), env.clone(), &owner, change).unwrap();
+        let pending = query_pending_config_changes(deps.as_ref()).unwrap();
+        assert_eq!(pending.timelock_delay, DEFAULT_TIMELOCK_DELAY);
+        assert_eq!(pending.changes.len(), 1);
+
+        let early = try_execute_config_change(deps.as_mut(), env.clone(), &owner, 0);
+        assert!(early.is_err());
+
+        env.block.time = env.block.time.plus_seconds(DEFAULT_TIMELOCK_DELAY);
+        try_execute_config_change(deps.as_mut(), env, &owner, 0).unwrap();
+        let pending = query_pending_config_changes(deps.as_ref()).unwrap();
+        assert_eq!(pending.timelock_delay, 3600);
+        assert!(pending.changes.is_empty());
+    }
+
+    #[test]
+    fn only_owner_removes_rewards() {
+        let (init_result, mut deps) = init_helper_with_config();
+        assert!(init_result.is_ok());
+        let owner = Addr::unchecked("instantiator");
+        let reward_manager = Addr::unchecked("reward_manager");
+        try_set_role(
+            deps.as_mut(),
+            &owner,
+            reward_manager.clone(),
+            Role::RewardManager,
+            true,
+        )
+        .unwrap();
+
+        assert!(try_remove_rewards(deps.as_mut(), &reward_manager).is_err());
+        let state = CONFIG_ITEM.load(&deps.storage).unwrap();
+        assert!(!state.reward_contracts[0].total_rewards.is_zero());
+
+        let response = try_remove_rewards(deps.as_mut(), &owner).unwrap();
+        assert_eq!(response.messages.len(), 1);
+        let state = CONFIG_ITEM.load(&deps.storage).unwrap();
+        assert!(state.reward_contracts[0].total_rewards.is_zero());
+    }
+
+    #[test]
+    fn created_viewing_key_authenticates_queries() {
+        let (init_result, mut deps) = init_helper_with_config();
+        assert!(init_result.is_ok());
+        let env = mock_env();
+        let user = Addr::unchecked("alice");
+
+        let response =
+            try_create_viewing_key(deps.as_mut(), env.clone(), &user, "seed".to_string()).unwrap();
+        let key = from_binary::<ViewingKeyResponse>(&response.data.unwrap())
+            .unwrap()
+            .key;
+        assert!(key.starts_with(VIEWING_KEY_PREFIX));
+
+        let viewer = ViewerInfo {
+            address: user.to_string(),
+            viewing_key: key,
+        };
+        let querier = get_querier(
+            deps.as_ref(),
+            None,
+            Some(viewer),
+            env.contract.address.clone(),
+        );
+        assert!(querier.is_ok());
+
+        let wrong_viewer = ViewerInfo {
+            address: user.to_string(),
+            viewing_key: "api_key_wrong".to_string(),
+        };
+        let querier = get_querier(
+            deps.as_ref(),
+            None,
+            Some(wrong_viewer),
+            env.contract.address,
+        );
+        assert!(querier.is_err());
+    }
+
+    #[test]
+    fn governance_reads_weighted_voting_power() {
+        let (init_result, mut deps) = init_helper_with_config();
+        assert!(init_result.is_ok());
+        let env = mock_env();
+        let owner = Addr::unchecked("instantiator");
+        let governance = Addr::unchecked("governance");
+        let staker_raw = deps.api.addr_canonicalize("alice").unwrap();
+        let state = CONFIG_ITEM.load(&deps.storage).unwrap();
+        let user_weights = vec![UserStakingWeight {
+            amount: Uint128::from(10u128),
+            weight_trait_type: "Pack".to_string(),
+        }];
+        checkpoint_stake(
+            &mut deps.storage,
+            &staker_raw,
+            Uint128::from(2u128),
+            &user_weights,
+            &state,
+            env.block.time.seconds(),
+        )
+        .unwrap();
+
+        let response =
+            try_create_viewing_key(deps.as_mut(), env.clone(), &governance, "seed".to_string())
+                .unwrap();
+        let viewer = ViewerInfo {
+            address: governance.to_string(),
+            viewing_key: from_binary::<ViewingKeyResponse>(&response.data.unwrap())
+                .unwrap()
+                .key,
+        };
+        let denied = get_authorized_staker(
+            deps.as_ref(),
+            None,
+            Some(viewer.clone()),
+            "alice".to_string(),
+            env.contract.address.clone(),
+        );
+        assert!(denied.is_err());
+
+        try_set_role(deps.as_mut(), &owner, governance, Role::Governance, true).unwrap();
+        let staker_raw = get_authorized_staker(
+            deps.as_ref(),
+            None,
+            Some(viewer),
+            "alice".to_string(),
+            env.contract.address.clone(),
+        )
+        .unwrap();
+
+        let power =
+            query_voting_power(deps.as_ref(), env.clone(), staker_raw.clone(), None, None).unwrap();
+        assert_eq!(power.power, Uint128::from(2u128));
+        let weighted = query_voting_power(
+            deps.as_ref(),
+            env.clone(),
+            staker_raw.clone(),
+            None,
+            Some("Pack".to_string()),
+        )
+        .unwrap();
+        assert_eq!(weighted.power, Uint128::from(10u128));
+        assert_eq!(weighted.total_power, Uint128::from(33u128));
+        let unknown = query_voting_power(
+            deps.as_ref(),
+            env,
+            staker_raw,
+            None,
+            Some("Fur".to_string()),
+        );
+        assert!(unknown.is_err());
+    }
+
+    #[test]
+    fn recover_only_returns_untracked_nfts() {
+        let (init_result, mut deps) = init_helper_with_config();
+        assert!(init_result.is_ok());
+        let env = mock_env();
+        let owner = Addr::unchecked("instantiator");
+        let staker_raw = deps.api.addr_canonicalize("alice").unwrap();
+        STAKED_NFTS_STORE
+            .insert(&mut deps.storage, &staker_raw, &vec!["1".to_string()])
+            .unwrap();
+        deps.querier.update_wasm(|_| {
+            SystemResult::Ok(ContractResult::Ok(Binary::from(
+                br#"{"token_list":{"tokens":["1","2"]}}"#.to_vec(),
+            )))
+        });
+
+        let return_to = |recipient: &str| RecoverAction::Return {
+            recipient: Addr::unchecked(recipient),
+        };
+        let not_owner = try_recover_nfts(
+            deps.as_mut(),
+            env.clone(),
+            &Addr::unchecked("alice"),
+            vec!["2".to_string()],
+            return_to("bob"),
+        );
+        assert!(not_owner.is_err());
+        let tracked = try_recover_nfts(
+            deps.as_mut(),
+            env.clone(),
+            &owner,
+            vec!["1".to_string()],
+            return_to("bob"),
+        );
+        assert!(tracked.is_err());
+        let invalid_recipient = try_recover_nfts(
+            deps.as_mut(),
+            env.clone(),
+            &owner,
+            vec!["2".to_string()],
+            return_to(""),
+        );
+        assert!(invalid_recipient.is_err());
+
+        let response = try_recover_nfts(
+            deps.as_mut(),
+            env,
+            &owner,
+            vec!["2".to_string()],
+            return_to("bob"),
+        )
+        .unwrap();
+        assert_eq!(response.messages.len(), 1);
+    }
+
+    #[test]
+    fn eject_settles_or_forfeits_and_removes_weights() {
+        let (init_result, mut deps) = init_helper_with_config();
+        assert!(init_result.is_ok());
+        let env = mock_env();
+        let owner = Addr::unchecked("instantiator");
+        let now = env.block.time.seconds();
+        let initial = CONFIG_ITEM.load(&deps.storage).unwrap();
+        seed_stake(&mut deps, "alice", &["1", "2"], 10, now - 86400);
+        seed_stake(&mut deps, "bob", &["3"], 5, now - 86400);
+
+        let response = try_eject(
+            deps.as_mut(),
+            env.clone(),
+            &owner,
+            vec![Addr::unchecked("alice")],
+            Some("cheating".to_string()),
+        )
+        .unwrap();
+        assert_eq!(response.messages.len(), 1);
+        let alice_raw = deps.api.addr_canonicalize("alice").unwrap();
+        let history = user_history_store(&alice_raw);
+        let actions: Vec<HistoryAction> = history
+            .iter(&deps.storage)
+            .unwrap()
+            .map(|x| x.unwrap().action)
+            .collect();
+        assert_eq!(actions, vec![HistoryAction::Penalty, HistoryAction::Eject]);
+
+        let response = try_eject(
+            deps.as_mut(),
+            env,
+            &owner,
+            vec![Addr::unchecked("bob")],
+            None,
+        )
+        .unwrap();
+        assert_eq!(response.messages.len(), 2);
+
+        let state = CONFIG_ITEM.load(&deps.storage).unwrap();
+        assert_eq!(state.total_staked_amount, initial.total_staked_amount);
+        assert_eq!(state.staking_weights, initial.staking_weights);
+        assert!(STAKED_NFT_INFO_STORE
+            .get(&deps.storage, &"1".to_string())
+            .is_none());
+    }
+
+    #[test]
+    fn transfer_position_moves_nfts_and_weights() {
+        let (init_result, mut deps) = init_helper_with_config();
+        assert!(init_result.is_ok());
+        let env = mock_env();
+        let now = env.block.time.seconds();
+        let alice = Addr::unchecked("alice");
+        let bob = Addr::unchecked("bob");
+        seed_stake(&mut deps, "alice", &["1", "2"], 10, now - 86400);
+        seed_stake(&mut deps, "bob", &["3"], 5, now - 3600);
+        let initial = CONFIG_ITEM.load(&deps.storage).unwrap();
+        let alice_raw = deps.api.addr_canonicalize("alice").unwrap();
+        let bob_raw = deps.api.addr_canonicalize("bob").unwrap();
+        let weights = |staked: &Staked| -> Vec<u128> {
+            staked
+                .staking_weights
+                .as_ref()
+                .unwrap()
+                .iter()
+                .map(|x| x.amount.u128())
+                .collect()
+        };
+
+        // only NFTs the sender has staked
+        assert!(try_transfer_position(
+            deps.as_mut(),
+            env.clone(),
+            &alice,
+            bob.clone(),
+            vec!["3".to_string()]
+        )
+        .is_err());
+
+        try_transfer_position(
+            deps.as_mut(),
+            env.clone(),
+            &alice,
+            bob.clone(),
+            vec!["1".to_string()],
+        )
+        .unwrap();
+        let state = CONFIG_ITEM.load(&deps.storage).unwrap();
+        assert_eq!(state.total_staked_amount, initial.total_staked_amount);
+        assert_eq!(state.staking_weights, initial.staking_weights);
+        let alice_staked = STAKED_STORE.get(&deps.storage, &alice_raw).unwrap();
+        let bob_staked = STAKED_STORE.get(&deps.storage, &bob_raw).unwrap();
+        assert_eq!(alice_staked.staked_amount, Uint128::from(1u128));
+        assert_eq!(bob_staked.staked_amount, Uint128::from(2u128));
+        assert_eq!(weights(&alice_staked), vec![10, 10]);
+        assert_eq!(weights(&bob_staked), vec![15, 15]);
+        // bob's stake now counts from the later of the two
+        assert_eq!(bob_staked.last_staked_date, Some(now - 3600));
+        assert_eq!(
+            STAKED_NFTS_STORE.get(&deps.storage, &bob_raw).unwrap(),
+            vec!["3".to_string(), "1".to_string()]
+        );
+        for (staker_raw, action) in [
+            (&alice_raw, HistoryAction::TransferOut),
+            (&bob_raw, HistoryAction::TransferIn),
+        ] {
+            let history = user_history_store(staker_raw);
+            let last = history
+                .get_at(&deps.storage, history.get_len(&deps.storage).unwrap() - 1)
+                .unwrap();
+            assert_eq!(last.action, action);
+            assert_eq!(last.token_ids, Some(vec!["1".to_string()]));
+        }
+
+        // a full transfer clears the sender's position
+        try_transfer_position(
+            deps.as_mut(),
+            env.clone(),
+            &alice,
+            bob.clone(),
+            vec!["2".to_string()],
+        )
+        .unwrap();
+        let alice_staked = STAKED_STORE.get(&deps.storage, &alice_raw).unwrap();
+        assert!(alice_staked.staked_amount.is_zero());
+        assert_eq!(alice_staked.last_staked_date, None);
+        assert_eq!(weights(&alice_staked), vec![0, 0]);
+        let bob_staked = STAKED_STORE.get(&deps.storage, &bob_raw).unwrap();
+        assert_eq!(weights(&bob_staked), vec![25, 25]);
+        let state = CONFIG_ITEM.load(&deps.storage).unwrap();
+        assert_eq!(state.staking_weights, initial.staking_weights);
+
+        // NFTs staked before their weights were recorded stay put
+        STAKED_NFT_INFO_STORE
+            .remove(&mut deps.storage, &"3".to_string())
+            .unwrap();
+        assert!(
+            try_transfer_position(deps.as_mut(), env, &bob, alice, vec!["3".to_string()]).is_err()
+        );
+    }
+
+    #[test]
+    fn nfts_sent_with_any_other_msg_are_staked() {
+        let (init_result, mut deps) = init_helper_with_config();
+        assert!(init_result.is_ok());
+        let env = mock_env();
+        let collection = Addr::unchecked("collection");
+        let alice = Addr::unchecked("alice");
+        let mut state = CONFIG_ITEM.load(&deps.storage).unwrap();
+        state.staking_contract.address = collection.clone();
+        state.trait_restriction = None;
+        state.staking_weights = None;
+        CONFIG_ITEM.save(&mut deps.storage, &state).unwrap();
+
+        for (token_id, msg) in [
+            ("1", None),
+            ("2", Some(Binary::from(b"a memo".to_vec()))),
+            ("3", to_binary(&"legacy").ok()),
+        ] {
+            try_batch_receive(
+                deps.as_mut(),
+                env.clone(),
+                &collection,
+                &alice,
+                vec![token_id.to_string()],
+                msg,
+            )
+            .unwrap();
+        }
+        let alice_raw = deps.api.addr_canonicalize(alice.as_str()).unwrap();
+        assert_eq!(
+            STAKED_NFTS_STORE.get(&deps.storage, &alice_raw).unwrap(),
+            vec!["1".to_string(), "2".to_string(), "3".to_string()]
+        );
+    }
+
+    /// Records `token_ids` as staked by `staker`, each weighing `weight` in every
+    /// trait type, updating the pool totals the same way staking does.
+    fn seed_stake(
+        deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>,
+        staker: &str,
+        token_ids: &[&str],
+        weight: u128,
+        staked_at: u64,
+    ) {
+        let staker_raw = deps.api.addr_canonicalize(staker).unwrap();
+        let mut state = CONFIG_ITEM.load(&deps.storage).unwrap();
+        let staked_amount = Uint128::from(token_ids.len() as u128);
+        let mut nft_weights: Vec<UserStakingWeight> = Vec::new();
+        let mut user_weights: Vec<UserStakingWeight> = Vec::new();
+        for pool_weight in state.staking_weights.as_mut().unwrap().iter_mut() {
+            pool_weight.amount += Uint128::from(weight) * staked_amount;
+            nft_weights.push(UserStakingWeight {
+                amount: Uint128::from(weight),
+                weight_trait_type: pool_weight.weight_trait_type.clone(),
+            });
+            user_weights.push(UserStakingWeight {
+                amount: Uint128::from(weight) * staked_amount,
+                weight_trait_type: pool_weight.weight_trait_type.clone(),
+            });
+        }
+        state.total_staked_amount += staked_amount;
+        CONFIG_ITEM.save(&mut deps.storage, &state).unwrap();
+
+        let token_ids: Vec<String> = token_ids.iter().map(|x| x.to_string()).collect();
+        for token_id in token_ids.iter() {
+            STAKED_NFT_INFO_STORE
+                .insert(
+                    &mut deps.storage,
+                    token_id,
+                    &StakedNftInfo {
+                        staked_date: staked_at,
+                        weights: nft_weights.clone(),
+                    },
+                )
+                .unwrap();
+        }
+        STAKED_NFTS_STORE
+            .insert(&mut deps.storage, &staker_raw, &token_ids)
+            .unwrap();
+        STAKED_STORE
+            .insert(
+                &mut deps.storage,
+                &staker_raw,
+                &Staked {
+                    last_claimed_date: None,
+                    staked_amount,
+                    last_staked_date: Some(staked_at),
+                    staking_weights: Some(user_weights),
+                },
+            )
+            .unwrap();
+    }
+
     fn init_helper_with_config() -> (
         StdResult<Response>,
         OwnedDeps<MockStorage, MockApi, MockQuerier>,
@@ -1383,6 +4588,7 @@ mod tests {
                     weight_percentage: Uint128::from(50u128),
                 },
             ]),
+            timelock_delay: None,
         };
 
         (instantiate(deps.as_mut(), env, info, init_msg), deps)
@@ -11,6 +11,8 @@ pub struct InstantiateMsg {
     pub reward_contracts: Vec<RewardsContractInfo>,
     pub trait_restriction: Option<String>,
     pub staking_weights: Option<Vec<StakingWeight>>,
+    /// Seconds admin config changes wait before they can be executed, 48h if unset
+    pub timelock_delay: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
@@ -51,13 +53,50 @@ pub struct UserStakingWeight {
     pub weight_trait_type: String,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryAction {
+    Stake,
+    Withdraw,
+    Claim,
+    Eject,
+    /// Rewards forfeited instead of paid out
+    Penalty,
+    /// Only in histories from before deposits moved to the admin log
+    RewardDeposit,
+    RaffleWin,
+    /// A dividend share paid out
+    Dividend,
+    /// Vested rewards released to the staker
+    VestedRelease,
+    /// Stake moved to another address
+    TransferOut,
+    /// Stake received from another address
+    TransferIn,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
 pub struct History {
     pub amount: Uint128,
     pub date: u64,
-    pub action: String,
+    pub action: HistoryAction,
+    /// Reward token contract for claim, penalty and deposit entries
+    pub reward_token: Option<Addr>,
+    pub token_ids: Option<Vec<String>>,
+}
+
+/// Narrows a user history query. `start_time` and `end_time` are inclusive.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, JsonSchema)]
+pub struct HistoryFilter {
+    pub action: Option<HistoryAction>,
+    pub start_time: Option<u64>,
+    pub end_time: Option<u64>,
+    pub newest_first: Option<bool>,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct MigrateMsg {}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum ExecuteMsg {
@@ -80,10 +119,29 @@ pub enum ExecuteMsg {
         quantity: Uint128,
     },
     WithdrawFundsNoReward {},
+    /// Ejects every address in `stakers`. The single `staker` field of earlier
+    /// versions is still accepted and is ejected along with them.
     Eject {
-        staker: Addr,
+        staker: Option<Addr>,
+        stakers: Option<Vec<Addr>>,
+        forfeit_reason: Option<String>,
     },
     ClaimRewards {},
+    ClaimDividends {},
+    WithdrawVested {},
+    /// Lets one operator claim for the sender, replacing any earlier one
+    SetOperator {
+        operator: Operator,
+    },
+    RevokeOperator {},
+    ClaimRewardsFor {
+        staker: Addr,
+    },
+    /// Moves part of the sender's stake to another address, keeping when it was staked
+    TransferPosition {
+        recipient: Addr,
+        token_ids: Vec<String>,
+    },
     UpdateRewardContract {
         contracts: Vec<RewardsContractInfo>,
     },
@@ -91,15 +149,341 @@ pub enum ExecuteMsg {
     SetViewingKey {
         key: String,
     },
+    CreateViewingKey {
+        entropy: String,
+    },
+    RevokeAdminViewingKeys {},
     SetActiveState {
         is_active: bool,
     },
+    /// Moves legacy history and seeds checkpoints for the next `limit` stakers
+    /// after a migration, from `start` or where the last call stopped
+    MigrateHistory {
+        start: Option<u32>,
+        limit: Option<u32>,
+    },
+    RecoverNfts {
+        token_ids: Vec<String>,
+        action: RecoverAction,
+    },
+    SetPauseState {
+        operation: PauseOperation,
+        is_paused: bool,
+        resume_time: Option<u64>,
+        reason: Option<String>,
+    },
+    ProposeOwner {
+        new_owner: Addr,
+    },
+    AcceptOwner {},
+    GrantRole {
+        address: Addr,
+        role: Role,
+    },
+    RevokeRole {
+        address: Addr,
+        role: Role,
+    },
+    SetTimelockDelay {
+        delay: u64,
+    },
+    /// Queued behind the timelock like other config changes. `None` pays claims
+    /// out right away again; schedules already running keep vesting.
+    SetVesting {
+        vesting: Option<VestingConfig>,
+    },
+    ExecuteConfigChange {
+        id: u64,
+    },
+    CancelConfigChange {
+        id: u64,
+    },
+    /// Addresses allowed to stake for a beneficiary other than themselves, anyone
+    /// can while unset
+    SetBeneficiaryAllowList {
+        allowed: Option<Vec<Addr>>,
+    },
+    SetSponsorList {
+        list: SponsorList,
+    },
+    RefundIncentive {
+        id: u64,
+    },
+    RunRaffle {
+        prize: RafflePrize,
+        /// Draw by this trait's staking weight instead of the staked count
+        weight_trait_type: Option<String>,
+    },
+}
+
+/// What raffle winners are paid. The prize has to be deposited with `RafflePrize`
+/// before the raffle is run.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RafflePrize {
+    /// `amount` of a SNIP-20 to each of `winners` winners
+    Token {
+        address: Addr,
+        code_hash: String,
+        amount: Uint128,
+        winners: u32,
+    },
+    /// One NFT per winner, in the order they are drawn
+    Nft {
+        address: Addr,
+        code_hash: String,
+        token_ids: Vec<String>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct Raffle {
+    pub prize: RafflePrize,
+    pub weight_trait_type: Option<String>,
+    pub winners: Vec<Addr>,
+    pub date: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct RafflesResponse {
+    pub raffles: Vec<Raffle>,
+    pub total: u32,
+}
+
+/// Admin parameter changes that only take effect after the timelock delay.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigChange {
+    UpdateRewardContract { contracts: Vec<RewardsContractInfo> },
+    SetTimelockDelay { delay: u64 },
+    SetVesting { vesting: Option<VestingConfig> },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct PendingConfigChange {
+    pub id: u64,
+    pub change: ConfigChange,
+    pub proposer: Addr,
+    pub queued_time: u64,
+    /// Earliest time the change can be executed
+    pub eta: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct AdminLogEntry {
+    pub actor: Addr,
+    pub action: String,
+    /// The executed message as JSON, empty for `set_viewing_key`
+    pub params: String,
+    pub date: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct AdminLogResponse {
+    pub entries: Vec<AdminLogEntry>,
+    pub total: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct PendingConfigChangesResponse {
+    pub timelock_delay: u64,
+    pub changes: Vec<PendingConfigChange>,
+}
+
+/// Admin roles below the owner. The owner implicitly holds every role.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Can toggle the active state and pause individual operations
+    Pauser,
+    /// Can update the reward contracts; pulling unallocated rewards stays with the owner
+    RewardManager,
+    /// Can read any staker's checkpointed balances and voting power, e.g. a DAO's
+    /// governance contract querying with its own viewing key or permit
+    Governance,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct RoleAssignment {
+    pub address: Addr,
+    pub roles: Vec<Role>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct AdminsResponse {
+    pub owner: Addr,
+    pub pending_owner: Option<Addr>,
+    pub roles: Vec<RoleAssignment>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PauseOperation {
+    Staking,
+    Claiming,
+    Withdrawing,
+    RewardFunding,
+}
+
+/// A paused operation stays paused until `resume_time` (if set) is reached
+/// or the pause is lifted.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, JsonSchema)]
+pub struct PauseStatus {
+    pub is_paused: bool,
+    pub resume_time: Option<u64>,
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, JsonSchema)]
+pub struct PauseFlags {
+    pub staking: PauseStatus,
+    pub claiming: PauseStatus,
+    pub withdrawing: PauseStatus,
+    pub reward_funding: PauseStatus,
+}
+
+/// What to do with NFTs that reached the contract through `TransferNft`
+/// instead of `SendNft` and were therefore never recorded as staked.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RecoverAction {
+    Return { recipient: Addr },
+    Stake { staker: Addr },
+}
+
+/// Optional `msg` of `BatchReceiveNft`
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReceiveNftMsg {
+    /// Credits the stake to `beneficiary` instead of the sender
+    ReceiveStake { beneficiary: Option<Addr> },
+    /// NFTs of another collection deposited by a reward manager as raffle prizes
+    RafflePrize {},
 }
 
 #[derive(Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum HandleReceiveMsg {
-    ReceiveRewards {},
+    ReceiveRewards {
+        mode: Option<RewardMode>,
+    },
+    /// Sent by a sponsor with an allowed token, using Send's `recipient_code_hash`
+    SponsorIncentive {
+        start: u64,
+        end: u64,
+    },
+    /// A raffle prize from a reward manager, held until `RunRaffle` pays it out
+    RafflePrize {},
+}
+
+/// Which tokens may sponsor the pool, any token not listed is refused, and which
+/// sponsors are refused whatever token they send.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, JsonSchema)]
+pub struct SponsorList {
+    pub allowed: Vec<SponsorToken>,
+    pub denied: Vec<Addr>,
+}
+
+/// An allowed sponsor token and the code hash it is paid out with.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct SponsorToken {
+    pub address: Addr,
+    pub code_hash: String,
+}
+
+/// A sponsor's `amount` streamed to stakers between `start` and `end`. `unused` is
+/// what streamed while nothing was staked and goes back to the sponsor.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct Incentive {
+    pub id: u64,
+    pub sponsor: Addr,
+    pub token: Addr,
+    pub code_hash: String,
+    pub amount: Uint128,
+    pub start: u64,
+    pub end: u64,
+    pub last_update: u64,
+    pub distributed: Uint128,
+    pub unused: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OperatorScope {
+    Claim,
+    /// Can compound as well, where rewards are paid in the staking token
+    ClaimAndRestake,
+}
+
+/// An address allowed to claim for a staker until `expires`. Claimed rewards go to
+/// `recipient`, or the staker if unset.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct Operator {
+    pub address: Addr,
+    pub scope: OperatorScope,
+    pub expires: Option<u64>,
+    pub recipient: Option<Addr>,
+}
+
+/// While set, claimed rewards vest linearly over `duration` seconds and none of
+/// them can be withdrawn before `cliff` seconds have passed.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct VestingConfig {
+    pub duration: u64,
+    pub cliff: u64,
+}
+
+/// A claim locked under vesting. `cliff` and `end` are timestamps.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct VestingSchedule {
+    pub reward_token: Addr,
+    pub code_hash: String,
+    pub amount: Uint128,
+    pub released: Uint128,
+    pub start: u64,
+    pub cliff: u64,
+    pub end: u64,
+}
+
+/// Claimed rewards of a token still held by the pool and those already released.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct VestingTotal {
+    pub reward_token: Addr,
+    pub locked: Uint128,
+    pub released: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct VestedReward {
+    pub reward_token: Addr,
+    pub amount: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct MyVestingResponse {
+    pub schedules: Vec<VestingSchedule>,
+    pub withdrawable: Vec<VestedReward>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct VestingInfoResponse {
+    pub vesting: Option<VestingConfig>,
+    pub totals: Vec<VestingTotal>,
+}
+
+/// `Stream` (the default) adds to the rewards paid out per day. `Dividend` splits
+/// the amount right away between everyone currently staked, pro-rata.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RewardMode {
+    Stream,
+    Dividend,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct PendingDividend {
+    pub reward_token: Addr,
+    pub amount: Uint128,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
@@ -107,7 +491,20 @@ pub enum HandleReceiveMsg {
 pub enum QueryMsg {
     GetStakedInfo {},
     GetMyStakedInfo {
-        permit: Permit,
+        permit: Option<Permit>,
+        viewer: Option<ViewerInfo>,
+    },
+    GetMyDividends {
+        permit: Option<Permit>,
+        viewer: Option<ViewerInfo>,
+    },
+    GetMyOperator {
+        permit: Option<Permit>,
+        viewer: Option<ViewerInfo>,
+    },
+    GetMyVesting {
+        permit: Option<Permit>,
+        viewer: Option<ViewerInfo>,
     },
     GetRewardBalance {
         viewer: ViewerInfo,
@@ -116,12 +513,73 @@ pub enum QueryMsg {
         viewer: ViewerInfo,
     },
     GetNumUserHistory {
-        permit: Permit,
+        permit: Option<Permit>,
+        viewer: Option<ViewerInfo>,
+    },
+    GetMyStakedNfts {
+        permit: Option<Permit>,
+        viewer: Option<ViewerInfo>,
+        start_page: u32,
+        page_size: u32,
+        include_info: Option<bool>,
     },
     GetUserHistory {
-        permit: Permit,
+        permit: Option<Permit>,
+        viewer: Option<ViewerInfo>,
         start_page: u32,
         page_size: u32,
+        filter: Option<HistoryFilter>,
+    },
+    GetPauseState {},
+    GetAdmins {},
+    GetPendingConfigChanges {},
+    GetAdminLog {
+        viewer: ViewerInfo,
+        start_page: u32,
+        page_size: u32,
+    },
+    GetStakerInfo {
+        viewer: ViewerInfo,
+        staker: String,
+    },
+    GetStakerHistory {
+        viewer: ViewerInfo,
+        staker: String,
+        page: u32,
+        page_size: u32,
+    },
+    ListStakers {
+        viewer: ViewerInfo,
+        start_after: Option<u32>,
+        limit: Option<u32>,
+    },
+    GetStakedAt {
+        permit: Option<Permit>,
+        viewer: Option<ViewerInfo>,
+        address: String,
+        time: u64,
+    },
+    GetTotalStakedAt {
+        time: u64,
+    },
+    GetVotingPower {
+        permit: Option<Permit>,
+        viewer: Option<ViewerInfo>,
+        address: String,
+        time: Option<u64>,
+        weight_trait_type: Option<String>,
+    },
+    GetVestingInfo {},
+    GetIncentives {},
+    GetBeneficiaryAllowList {},
+    GetSponsorList {},
+    GetRaffles {
+        viewer: ViewerInfo,
+        start_page: u32,
+        page_size: u32,
+    },
+    GetStrayNfts {
+        viewer: ViewerInfo,
     },
 }
 
@@ -135,6 +593,53 @@ pub struct StakedInfoResponse {
     pub trait_restriction: Option<String>,
     pub staking_weights: Option<Vec<StakingWeight>>,
     pub is_active: Option<bool>,
+    pub pause_flags: Option<PauseFlags>,
+}
+
+/// When an NFT was staked and what it added to its staker's weights
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct StakedNftInfo {
+    pub staked_date: u64,
+    pub weights: Vec<UserStakingWeight>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct StakedNft {
+    pub token_id: String,
+    pub info: Option<StakedNftInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct StakedNftsResponse {
+    pub nfts: Vec<StakedNft>,
+    pub total: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct ViewingKeyResponse {
+    pub key: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct StakerEntry {
+    pub address: Addr,
+    pub staked: Staked,
+    pub estimated_rewards: Vec<EstimatedReward>,
+}
+
+/// `next` is the `start_after` cursor for the following page, `None` once all stakers are listed
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct StakersResponse {
+    pub stakers: Vec<StakerEntry>,
+    pub next: Option<u32>,
+}
+
+/// `power` out of `total_power` as of `time`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct VotingPowerResponse {
+    pub power: Uint128,
+    pub total_power: Uint128,
+    pub time: u64,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
@@ -143,6 +648,13 @@ pub struct MyStakedInfoResponse {
     pub estimated_rewards: Vec<EstimatedReward>,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct StakerInfoResponse {
+    pub staked: Staked,
+    pub estimated_rewards: Vec<EstimatedReward>,
+    pub staked_nfts: Vec<String>,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
 pub struct EstimatedReward {
     pub estimated_rewards: Uint128,
@@ -1,10 +1,11 @@
 use crate::error::ContractError;
 use crate::msg::{
     ExecuteMsg, InstantiateMsg, QueryMsg,
-    RewardsContractInfo, ContractInfo, StakedInfoResponse, StakedInfoQueryMsg, ContractsWithInfoResponse
+    RewardsContractInfo, ContractInfo, StakedInfoResponse, StakedInfoQueryMsg, ContractsWithInfoResponse,
+    AdminsResponse, Role, RoleAssignment
 }; 
 use crate::state::{
-    State, CONFIG_ITEM
+    State, CONFIG_ITEM, PENDING_OWNER_ITEM, ROLES_STORE
 };
 use cosmwasm_std::{
     entry_point, from_binary, to_binary, Addr, Binary, CanonicalAddr, CosmosMsg, Deps, DepsMut,
@@ -51,6 +52,16 @@ pub fn execute(
         ExecuteMsg::SetActiveState { is_active } => {
             try_set_active_state(deps, _env, &info.sender, is_active)
         }
+        ExecuteMsg::ProposeOwner { new_owner } => {
+            try_propose_owner(deps, &info.sender, new_owner)
+        }
+        ExecuteMsg::AcceptOwner {} => try_accept_owner(deps, &info.sender),
+        ExecuteMsg::GrantRole { address, role } => {
+            try_set_role(deps, &info.sender, address, role, true)
+        }
+        ExecuteMsg::RevokeRole { address, role } => {
+            try_set_role(deps, &info.sender, address, role, false)
+        }
     }
 }
 
@@ -110,6 +121,7 @@ pub fn try_set_active_state(
 ) -> Result<Response, ContractError> {
     let mut state = CONFIG_ITEM.load(deps.storage)?;
 
+    check_role(deps.as_ref(), &state, sender, Role::Pauser)?;
     state.is_active = is_active;
 
     CONFIG_ITEM.save(deps.storage, &state)?;
@@ -117,11 +129,99 @@ pub fn try_set_active_state(
     Ok(Response::default())
 }
 
+pub fn try_propose_owner(
+    deps: DepsMut,
+    sender: &Addr,
+    new_owner: Addr,
+) -> Result<Response, ContractError> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+
+    if sender.clone() != state.owner {
+        return Err(ContractError::CustomError {
+            val: "You don't have the permissions to execute this command".to_string(),
+        });
+    }
+
+    let new_owner = deps.api.addr_validate(new_owner.as_str())?;
+    PENDING_OWNER_ITEM.save(deps.storage, &new_owner)?;
+
+    Ok(Response::default())
+}
+
+pub fn try_accept_owner(deps: DepsMut, sender: &Addr) -> Result<Response, ContractError> {
+    let mut state = CONFIG_ITEM.load(deps.storage)?;
+
+    if PENDING_OWNER_ITEM.may_load(deps.storage)?.as_ref() != Some(sender) {
+        return Err(ContractError::CustomError {
+            val: "You are not the pending owner".to_string(),
+        });
+    }
+
+    state.owner = sender.clone();
+    CONFIG_ITEM.save(deps.storage, &state)?;
+    PENDING_OWNER_ITEM.remove(deps.storage);
+
+    Ok(Response::default())
+}
+
+pub fn try_set_role(
+    deps: DepsMut,
+    sender: &Addr,
+    address: Addr,
+    role: Role,
+    grant: bool,
+) -> Result<Response, ContractError> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+
+    if sender.clone() != state.owner {
+        return Err(ContractError::CustomError {
+            val: "You don't have the permissions to execute this command".to_string(),
+        });
+    }
+
+    let address_raw = deps.api.addr_canonicalize(address.as_str())?;
+    let mut roles = ROLES_STORE
+        .get(deps.storage, &address_raw)
+        .unwrap_or_default();
+    roles.retain(|x| *x != role);
+    if grant {
+        roles.push(role);
+    }
+
+    if roles.is_empty() {
+        ROLES_STORE.remove(deps.storage, &address_raw)?;
+    } else {
+        ROLES_STORE.insert(deps.storage, &address_raw, &roles)?;
+    }
+
+    Ok(Response::default())
+}
+
+/// Passes if `sender` is the owner or has been granted `role`.
+fn check_role(deps: Deps, state: &State, sender: &Addr, role: Role) -> Result<(), ContractError> {
+    if sender.clone() == state.owner {
+        return Ok(());
+    }
+
+    let sender_raw = deps.api.addr_canonicalize(sender.as_str())?;
+    let roles = ROLES_STORE
+        .get(deps.storage, &sender_raw)
+        .unwrap_or_default();
+    if !roles.contains(&role) {
+        return Err(ContractError::CustomError {
+            val: "You don't have the permissions to execute this command".to_string(),
+        });
+    }
+
+    Ok(())
+}
+
 #[entry_point]
 pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
     match msg {
         QueryMsg::GetContracts {} => to_binary(&query_contracts(deps)?),
         QueryMsg::GetContractsWithInfo {} => to_binary(&query_contracts_info(deps)?),
+        QueryMsg::GetAdmins {} => to_binary(&query_admins(deps)?),
     }
 }
 
@@ -130,6 +230,24 @@ fn query_contracts(deps: Deps) -> StdResult<Vec<ContractInfo>> {
     Ok(state.staking_contracts)
 }
 
+fn query_admins(deps: Deps) -> StdResult<AdminsResponse> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+    let mut roles: Vec<RoleAssignment> = Vec::new();
+    for entry in ROLES_STORE.iter(deps.storage)? {
+        let (address_raw, address_roles) = entry?;
+        roles.push(RoleAssignment {
+            address: deps.api.addr_humanize(&address_raw)?,
+            roles: address_roles,
+        });
+    }
+
+    Ok(AdminsResponse {
+        owner: state.owner,
+        pending_owner: PENDING_OWNER_ITEM.may_load(deps.storage)?,
+        roles,
+    })
+}
+
 fn query_contracts_info(deps: Deps) -> StdResult<Vec<ContractsWithInfoResponse>> {
     let state = CONFIG_ITEM.load(deps.storage)?;
     let mut response: Vec<ContractsWithInfoResponse> = Vec::new();
@@ -42,6 +42,39 @@ pub enum ExecuteMsg {
     SetActiveState {
         is_active: bool
     },
+    ProposeOwner {
+        new_owner: Addr,
+    },
+    AcceptOwner {},
+    GrantRole {
+        address: Addr,
+        role: Role,
+    },
+    RevokeRole {
+        address: Addr,
+        role: Role,
+    },
+}
+
+/// Admin roles below the owner. The owner implicitly holds every role.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Can toggle the active state
+    Pauser,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct RoleAssignment {
+    pub address: Addr,
+    pub roles: Vec<Role>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct AdminsResponse {
+    pub owner: Addr,
+    pub pending_owner: Option<Addr>,
+    pub roles: Vec<RoleAssignment>,
 }
  
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
@@ -49,6 +82,7 @@ pub enum ExecuteMsg {
 pub enum QueryMsg {
     GetContracts {},
     GetContractsWithInfo {},
+    GetAdmins {},
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
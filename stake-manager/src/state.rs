@@ -1,7 +1,7 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use crate::msg::{ContractInfo};
+use crate::msg::{ContractInfo, Role};
 use cosmwasm_std::{Addr, CanonicalAddr, Uint128};
 use secret_toolkit::{
     snip721::ViewerInfo,
@@ -10,6 +10,10 @@ use secret_toolkit::{
 
 pub static CONFIG_KEY: &[u8] = b"config";  
 pub static CONFIG_ITEM: Item<State> = Item::new(CONFIG_KEY);  
+pub const PENDING_OWNER_KEY: &[u8] = b"pending_owner";
+pub const ROLES_KEY: &[u8] = b"roles";
+pub static PENDING_OWNER_ITEM: Item<Addr> = Item::new(PENDING_OWNER_KEY);
+pub static ROLES_STORE: Keymap<CanonicalAddr, Vec<Role>> = Keymap::new(ROLES_KEY);
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
 pub struct State {
@@ -1,23 +1,48 @@
 use crate::error::ContractError;
 use crate::msg::{
-    ExecuteMsg, HandleReceiveMsg, History, InstantiateMsg, MyStakedInfoResponse, QueryMsg,
-    RewardsContractInfo, Staked, StakedInfoResponse,
+    AdminLogEntry, AdminLogResponse, AdminsResponse, ConfigChange, ExecuteMsg, HandleReceiveMsg,
+    History, HistoryAction, HistoryFilter, Incentive, InstantiateMsg, MigrateMsg,
+    MyStakedInfoResponse, MyVestingResponse, Operator, OperatorScope, PauseFlags, PauseOperation,
+    PauseStatus, PendingConfigChange, PendingConfigChangesResponse, PendingDividend, QueryMsg,
+    Raffle, RafflePrize, RafflesResponse, ReceiveNftMsg, RewardMode, RewardsContractInfo, Role,
+    RoleAssignment, SponsorList, Staked, StakedInfoResponse, StakerEntry, StakersResponse,
+    VestedReward, VestingConfig, VestingInfoResponse, VestingSchedule, VestingTotal,
+    ViewingKeyResponse, VotingPowerResponse,
 };
-use crate::rand::sha_256;
+use crate::rand::{sha_256, Prng};
 use crate::state::{
-    State, ADMIN_VIEWING_KEY_ITEM, CONFIG_ITEM, HISTORY_STORE, PREFIX_REVOKED_PERMITS, STAKED_STORE,
+    user_checkpoint_store, user_history_store, Checkpoint, DividendPool, LegacyHistory, Migration,
+    Shutdown, State, UserDividend, ADMIN_LOG_STORE, ADMIN_VIEWING_KEYS_ITEM,
+    ADMIN_VIEWING_KEY_ITEM, AUTO_COMPOUND_STORE, BENEFICIARY_ALLOW_LIST_ITEM, CONFIG_CHANGES_STORE,
+    CONFIG_CHANGE_ID_ITEM, CONFIG_ITEM, DIVIDEND_POOLS_ITEM, INCENTIVES_ITEM, INCENTIVE_ID_ITEM,
+    LEGACY_CONFIG_ITEM, LEGACY_HISTORY_STORE, MIGRATION_ITEM, OPERATORS_STORE, PAUSE_ITEM,
+    PENDING_OWNER_ITEM, PREFIX_REVOKED_PERMITS, PRNG_SEED_ITEM, RAFFLES_STORE,
+    RAFFLE_NFT_PRIZES_STORE, RAFFLE_TOKEN_PRIZES_STORE, REDEEMABLE_ITEM, ROLES_STORE,
+    SHUTDOWN_ITEM, SPONSOR_LIST_ITEM, STAKED_STORE, TIMELOCK_ITEM, TOTAL_CHECKPOINTS_STORE,
+    USER_DIVIDENDS_STORE, USER_VESTING_STORE, USER_VIEWING_KEYS_STORE, VESTING_ITEM,
+    VESTING_TOTALS_ITEM,
 };
+use base64::{engine::general_purpose::STANDARD, Engine};
 use cosmwasm_std::{
-    entry_point, from_binary, to_binary, Addr, Binary, CanonicalAddr, CosmosMsg, Decimal, Deps,
-    DepsMut, Env, MessageInfo, Response, StdError, StdResult, Uint128,
+    entry_point, from_binary, to_binary, Addr, Api, Binary, CanonicalAddr, CosmosMsg, Decimal,
+    Deps, DepsMut, Env, MessageInfo, Response, StdError, StdResult, Storage, Uint128,
 };
 use secret_toolkit::{
     permit::{validate, Permit, RevokedPermits},
-    snip20::{balance_query, set_viewing_key_msg, transfer_msg, Balance},
-    snip721::ViewerInfo,
+    snip20::{balance_query, burn_msg, mint_msg, set_viewing_key_msg, transfer_msg, Balance},
+    snip721::{batch_transfer_nft_msg, Transfer, ViewerInfo},
+    storage::AppendStore,
 };
+use serde::{de::DeserializeOwned, Serialize};
 
 pub const BLOCK_SIZE: usize = 256;
+pub const VIEWING_KEY_PREFIX: &str = "api_key_";
+pub const MAX_ADMIN_VIEWING_KEYS: usize = 10;
+pub const DEFAULT_TIMELOCK_DELAY: u64 = 172800;
+pub const DIVIDEND_SCALE: u128 = 1_000_000_000_000;
+pub const DEFAULT_RETURN_LIMIT: u32 = 30;
+pub const MAX_RETURN_LIMIT: u32 = 100;
+pub const MAX_ACTIVE_INCENTIVES: usize = 10;
 ///  Add function to get balance
 
 #[entry_point]
@@ -27,8 +52,10 @@ pub fn instantiate(
     info: MessageInfo,
     msg: InstantiateMsg,
 ) -> Result<Response, StdError> {
-    let prng_seed: Vec<u8> = sha_256(base64::encode(msg.entropy).as_bytes()).to_vec();
-    let viewing_key = base64::encode(&prng_seed);
+    let mut seed_entropy = msg.entropy.as_bytes().to_vec();
+    seed_entropy.extend_from_slice(info.sender.as_bytes());
+    PRNG_SEED_ITEM.save(deps.storage, &new_prng_seed(&_env, &seed_entropy))?;
+    let viewing_key = new_viewing_key(deps.storage, &_env, &_env.contract.address, &msg.entropy)?;
 
     // create initial state
     let state = State {
@@ -39,10 +66,15 @@ pub fn instantiate(
         total_staked_amount: Uint128::from(0u128),
         total_rewards: Uint128::from(0u128),
         is_active: true,
+        receipt_contract: msg.receipt_contract,
     };
 
     //Save Contract state
     CONFIG_ITEM.save(deps.storage, &state)?;
+    TIMELOCK_ITEM.save(
+        deps.storage,
+        &msg.timelock_delay.unwrap_or(DEFAULT_TIMELOCK_DELAY),
+    )?;
 
     let mut response_msgs: Vec<CosmosMsg> = Vec::new();
 
@@ -70,8 +102,220 @@ pub fn instantiate(
     Ok(Response::new().add_messages(response_msgs))
 }
 
+#[entry_point]
+pub fn migrate(deps: DepsMut, env: Env, _msg: MigrateMsg) -> StdResult<Response> {
+    if PRNG_SEED_ITEM.may_load(deps.storage)?.is_none() {
+        PRNG_SEED_ITEM.save(deps.storage, &new_prng_seed(&env, b"migrate"))?;
+    }
+    if let Some(admin_key) = ADMIN_VIEWING_KEY_ITEM.may_load(deps.storage)? {
+        ADMIN_VIEWING_KEYS_ITEM.save(deps.storage, &vec![admin_key])?;
+        ADMIN_VIEWING_KEY_ITEM.remove(deps.storage);
+    }
+    migrate_state(deps.storage)?;
+    let time = env.block.time.seconds();
+    seed_total_checkpoints(deps.storage, time)?;
+    // stakers are migrated in pages by MigrateHistory
+    MIGRATION_ITEM.save(deps.storage, &Migration { time, cursor: 0 })?;
+    Ok(Response::default())
+}
+
+fn migrate_state(storage: &mut dyn Storage) -> StdResult<()> {
+    if CONFIG_ITEM.load(storage).is_ok() {
+        return Ok(());
+    }
+    let legacy = LEGACY_CONFIG_ITEM.load(storage)?;
+    let state = State {
+        owner: legacy.owner,
+        is_active: legacy.is_active,
+        staking_contract: legacy.staking_contract,
+        reward_contract: legacy.reward_contract,
+        viewing_key: legacy.viewing_key,
+        total_staked_amount: legacy.total_staked_amount,
+        total_rewards: legacy.total_rewards,
+        receipt_contract: None,
+    };
+    CONFIG_ITEM.save(storage, &state)
+}
+
+/// Checkpoints start with the first migration that has them, so times before it
+/// read as nothing staked. Stakers are seeded by `MigrateHistory`.
+fn seed_total_checkpoints(storage: &mut dyn Storage, time: u64) -> StdResult<()> {
+    if TOTAL_CHECKPOINTS_STORE.get_len(storage)? > 0 {
+        return Ok(());
+    }
+    let state = CONFIG_ITEM.load(storage)?;
+    write_checkpoint(
+        storage,
+        &TOTAL_CHECKPOINTS_STORE,
+        time,
+        state.total_staked_amount,
+    )
+}
+
+/// Checkpoints a staker's amount as of the migration unless they already have
+/// checkpoints from staking since.
+fn seed_user_checkpoints(
+    storage: &mut dyn Storage,
+    staker_raw: &CanonicalAddr,
+    staked: &Staked,
+    time: u64,
+) -> StdResult<()> {
+    let store = user_checkpoint_store(staker_raw);
+    if staked.staked_amount.is_zero() || store.get_len(storage)? > 0 {
+        return Ok(());
+    }
+    write_checkpoint(storage, &store, time, staked.staked_amount)
+}
+
+/// Earlier versions wrote history under the staker's human address but read it
+/// under the canonical one. Moves those entries over, converting their string
+/// actions to `HistoryAction`.
+fn migrate_user_history(
+    storage: &mut dyn Storage,
+    api: &dyn Api,
+    staker_raw: &CanonicalAddr,
+) -> StdResult<()> {
+    let staker = api.addr_humanize(staker_raw)?;
+    let legacy_store = LEGACY_HISTORY_STORE.add_suffix(staker.as_bytes());
+    let legacy_history = legacy_store
+        .iter(storage)?
+        .collect::<StdResult<Vec<LegacyHistory>>>()?;
+
+    let history_store = user_history_store(staker_raw);
+    for legacy in legacy_history.iter() {
+        let action = match legacy.action.as_str() {
+            "stake" => HistoryAction::Stake,
+            "withdraw" => HistoryAction::Withdraw,
+            "claim" => HistoryAction::Claim,
+            "eject" => HistoryAction::Eject,
+            "penalty" => HistoryAction::Penalty,
+            other => {
+                return Err(StdError::generic_err(format!(
+                    "Unknown history action {}",
+                    other
+                )))
+            }
+        };
+        let history: History = {
+            History {
+                amount: legacy.amount,
+                date: legacy.date,
+                action,
+                reward_token: None,
+                token_ids: None,
+            }
+        };
+        history_store.push(storage, &history)?;
+    }
+    for _ in legacy_history.iter() {
+        legacy_store.pop(storage)?;
+    }
+
+    Ok(())
+}
+
+/// Migrates the next `limit` stakers, starting at `start` if given or else where
+/// the last call stopped.
+pub fn try_migrate_history(
+    deps: DepsMut,
+    sender: &Addr,
+    start: Option<u32>,
+    limit: Option<u32>,
+) -> Result<Response, ContractError> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+    if sender.clone() != state.owner {
+        return Err(ContractError::CustomError {
+            val: "You don't have the permissions to execute this command".to_string(),
+        });
+    }
+
+    let mut migration = MIGRATION_ITEM
+        .may_load(deps.storage)?
+        .ok_or_else(|| StdError::generic_err("There is no migration to continue"))?;
+    let cursor = start.unwrap_or(migration.cursor);
+    if cursor >= STAKED_STORE.get_len(deps.storage)? {
+        return Err(ContractError::CustomError {
+            val: "All stakers have already been migrated".to_string(),
+        });
+    }
+
+    let limit = limit.unwrap_or(DEFAULT_RETURN_LIMIT).min(MAX_RETURN_LIMIT);
+    let stakers = STAKED_STORE
+        .iter(deps.storage)?
+        .skip(cursor as usize)
+        .take(limit as usize)
+        .collect::<StdResult<Vec<(CanonicalAddr, Staked)>>>()?;
+    for (staker_raw, staked) in stakers.iter() {
+        migrate_user_history(deps.storage, deps.api, staker_raw)?;
+        seed_user_checkpoints(deps.storage, staker_raw, staked, migration.time)?;
+    }
+
+    migration.cursor = cursor + stakers.len() as u32;
+    MIGRATION_ITEM.save(deps.storage, &migration)?;
+    Ok(Response::new().add_attribute("cursor", migration.cursor.to_string()))
+}
+
 #[entry_point]
 pub fn execute(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: ExecuteMsg,
+) -> Result<Response, ContractError> {
+    let is_owner = CONFIG_ITEM.load(deps.storage)?.owner == info.sender;
+    let admin_action = get_admin_action(&msg, is_owner)?;
+    let response = handle_execute(deps.branch(), env.clone(), info.clone(), msg)?;
+
+    if let Some((action, params)) = admin_action {
+        let entry = AdminLogEntry {
+            actor: info.sender,
+            action: action.to_string(),
+            params,
+            date: env.block.time.seconds(),
+        };
+        ADMIN_LOG_STORE.push(deps.storage, &entry)?;
+    }
+
+    Ok(response)
+}
+
+/// Returns the action name and JSON parameters to record in the admin log,
+/// or `None` if `msg` isn't an admin action. Viewing keys only count when the owner creates them.
+fn get_admin_action(msg: &ExecuteMsg, is_owner: bool) -> StdResult<Option<(&'static str, String)>> {
+    let action = match msg {
+        ExecuteMsg::UpdateRewardContract { .. } => "update_reward_contract",
+        ExecuteMsg::RemoveRewards { .. } => "remove_rewards",
+        ExecuteMsg::CreateViewingKey { .. } if is_owner => "create_viewing_key",
+        ExecuteMsg::RevokeAdminViewingKeys { .. } => "revoke_admin_viewing_keys",
+        ExecuteMsg::SetActiveState { .. } => "set_active_state",
+        ExecuteMsg::SetPauseState { .. } => "set_pause_state",
+        ExecuteMsg::Eject { .. } => "eject",
+        ExecuteMsg::EmergencyShutdown { .. } => "emergency_shutdown",
+        ExecuteMsg::MigrateHistory { .. } => "migrate_history",
+        ExecuteMsg::ProposeOwner { .. } => "propose_owner",
+        ExecuteMsg::AcceptOwner { .. } => "accept_owner",
+        ExecuteMsg::GrantRole { .. } => "grant_role",
+        ExecuteMsg::RevokeRole { .. } => "revoke_role",
+        ExecuteMsg::SetTimelockDelay { .. } => "set_timelock_delay",
+        ExecuteMsg::SetReceiptContract { .. } => "set_receipt_contract",
+        ExecuteMsg::ExecuteConfigChange { .. } => "execute_config_change",
+        ExecuteMsg::CancelConfigChange { .. } => "cancel_config_change",
+        ExecuteMsg::RunRaffle { .. } => "run_raffle",
+        ExecuteMsg::SetSponsorList { .. } => "set_sponsor_list",
+        ExecuteMsg::SetBeneficiaryAllowList { .. } => "set_beneficiary_allow_list",
+        ExecuteMsg::SetVesting { .. } => "set_vesting",
+        _ => return Ok(None),
+    };
+    let params = match msg {
+        // never write the key itself to the log
+        ExecuteMsg::SetViewingKey { .. } | ExecuteMsg::CreateViewingKey { .. } => String::new(),
+        _ => serde_json::to_string(msg).map_err(|e| StdError::generic_err(e.to_string()))?,
+    };
+
+    Ok(Some((action, params)))
+}
+
+fn handle_execute(
     deps: DepsMut,
     _env: Env,
     info: MessageInfo,
@@ -81,9 +325,12 @@ pub fn execute(
         ExecuteMsg::RevokePermit { permit_name } => {
             try_revoke_permit(deps, &info.sender, &permit_name)
         }
-        ExecuteMsg::UpdateRewardContract { contract } => {
-            try_update_reward_contract(deps, &info.sender, contract)
-        }
+        ExecuteMsg::UpdateRewardContract { contract } => try_queue_config_change(
+            deps,
+            _env,
+            &info.sender,
+            ConfigChange::UpdateRewardContract { contract },
+        ),
         ExecuteMsg::RemoveRewards {} => try_remove_rewards(deps, &info.sender),
         ExecuteMsg::Receive {
             sender,
@@ -91,13 +338,90 @@ pub fn execute(
             amount,
             msg,
         } => receive(deps, _env, &info.sender, &sender, &from, amount, msg),
+        ExecuteMsg::BatchReceiveNft {
+            from,
+            token_ids,
+            msg,
+        } => try_batch_receive(deps, &info.sender, &from, token_ids, msg),
         ExecuteMsg::WithdrawFunds {} => try_withdraw(deps, _env, &info.sender),
         ExecuteMsg::WithdrawFundsNoReward {} => try_withdraw_no_reward(deps, _env, &info.sender),
-        ExecuteMsg::ClaimRewards {} => try_claim_rewards(deps, _env, &info.sender),
+        ExecuteMsg::ClaimRewards {} => try_claim_rewards(deps, _env, &info.sender, &info.sender),
+        ExecuteMsg::SetOperator { operator } => {
+            try_set_operator(deps, _env, &info.sender, operator)
+        }
+        ExecuteMsg::RevokeOperator {} => try_revoke_operator(deps, &info.sender),
+        ExecuteMsg::ClaimRewardsFor { staker } => {
+            try_claim_rewards_for(deps, _env, &info.sender, staker)
+        }
+        ExecuteMsg::CompoundFor { staker } => try_compound_for(deps, _env, &info.sender, staker),
+        ExecuteMsg::ClaimDividends {} => try_claim_dividends(deps, _env, &info.sender),
+        ExecuteMsg::WithdrawVested {} => try_withdraw_vested(deps, _env, &info.sender),
+        ExecuteMsg::TransferPosition { recipient, amount } => {
+            try_transfer_position(deps, _env, &info.sender, recipient, amount)
+        }
+        ExecuteMsg::Compound {} => try_compound(deps, _env, &info.sender),
+        ExecuteMsg::SetAutoCompound { enabled } => {
+            try_set_auto_compound(deps, &info.sender, enabled)
+        }
         ExecuteMsg::SetViewingKey { key } => try_set_viewing_key(deps, _env, &info.sender, key),
+        ExecuteMsg::CreateViewingKey { entropy } => {
+            try_create_viewing_key(deps, _env, &info.sender, entropy)
+        }
+        ExecuteMsg::RevokeAdminViewingKeys {} => try_revoke_admin_viewing_keys(deps, &info.sender),
         ExecuteMsg::SetActiveState { is_active } => {
             try_set_active_state(deps, _env, &info.sender, is_active)
         }
+        ExecuteMsg::SetPauseState {
+            operation,
+            is_paused,
+            resume_time,
+            reason,
+        } => try_set_pause_state(
+            deps,
+            _env,
+            &info.sender,
+            operation,
+            is_paused,
+            resume_time,
+            reason,
+        ),
+        ExecuteMsg::Eject { staker } => try_eject(deps, _env, &info.sender, &staker),
+        ExecuteMsg::EmergencyShutdown {} => try_emergency_shutdown(deps, _env, &info.sender),
+        ExecuteMsg::ReturnStakes { limit } => try_return_stakes(deps, _env, limit),
+        ExecuteMsg::MigrateHistory { start, limit } => {
+            try_migrate_history(deps, &info.sender, start, limit)
+        }
+        ExecuteMsg::ProposeOwner { new_owner } => try_propose_owner(deps, &info.sender, new_owner),
+        ExecuteMsg::AcceptOwner {} => try_accept_owner(deps, &info.sender),
+        ExecuteMsg::GrantRole { address, role } => {
+            try_set_role(deps, &info.sender, address, role, true)
+        }
+        ExecuteMsg::RevokeRole { address, role } => {
+            try_set_role(deps, &info.sender, address, role, false)
+        }
+        ExecuteMsg::SetTimelockDelay { delay } => try_queue_config_change(
+            deps,
+            _env,
+            &info.sender,
+            ConfigChange::SetTimelockDelay { delay },
+        ),
+        ExecuteMsg::SetReceiptContract { contract } => try_queue_config_change(
+            deps,
+            _env,
+            &info.sender,
+            ConfigChange::SetReceiptContract { contract },
+        ),
+        ExecuteMsg::SetVesting { vesting } => try_set_vesting(deps, _env, &info.sender, vesting),
+        ExecuteMsg::ExecuteConfigChange { id } => {
+            try_execute_config_change(deps, _env, &info.sender, id)
+        }
+        ExecuteMsg::CancelConfigChange { id } => try_cancel_config_change(deps, &info.sender, id),
+        ExecuteMsg::SetBeneficiaryAllowList { allowed } => {
+            try_set_beneficiary_allow_list(deps, &info.sender, allowed)
+        }
+        ExecuteMsg::SetSponsorList { list } => try_set_sponsor_list(deps, &info.sender, list),
+        ExecuteMsg::RefundIncentive { id } => try_refund_incentive(deps, _env, &info.sender, id),
+        ExecuteMsg::RunRaffle { prize } => try_run_raffle(deps, _env, &info.sender, prize),
     }
 }
 
@@ -116,13 +440,17 @@ fn receive(
 
     if let Some(bin_msg) = msg {
         match from_binary(&bin_msg)? {
-            HandleReceiveMsg::ReceiveStake {} => {
+            HandleReceiveMsg::ReceiveStake { beneficiary } => {
+                let beneficiary = get_beneficiary(deps.as_ref(), from, beneficiary)?;
+                let from = &beneficiary;
                 if !state.is_active {
                     return Err(ContractError::CustomError {
                         val: "You cannot perform this action right now".to_string(),
                     });
                 }
-                let history_store = HISTORY_STORE.add_suffix(from.to_string().as_bytes());
+                let current_time = _env.block.time.seconds();
+                check_not_paused(deps.storage, PauseOperation::Staking, current_time)?;
+                let history_store = user_history_store(&deps.api.addr_canonicalize(from.as_str())?);
 
                 if info_sender != &state.staking_contract.address {
                     return Err(ContractError::CustomError {
@@ -143,31 +471,52 @@ fn receive(
                     });
                 let current_time = _env.block.time.seconds();
                 let rewards_to_claim = get_estimated_rewards(&staked, &current_time, &state)?;
+                let mut compounded = Uint128::zero();
                 if rewards_to_claim > Uint128::from(0u128) && rewards_to_claim < state.total_rewards {
                     //claim rewards
                     staked.last_claimed_date = Some(current_time);
+                    let staker_raw = deps.api.addr_canonicalize(from.as_str())?;
+                    let compound = auto_compounds(deps.storage, &state, &staker_raw);
                     let claim_history: History = {
                         History {
                             amount: rewards_to_claim,
                             date: current_time,
-                            action: "claim".to_string(),
+                            action: if compound {
+                                HistoryAction::Compound
+                            } else {
+                                HistoryAction::Claim
+                            },
+                            reward_token: Some(state.reward_contract.address.clone()),
+                            token_ids: None,
                         }
                     };
 
                     history_store.push(deps.storage, &claim_history)?;
-                    response_msgs.push(transfer_msg(
-                        from.to_string(),
-                        rewards_to_claim,
-                        None,
-                        None,
-                        BLOCK_SIZE,
-                        state.reward_contract.code_hash.to_string(),
-                        state.reward_contract.address.to_string(),
-                    )?);
+                    if compound {
+                        compounded = rewards_to_claim;
+                    } else {
+                        response_msgs.extend(reward_msg(
+                            deps.storage,
+                            &staker_raw,
+                            from,
+                            rewards_to_claim,
+                            &state.reward_contract.address,
+                            &state.reward_contract.code_hash,
+                            current_time,
+                        )?);
+                    }
                     state.total_rewards -= rewards_to_claim;
                 }
-                state.total_staked_amount += amount;
-                staked.staked_amount += amount;
+                settle_dividends(
+                    deps.storage,
+                    &deps.api.addr_canonicalize(from.as_str())?,
+                    staked.staked_amount,
+                    state.total_staked_amount,
+                    current_time,
+                )?;
+                response_msgs.extend(mint_receipt_msg(&state, from, amount + compounded)?);
+                state.total_staked_amount += amount + compounded;
+                staked.staked_amount += amount + compounded;
                 staked.last_staked_date = Some(current_time);
                 CONFIG_ITEM.save(deps.storage, &state)?;
                 STAKED_STORE.insert(
@@ -175,27 +524,100 @@ fn receive(
                     &deps.api.addr_canonicalize(&from.to_string())?,
                     &staked,
                 )?;
+                checkpoint_stake(
+                    deps.storage,
+                    &deps.api.addr_canonicalize(from.as_str())?,
+                    staked.staked_amount,
+                    state.total_staked_amount,
+                    current_time,
+                )?;
 
                 let stake_history: History = {
                     History {
                         amount: amount,
                         date: current_time,
-                        action: "stake".to_string(),
+                        action: HistoryAction::Stake,
+                        reward_token: None,
+                        token_ids: None,
                     }
                 };
 
                 history_store.push(deps.storage, &stake_history)?;
             }
-            HandleReceiveMsg::ReceiveRewards {} => {
+            HandleReceiveMsg::SponsorIncentive { start, end } => {
+                let current_time = _env.block.time.seconds();
+                check_not_paused(deps.storage, PauseOperation::RewardFunding, current_time)?;
+                add_incentive(
+                    deps.storage,
+                    info_sender,
+                    from,
+                    amount,
+                    start,
+                    end,
+                    current_time,
+                )?
+            }
+            HandleReceiveMsg::RafflePrize {} => {
+                check_role(deps.as_ref(), &state, from, Role::RewardManager)?;
+                deposit_raffle_tokens(deps.storage, &state, info_sender, amount)?
+            }
+            HandleReceiveMsg::Redeem {} => {
+                if state.receipt_contract.as_ref().map(|x| &x.address) != Some(info_sender) {
+                    return Err(ContractError::CustomError {
+                        val: info_sender.to_string() + " Address is not correct snip contract",
+                    });
+                }
+                response_msgs.extend(redeem_receipts(
+                    deps,
+                    _env.block.time.seconds(),
+                    &mut state,
+                    from,
+                    amount,
+                )?);
+            }
+            HandleReceiveMsg::ReceiveRewards { mode } => {
+                check_not_paused(
+                    deps.storage,
+                    PauseOperation::RewardFunding,
+                    _env.block.time.seconds(),
+                )?;
                 if info_sender != &state.reward_contract.address {
                     return Err(ContractError::CustomError {
                         val: info_sender.to_string()
                             + &" Address is not correct reward snip contract".to_string(),
                     });
                 }
-                state.total_rewards += amount;
+                match mode.unwrap_or(RewardMode::Stream) {
+                    RewardMode::Stream => state.total_rewards += amount,
+                    RewardMode::Dividend => add_dividend(
+                        deps.storage,
+                        state.total_staked_amount,
+                        &state.reward_contract.address,
+                        &state.reward_contract.code_hash,
+                        amount,
+                    )?,
+                }
 
                 CONFIG_ITEM.save(deps.storage, &state)?;
+
+                let deposit_history: History = {
+                    History {
+                        amount,
+                        date: _env.block.time.seconds(),
+                        action: HistoryAction::RewardDeposit,
+                        reward_token: Some(info_sender.clone()),
+                        token_ids: None,
+                    }
+                };
+                // deposits go to the admin log rather than the depositor's own history
+                let entry = AdminLogEntry {
+                    actor: from.clone(),
+                    action: "reward_deposit".to_string(),
+                    params: serde_json::to_string(&deposit_history)
+                        .map_err(|e| StdError::generic_err(e.to_string()))?,
+                    date: _env.block.time.seconds(),
+                };
+                ADMIN_LOG_STORE.push(deps.storage, &entry)?;
             }
         }
     } else {
@@ -207,10 +629,79 @@ fn receive(
     Ok(Response::new().add_messages(response_msgs))
 }
 
+/// NFTs can only be sent to the pool as raffle prizes.
+fn try_batch_receive(
+    deps: DepsMut,
+    sender: &Addr,
+    from: &Addr,
+    token_ids: Vec<String>,
+    msg: Option<Binary>,
+) -> Result<Response, ContractError> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+    match msg {
+        Some(bin_msg) => match from_binary(&bin_msg)? {
+            ReceiveNftMsg::RafflePrize {} => {
+                check_role(deps.as_ref(), &state, from, Role::RewardManager)?;
+                deposit_raffle_nfts(deps.storage, &state, sender, token_ids)?
+            }
+        },
+        None => {
+            return Err(ContractError::CustomError {
+                val: "data should be given".to_string(),
+            })
+        }
+    }
+    Ok(Response::default())
+}
+
+/// The address a stake sent by `from` is credited to. Once the owner sets an allow
+/// list, only addresses on it can stake on behalf of others.
+fn get_beneficiary(
+    deps: Deps,
+    from: &Addr,
+    beneficiary: Option<Addr>,
+) -> Result<Addr, ContractError> {
+    let beneficiary = match beneficiary {
+        Some(beneficiary) => deps.api.addr_validate(beneficiary.as_str())?,
+        None => return Ok(from.clone()),
+    };
+    if beneficiary != *from {
+        if let Some(allowed) = BENEFICIARY_ALLOW_LIST_ITEM.may_load(deps.storage)? {
+            if !allowed.contains(from) {
+                return Err(ContractError::CustomError {
+                    val: "You aren't allowed to stake on behalf of others".to_string(),
+                });
+            }
+        }
+    }
+    Ok(beneficiary)
+}
+
+pub fn try_set_beneficiary_allow_list(
+    deps: DepsMut,
+    sender: &Addr,
+    allowed: Option<Vec<Addr>>,
+) -> Result<Response, ContractError> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+    if sender.clone() != state.owner {
+        return Err(ContractError::CustomError {
+            val: "You don't have the permissions to execute this command".to_string(),
+        });
+    }
+
+    match allowed {
+        Some(allowed) => BENEFICIARY_ALLOW_LIST_ITEM.save(deps.storage, &allowed)?,
+        None => BENEFICIARY_ALLOW_LIST_ITEM.remove(deps.storage),
+    }
+    Ok(Response::default())
+}
+
 fn try_withdraw(deps: DepsMut, _env: Env, info_sender: &Addr) -> Result<Response, ContractError> {
     let mut state = CONFIG_ITEM.load(deps.storage)?;
-    let history_store = HISTORY_STORE.add_suffix(info_sender.to_string().as_bytes());
+    check_no_receipts(&state)?;
+    let history_store = user_history_store(&deps.api.addr_canonicalize(info_sender.as_str())?);
     let current_time = _env.block.time.seconds();
+    check_not_paused(deps.storage, PauseOperation::Withdrawing, current_time)?;
     let staked = STAKED_STORE
         .get(
             deps.storage,
@@ -235,30 +726,40 @@ fn try_withdraw(deps: DepsMut, _env: Env, info_sender: &Addr) -> Result<Response
         state.staking_contract.address.to_string(),
     )?);
     let current_time = _env.block.time.seconds();
-    let rewards_to_claim = get_estimated_rewards(&staked, &current_time, &state)?;
+    let accrual_time = get_accrual_time(deps.storage, current_time)?;
+    let rewards_to_claim = get_estimated_rewards(&staked, &accrual_time, &state)?;
     if rewards_to_claim > Uint128::from(0u128) && rewards_to_claim < state.total_rewards {
         //claim rewards
         let claim_history: History = {
             History {
                 amount: rewards_to_claim,
                 date: current_time,
-                action: "claim".to_string(),
+                action: HistoryAction::Claim,
+                reward_token: Some(state.reward_contract.address.clone()),
+                token_ids: None,
             }
         };
 
         history_store.push(deps.storage, &claim_history)?;
-        response_msgs.push(transfer_msg(
-            info_sender.to_string(),
+        response_msgs.extend(reward_msg(
+            deps.storage,
+            &deps.api.addr_canonicalize(info_sender.as_str())?,
+            info_sender,
             rewards_to_claim,
-            None,
-            None,
-            BLOCK_SIZE,
-            state.reward_contract.code_hash.to_string(),
-            state.reward_contract.address.to_string(),
+            &state.reward_contract.address,
+            &state.reward_contract.code_hash,
+            current_time,
         )?);
         state.total_rewards -= rewards_to_claim;
     }
 
+    settle_dividends(
+        deps.storage,
+        &deps.api.addr_canonicalize(info_sender.as_str())?,
+        staked.staked_amount,
+        state.total_staked_amount,
+        current_time,
+    )?;
     state.total_staked_amount -= staked.staked_amount;
     CONFIG_ITEM.save(deps.storage, &state)?;
     STAKED_STORE.insert(
@@ -270,12 +771,21 @@ fn try_withdraw(deps: DepsMut, _env: Env, info_sender: &Addr) -> Result<Response
             last_staked_date: None,
         },
     )?;
+    checkpoint_stake(
+        deps.storage,
+        &deps.api.addr_canonicalize(info_sender.as_str())?,
+        Uint128::zero(),
+        state.total_staked_amount,
+        current_time,
+    )?;
 
     let stake_history: History = {
         History {
             amount: staked.staked_amount,
             date: current_time,
-            action: "withdraw".to_string(),
+            action: HistoryAction::Withdraw,
+            reward_token: None,
+            token_ids: None,
         }
     };
 
@@ -285,8 +795,10 @@ fn try_withdraw(deps: DepsMut, _env: Env, info_sender: &Addr) -> Result<Response
 
 fn try_withdraw_no_reward(deps: DepsMut, _env: Env, info_sender: &Addr) -> Result<Response, ContractError> {
     let mut state = CONFIG_ITEM.load(deps.storage)?;
-    let history_store = HISTORY_STORE.add_suffix(info_sender.to_string().as_bytes());
+    check_no_receipts(&state)?;
+    let history_store = user_history_store(&deps.api.addr_canonicalize(info_sender.as_str())?);
     let current_time = _env.block.time.seconds();
+    check_not_paused(deps.storage, PauseOperation::Withdrawing, current_time)?;
     let staked = STAKED_STORE
         .get(
             deps.storage,
@@ -312,6 +824,13 @@ fn try_withdraw_no_reward(deps: DepsMut, _env: Env, info_sender: &Addr) -> Resul
     )?);
     let current_time = _env.block.time.seconds();
 
+    settle_dividends(
+        deps.storage,
+        &deps.api.addr_canonicalize(info_sender.as_str())?,
+        staked.staked_amount,
+        state.total_staked_amount,
+        current_time,
+    )?;
     state.total_staked_amount -= staked.staked_amount;
     CONFIG_ITEM.save(deps.storage, &state)?;
     STAKED_STORE.insert(
@@ -323,12 +842,21 @@ fn try_withdraw_no_reward(deps: DepsMut, _env: Env, info_sender: &Addr) -> Resul
             last_staked_date: None,
         },
     )?;
+    checkpoint_stake(
+        deps.storage,
+        &deps.api.addr_canonicalize(info_sender.as_str())?,
+        Uint128::zero(),
+        state.total_staked_amount,
+        current_time,
+    )?;
 
     let stake_history: History = {
         History {
             amount: staked.staked_amount,
             date: current_time,
-            action: "withdraw".to_string(),
+            action: HistoryAction::Withdraw,
+            reward_token: None,
+            token_ids: None,
         }
     };
 
@@ -336,249 +864,2300 @@ fn try_withdraw_no_reward(deps: DepsMut, _env: Env, info_sender: &Addr) -> Resul
     Ok(Response::new().add_messages(response_msgs))
 }
 
-fn try_claim_rewards(
+/// Moves `amount` of the sender's stake to `recipient` without restarting it. Both
+/// sides' pending rewards are paid out first so neither accrues on the other's stake.
+fn try_transfer_position(
     deps: DepsMut,
     _env: Env,
     info_sender: &Addr,
+    recipient: Addr,
+    amount: Uint128,
 ) -> Result<Response, ContractError> {
     let mut state = CONFIG_ITEM.load(deps.storage)?;
-    let history_store = HISTORY_STORE.add_suffix(info_sender.to_string().as_bytes());
+    if !state.is_active {
+        return Err(ContractError::CustomError {
+            val: "You cannot perform this action right now".to_string(),
+        });
+    }
+    check_no_receipts(&state)?;
     let current_time = _env.block.time.seconds();
+    check_not_paused(deps.storage, PauseOperation::Withdrawing, current_time)?;
+
+    let recipient = deps.api.addr_validate(recipient.as_str())?;
+    if recipient == *info_sender {
+        return Err(ContractError::CustomError {
+            val: "You can't transfer a position to yourself".to_string(),
+        });
+    }
+    let sender_raw = deps.api.addr_canonicalize(info_sender.as_str())?;
+    let recipient_raw = deps.api.addr_canonicalize(recipient.as_str())?;
     let mut staked = STAKED_STORE
-        .get(
-            deps.storage,
-            &deps.api.addr_canonicalize(&info_sender.to_string())?,
-        )
+        .get(deps.storage, &sender_raw)
         .ok_or_else(|| StdError::generic_err("You aren't staked"))?;
-
-    if staked.staked_amount == Uint128::from(0u128) {
+    if amount.is_zero() {
         return Err(ContractError::CustomError {
-            val: "There is nothing to claim".to_string(),
+            val: "There is nothing to transfer".to_string(),
+        });
+    }
+    if staked.staked_amount < amount {
+        return Err(ContractError::CustomError {
+            val: "You are trying to transfer more than is staked".to_string(),
         });
     }
+    let mut recipient_staked = STAKED_STORE
+        .get(deps.storage, &recipient_raw)
+        .unwrap_or(Staked {
+            last_claimed_date: None,
+            staked_amount: Uint128::from(0u128),
+            last_staked_date: None,
+        });
 
+    let accrual_time = get_accrual_time(deps.storage, current_time)?;
     let mut response_msgs: Vec<CosmosMsg> = Vec::new();
-    let current_time = _env.block.time.seconds();
-    let rewards_to_claim = get_estimated_rewards(&staked, &current_time, &state)?;
-    if rewards_to_claim > Uint128::from(0u128) {
-        if state.total_rewards < rewards_to_claim {
+    for (staker_raw, staker, position) in [
+        (&sender_raw, info_sender, &mut staked),
+        (&recipient_raw, &recipient, &mut recipient_staked),
+    ] {
+        let (claim_msgs, all_paid) = claim_rewards_to(
+            deps.storage,
+            &mut state,
+            position,
+            staker_raw,
+            staker,
+            accrual_time,
+            current_time,
+        )?;
+        // the claim date moves on below, so nothing accrued may be left unpaid
+        if !all_paid {
             return Err(ContractError::CustomError {
-                val: "Error trying to claim rewards".to_string(),
+                val: "The pool can't cover the accrued rewards, so the position can't be transferred yet"
+                    .to_string(),
             });
         }
-        let claim_history: History = {
-            History {
-                amount: rewards_to_claim,
-                date: current_time,
-                action: "claim".to_string(),
-            }
-        };
+        response_msgs.extend(claim_msgs);
+        settle_dividends(
+            deps.storage,
+            staker_raw,
+            position.staked_amount,
+            state.total_staked_amount,
+            current_time,
+        )?;
+        position.last_claimed_date = Some(accrual_time);
+    }
 
-        history_store.push(deps.storage, &claim_history)?;
-        response_msgs.push(transfer_msg(
-            info_sender.to_string(),
-            rewards_to_claim,
-            None,
-            None,
-            BLOCK_SIZE,
-            state.reward_contract.code_hash.to_string(),
-            state.reward_contract.address.to_string(),
-        )?);
-        staked.last_claimed_date = Some(current_time);
-        state.total_rewards -= rewards_to_claim;
-        STAKED_STORE.insert(
+    staked.staked_amount -= amount;
+    recipient_staked.staked_amount += amount;
+    // the recipient's stake counts as staked no earlier than either side's
+    recipient_staked.last_staked_date = recipient_staked
+        .last_staked_date
+        .max(staked.last_staked_date);
+    if staked.staked_amount.is_zero() {
+        staked.last_claimed_date = None;
+        staked.last_staked_date = None;
+    }
+    CONFIG_ITEM.save(deps.storage, &state)?;
+
+    for (staker_raw, position, action) in [
+        (&sender_raw, &staked, HistoryAction::TransferOut),
+        (&recipient_raw, &recipient_staked, HistoryAction::TransferIn),
+    ] {
+        STAKED_STORE.insert(deps.storage, staker_raw, position)?;
+        checkpoint_stake(
             deps.storage,
-            &deps.api.addr_canonicalize(&info_sender.to_string())?,
-            &staked,
+            staker_raw,
+            position.staked_amount,
+            state.total_staked_amount,
+            current_time,
         )?;
-        CONFIG_ITEM.save(deps.storage, &state)?;
-    } else {
-        //this technically should never happen
-        return Err(ContractError::CustomError {
-            val: "Not allowed to claim yet".to_string(),
-        });
+        let transfer_history = History {
+            amount,
+            date: current_time,
+            action,
+            reward_token: None,
+            token_ids: None,
+        };
+        user_history_store(staker_raw).push(deps.storage, &transfer_history)?;
     }
 
     Ok(Response::new().add_messages(response_msgs))
 }
 
-fn try_revoke_permit(
-    deps: DepsMut,
-    sender: &Addr,
-    permit_name: &str,
-) -> Result<Response, ContractError> {
-    RevokedPermits::revoke_permit(
-        deps.storage,
-        PREFIX_REVOKED_PERMITS,
-        &sender.to_string(),
-        permit_name,
-    );
+/// Pays out the rewards `staked` has accrued up to `accrual_time` and records the claim.
+/// Returns whether they were paid; rewards the pool can't currently cover are left unpaid.
+fn claim_rewards_to(
+    storage: &mut dyn Storage,
+    state: &mut State,
+    staked: &Staked,
+    staker_raw: &CanonicalAddr,
+    staker: &Addr,
+    accrual_time: u64,
+    current_time: u64,
+) -> StdResult<(Vec<CosmosMsg>, bool)> {
+    let rewards_to_claim = get_estimated_rewards(staked, &accrual_time, state)?;
+    if rewards_to_claim > state.total_rewards {
+        return Ok((Vec::new(), false));
+    }
+    if rewards_to_claim.is_zero() {
+        return Ok((Vec::new(), true));
+    }
 
-    Ok(Response::default())
+    let claim_history = History {
+        amount: rewards_to_claim,
+        date: current_time,
+        action: HistoryAction::Claim,
+        reward_token: Some(state.reward_contract.address.clone()),
+        token_ids: None,
+    };
+    user_history_store(staker_raw).push(storage, &claim_history)?;
+    state.total_rewards -= rewards_to_claim;
+    let claim_msgs = reward_msg(
+        storage,
+        staker_raw,
+        staker,
+        rewards_to_claim,
+        &state.reward_contract.address,
+        &state.reward_contract.code_hash,
+        current_time,
+    )?;
+    Ok((claim_msgs.into_iter().collect(), true))
 }
 
-fn try_update_reward_contract(
-    deps: DepsMut,
-    sender: &Addr,
-    contract: RewardsContractInfo,
-) -> Result<Response, ContractError> {
-    let mut state = CONFIG_ITEM.load(deps.storage)?;
+/// Mints receipts for `amount` newly staked by `staker` when the pool has a receipt token.
+fn mint_receipt_msg(state: &State, staker: &Addr, amount: Uint128) -> StdResult<Option<CosmosMsg>> {
+    match &state.receipt_contract {
+        Some(receipt) if !amount.is_zero() => Ok(Some(mint_msg(
+            staker.to_string(),
+            amount,
+            None,
+            None,
+            BLOCK_SIZE,
+            receipt.code_hash.to_string(),
+            receipt.address.to_string(),
+        )?)),
+        _ => Ok(None),
+    }
+}
 
-    if sender.clone() != state.owner {
+/// With a receipt token the principal follows the receipts, so it can only leave
+/// the pool through `Redeem`.
+fn check_no_receipts(state: &State) -> Result<(), ContractError> {
+    if state.receipt_contract.is_some() {
         return Err(ContractError::CustomError {
-            val: "You don't have the permissions to execute this command".to_string(),
+            val: "Send your receipt tokens back with Redeem instead".to_string(),
         });
     }
+    Ok(())
+}
 
-    if state.total_rewards != Uint128::from(0u128) {
+/// Burns the receipts `holder` sent back and pays them the same amount of staked
+/// tokens, taken from their own position first and then from ejected or returned
+/// stakes. Other stakers' positions are never touched.
+fn redeem_receipts(
+    deps: DepsMut,
+    current_time: u64,
+    state: &mut State,
+    holder: &Addr,
+    amount: Uint128,
+) -> Result<Vec<CosmosMsg>, ContractError> {
+    check_not_paused(deps.storage, PauseOperation::Withdrawing, current_time)?;
+    if amount.is_zero() {
         return Err(ContractError::CustomError {
-            val: "Clear out rewards first before updating".to_string(),
+            val: "There is nothing to redeem".to_string(),
         });
     }
+    let holder_raw = deps.api.addr_canonicalize(holder.as_str())?;
+    let mut staked = STAKED_STORE
+        .get(deps.storage, &holder_raw)
+        .unwrap_or(Staked {
+            last_claimed_date: None,
+            staked_amount: Uint128::from(0u128),
+            last_staked_date: None,
+        });
+    let from_position = staked.staked_amount.min(amount);
+    let redeemable = REDEEMABLE_ITEM
+        .may_load(deps.storage)?
+        .unwrap_or_default()
+        .checked_sub(amount - from_position)
+        .map_err(|_| StdError::generic_err("There isn't enough stake to redeem against"))?;
 
-    state.reward_contract = contract;
-    CONFIG_ITEM.save(deps.storage, &state)?;
-    Ok(Response::new().add_message(set_viewing_key_msg(
-        state.viewing_key.unwrap().to_string(),
-        None,
-        BLOCK_SIZE,
+    let mut response_msgs: Vec<CosmosMsg> = Vec::new();
+    if let Some(receipt) = &state.receipt_contract {
+        response_msgs.push(burn_msg(
+            amount,
+            None,
+            None,
+            BLOCK_SIZE,
+            receipt.code_hash.to_string(),
+            receipt.address.to_string(),
+        )?);
+    }
+    response_msgs.push(transfer_msg(
+        holder.to_string(),
+        amount,
+        None,
+        None,
+        BLOCK_SIZE,
+        state.staking_contract.code_hash.to_string(),
+        state.staking_contract.address.to_string(),
+    )?);
+
+    if !from_position.is_zero() {
+        let accrual_time = get_accrual_time(deps.storage, current_time)?;
+        let (claim_msgs, all_paid) = claim_rewards_to(
+            deps.storage,
+            state,
+            &staked,
+            &holder_raw,
+            holder,
+            accrual_time,
+            current_time,
+        )?;
+        response_msgs.extend(claim_msgs);
+        settle_dividends(
+            deps.storage,
+            &holder_raw,
+            staked.staked_amount,
+            state.total_staked_amount,
+            current_time,
+        )?;
+        staked.staked_amount -= from_position;
+        // rewards the pool couldn't cover stay claimable on what's left of the position
+        if all_paid {
+            staked.last_claimed_date = Some(accrual_time);
+        }
+        if staked.staked_amount.is_zero() {
+            staked.last_claimed_date = None;
+            staked.last_staked_date = None;
+        }
+        state.total_staked_amount -= from_position;
+        STAKED_STORE.insert(deps.storage, &holder_raw, &staked)?;
+        checkpoint_stake(
+            deps.storage,
+            &holder_raw,
+            staked.staked_amount,
+            state.total_staked_amount,
+            current_time,
+        )?;
+    }
+    REDEEMABLE_ITEM.save(deps.storage, &redeemable)?;
+    CONFIG_ITEM.save(deps.storage, state)?;
+
+    let withdraw_history = History {
+        amount,
+        date: current_time,
+        action: HistoryAction::Withdraw,
+        reward_token: None,
+        token_ids: None,
+    };
+    user_history_store(&holder_raw).push(deps.storage, &withdraw_history)?;
+    Ok(response_msgs)
+}
+
+/// Whether `staker_raw` opted into compounding and rewards can be restaked.
+fn auto_compounds(storage: &dyn Storage, state: &State, staker_raw: &CanonicalAddr) -> bool {
+    state.reward_contract.address == state.staking_contract.address
+        && AUTO_COMPOUND_STORE
+            .get(storage, staker_raw)
+            .unwrap_or_default()
+}
+
+fn check_compoundable(state: &State) -> Result<(), ContractError> {
+    if state.reward_contract.address != state.staking_contract.address {
+        return Err(ContractError::CustomError {
+            val: "Rewards can only be compounded when they are paid in the staking token"
+                .to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Restakes accrued rewards without transferring them anywhere.
+fn try_compound(deps: DepsMut, _env: Env, info_sender: &Addr) -> Result<Response, ContractError> {
+    let mut state = CONFIG_ITEM.load(deps.storage)?;
+    check_compoundable(&state)?;
+    if !state.is_active {
+        return Err(ContractError::CustomError {
+            val: "You cannot perform this action right now".to_string(),
+        });
+    }
+    let current_time = _env.block.time.seconds();
+    check_not_paused(deps.storage, PauseOperation::Staking, current_time)?;
+    check_not_paused(deps.storage, PauseOperation::Claiming, current_time)?;
+
+    let staker_raw = deps.api.addr_canonicalize(info_sender.as_str())?;
+    let mut staked = STAKED_STORE
+        .get(deps.storage, &staker_raw)
+        .ok_or_else(|| StdError::generic_err("You aren't staked"))?;
+    let accrual_time = get_accrual_time(deps.storage, current_time)?;
+    let rewards_to_compound = get_estimated_rewards(&staked, &accrual_time, &state)?;
+    if rewards_to_compound.is_zero() || state.total_rewards < rewards_to_compound {
+        return Err(ContractError::CustomError {
+            val: "There is nothing to compound".to_string(),
+        });
+    }
+
+    settle_dividends(
+        deps.storage,
+        &staker_raw,
+        staked.staked_amount,
+        state.total_staked_amount,
+        current_time,
+    )?;
+    staked.last_claimed_date = Some(accrual_time);
+    staked.staked_amount += rewards_to_compound;
+    state.total_rewards -= rewards_to_compound;
+    state.total_staked_amount += rewards_to_compound;
+    CONFIG_ITEM.save(deps.storage, &state)?;
+    STAKED_STORE.insert(deps.storage, &staker_raw, &staked)?;
+    checkpoint_stake(
+        deps.storage,
+        &staker_raw,
+        staked.staked_amount,
+        state.total_staked_amount,
+        current_time,
+    )?;
+
+    let compound_history = History {
+        amount: rewards_to_compound,
+        date: current_time,
+        action: HistoryAction::Compound,
+        reward_token: Some(state.reward_contract.address.clone()),
+        token_ids: None,
+    };
+    user_history_store(&staker_raw).push(deps.storage, &compound_history)?;
+
+    Ok(Response::new().add_messages(mint_receipt_msg(&state, info_sender, rewards_to_compound)?))
+}
+
+fn try_set_auto_compound(
+    deps: DepsMut,
+    info_sender: &Addr,
+    enabled: bool,
+) -> Result<Response, ContractError> {
+    let staker_raw = deps.api.addr_canonicalize(info_sender.as_str())?;
+    if enabled {
+        check_compoundable(&CONFIG_ITEM.load(deps.storage)?)?;
+        AUTO_COMPOUND_STORE.insert(deps.storage, &staker_raw, &true)?;
+    } else {
+        AUTO_COMPOUND_STORE.remove(deps.storage, &staker_raw)?;
+    }
+
+    Ok(Response::default())
+}
+
+fn try_claim_rewards(
+    deps: DepsMut,
+    _env: Env,
+    info_sender: &Addr,
+    recipient: &Addr,
+) -> Result<Response, ContractError> {
+    let mut state = CONFIG_ITEM.load(deps.storage)?;
+    let history_store = user_history_store(&deps.api.addr_canonicalize(info_sender.as_str())?);
+    let current_time = _env.block.time.seconds();
+    check_not_paused(deps.storage, PauseOperation::Claiming, current_time)?;
+    let mut staked = STAKED_STORE
+        .get(
+            deps.storage,
+            &deps.api.addr_canonicalize(&info_sender.to_string())?,
+        )
+        .ok_or_else(|| StdError::generic_err("You aren't staked"))?;
+
+    if staked.staked_amount == Uint128::from(0u128) {
+        return Err(ContractError::CustomError {
+            val: "There is nothing to claim".to_string(),
+        });
+    }
+
+    let mut response_msgs: Vec<CosmosMsg> = Vec::new();
+    let current_time = _env.block.time.seconds();
+    let accrual_time = get_accrual_time(deps.storage, current_time)?;
+    let rewards_to_claim = get_estimated_rewards(&staked, &accrual_time, &state)?;
+    if rewards_to_claim > Uint128::from(0u128) {
+        if state.total_rewards < rewards_to_claim {
+            return Err(ContractError::CustomError {
+                val: "Error trying to claim rewards".to_string(),
+            });
+        }
+        let claim_history: History = {
+            History {
+                amount: rewards_to_claim,
+                date: current_time,
+                action: HistoryAction::Claim,
+                reward_token: Some(state.reward_contract.address.clone()),
+                token_ids: None,
+            }
+        };
+
+        history_store.push(deps.storage, &claim_history)?;
+        response_msgs.extend(reward_msg(
+            deps.storage,
+            &deps.api.addr_canonicalize(recipient.as_str())?,
+            recipient,
+            rewards_to_claim,
+            &state.reward_contract.address,
+            &state.reward_contract.code_hash,
+            current_time,
+        )?);
+        staked.last_claimed_date = Some(accrual_time);
+        state.total_rewards -= rewards_to_claim;
+        STAKED_STORE.insert(
+            deps.storage,
+            &deps.api.addr_canonicalize(&info_sender.to_string())?,
+            &staked,
+        )?;
+        CONFIG_ITEM.save(deps.storage, &state)?;
+    } else {
+        //this technically should never happen
+        return Err(ContractError::CustomError {
+            val: "Not allowed to claim yet".to_string(),
+        });
+    }
+
+    Ok(Response::new().add_messages(response_msgs))
+}
+
+pub fn try_set_operator(
+    deps: DepsMut,
+    env: Env,
+    sender: &Addr,
+    operator: Operator,
+) -> Result<Response, ContractError> {
+    let address = deps.api.addr_validate(operator.address.as_str())?;
+    if address == *sender {
+        return Err(ContractError::CustomError {
+            val: "You can't be your own operator".to_string(),
+        });
+    }
+    if operator.expires.unwrap_or(u64::MAX) <= env.block.time.seconds() {
+        return Err(ContractError::CustomError {
+            val: "The operator would already be expired".to_string(),
+        });
+    }
+    let recipient = match operator.recipient {
+        Some(recipient) => Some(deps.api.addr_validate(recipient.as_str())?),
+        None => None,
+    };
+
+    let operator = Operator {
+        address,
+        recipient,
+        ..operator
+    };
+    OPERATORS_STORE.insert(
+        deps.storage,
+        &deps.api.addr_canonicalize(sender.as_str())?,
+        &operator,
+    )?;
+    Ok(Response::default())
+}
+
+pub fn try_revoke_operator(deps: DepsMut, sender: &Addr) -> Result<Response, ContractError> {
+    OPERATORS_STORE.remove(deps.storage, &deps.api.addr_canonicalize(sender.as_str())?)?;
+    Ok(Response::default())
+}
+
+/// The operator `staker` granted to `sender`, as long as it hasn't expired.
+fn get_operator(
+    deps: Deps,
+    env: &Env,
+    sender: &Addr,
+    staker: &Addr,
+) -> Result<Operator, ContractError> {
+    OPERATORS_STORE
+        .get(deps.storage, &deps.api.addr_canonicalize(staker.as_str())?)
+        .filter(|x| {
+            x.address == *sender && x.expires.unwrap_or(u64::MAX) > env.block.time.seconds()
+        })
+        .ok_or_else(|| ContractError::CustomError {
+            val: "You aren't an operator for this staker".to_string(),
+        })
+}
+
+/// Claims `staker`'s rewards for them, paid to the recipient they set for the operator.
+fn try_claim_rewards_for(
+    deps: DepsMut,
+    env: Env,
+    sender: &Addr,
+    staker: Addr,
+) -> Result<Response, ContractError> {
+    let staker = deps.api.addr_validate(staker.as_str())?;
+    let operator = get_operator(deps.as_ref(), &env, sender, &staker)?;
+    let recipient = operator.recipient.unwrap_or_else(|| staker.clone());
+    try_claim_rewards(deps, env, &staker, &recipient)
+}
+
+fn try_compound_for(
+    deps: DepsMut,
+    env: Env,
+    sender: &Addr,
+    staker: Addr,
+) -> Result<Response, ContractError> {
+    let staker = deps.api.addr_validate(staker.as_str())?;
+    let operator = get_operator(deps.as_ref(), &env, sender, &staker)?;
+    if operator.scope != OperatorScope::ClaimAndRestake {
+        return Err(ContractError::CustomError {
+            val: "The operator can only claim for this staker".to_string(),
+        });
+    }
+    try_compound(deps, env, &staker)
+}
+
+fn try_revoke_permit(
+    deps: DepsMut,
+    sender: &Addr,
+    permit_name: &str,
+) -> Result<Response, ContractError> {
+    RevokedPermits::revoke_permit(
+        deps.storage,
+        PREFIX_REVOKED_PERMITS,
+        &sender.to_string(),
+        permit_name,
+    );
+
+    Ok(Response::default())
+}
+
+fn try_queue_config_change(
+    deps: DepsMut,
+    env: Env,
+    sender: &Addr,
+    change: ConfigChange,
+) -> Result<Response, ContractError> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+    check_config_change_role(deps.as_ref(), &state, sender, &change)?;
+
+    let current_time = env.block.time.seconds();
+    let id = CONFIG_CHANGE_ID_ITEM
+        .may_load(deps.storage)?
+        .unwrap_or_default();
+    let eta = current_time + get_timelock_delay(deps.storage)?;
+    let pending_change = PendingConfigChange {
+        id,
+        change,
+        proposer: sender.clone(),
+        queued_time: current_time,
+        eta,
+    };
+    CONFIG_CHANGES_STORE.insert(deps.storage, &id, &pending_change)?;
+    CONFIG_CHANGE_ID_ITEM.save(deps.storage, &(id + 1))?;
+
+    Ok(Response::new()
+        .add_attribute("change_id", id.to_string())
+        .add_attribute("eta", eta.to_string()))
+}
+
+fn try_execute_config_change(
+    deps: DepsMut,
+    env: Env,
+    sender: &Addr,
+    id: u64,
+) -> Result<Response, ContractError> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+    let pending_change = get_pending_config_change(deps.storage, id)?;
+    check_config_change_role(deps.as_ref(), &state, sender, &pending_change.change)?;
+
+    if env.block.time.seconds() < pending_change.eta {
+        return Err(ContractError::CustomError {
+            val: format!(
+                "Config change {} is timelocked until {}",
+                id, pending_change.eta
+            ),
+        });
+    }
+
+    CONFIG_CHANGES_STORE.remove(deps.storage, &id)?;
+    let mut response_msgs: Vec<CosmosMsg> = Vec::new();
+    match pending_change.change {
+        ConfigChange::UpdateRewardContract { contract } => {
+            response_msgs = update_reward_contract(deps.storage, contract)?;
+        }
+        ConfigChange::SetTimelockDelay { delay } => {
+            TIMELOCK_ITEM.save(deps.storage, &delay)?;
+        }
+        ConfigChange::SetReceiptContract { contract } => {
+            let mut state = CONFIG_ITEM.load(deps.storage)?;
+            let redeemable = REDEEMABLE_ITEM.may_load(deps.storage)?.unwrap_or_default();
+            if !state.total_staked_amount.is_zero() || !redeemable.is_zero() {
+                return Err(ContractError::CustomError {
+                    val: "The receipt token can only be changed while nothing is staked"
+                        .to_string(),
+                });
+            }
+            state.receipt_contract = contract;
+            CONFIG_ITEM.save(deps.storage, &state)?;
+        }
+        ConfigChange::SetVesting { vesting } => match vesting {
+            Some(vesting) => VESTING_ITEM.save(deps.storage, &vesting)?,
+            None => VESTING_ITEM.remove(deps.storage),
+        },
+    }
+
+    Ok(Response::new().add_messages(response_msgs))
+}
+
+fn try_cancel_config_change(
+    deps: DepsMut,
+    sender: &Addr,
+    id: u64,
+) -> Result<Response, ContractError> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+    let pending_change = get_pending_config_change(deps.storage, id)?;
+    check_config_change_role(deps.as_ref(), &state, sender, &pending_change.change)?;
+
+    CONFIG_CHANGES_STORE.remove(deps.storage, &id)?;
+
+    Ok(Response::default())
+}
+
+fn get_pending_config_change(
+    storage: &dyn Storage,
+    id: u64,
+) -> Result<PendingConfigChange, ContractError> {
+    CONFIG_CHANGES_STORE
+        .get(storage, &id)
+        .ok_or_else(|| ContractError::CustomError {
+            val: format!("Config change {} not found", id),
+        })
+}
+
+/// Reward contract changes belong to the reward manager, the timelock delay
+/// itself can only be changed by the owner.
+fn check_config_change_role(
+    deps: Deps,
+    state: &State,
+    sender: &Addr,
+    change: &ConfigChange,
+) -> Result<(), ContractError> {
+    match change {
+        ConfigChange::UpdateRewardContract { .. } | ConfigChange::SetVesting { .. } => {
+            check_role(deps, state, sender, Role::RewardManager)
+        }
+        ConfigChange::SetTimelockDelay { .. } | ConfigChange::SetReceiptContract { .. } => {
+            if sender.clone() != state.owner {
+                return Err(ContractError::CustomError {
+                    val: "You don't have the permissions to execute this command".to_string(),
+                });
+            }
+            Ok(())
+        }
+    }
+}
+
+fn get_timelock_delay(storage: &dyn Storage) -> StdResult<u64> {
+    Ok(TIMELOCK_ITEM
+        .may_load(storage)?
+        .unwrap_or(DEFAULT_TIMELOCK_DELAY))
+}
+
+fn update_reward_contract(
+    storage: &mut dyn Storage,
+    contract: RewardsContractInfo,
+) -> Result<Vec<CosmosMsg>, ContractError> {
+    let mut state = CONFIG_ITEM.load(storage)?;
+
+    if state.total_rewards != Uint128::from(0u128) {
+        return Err(ContractError::CustomError {
+            val: "Clear out rewards first before updating".to_string(),
+        });
+    }
+
+    state.reward_contract = contract;
+    CONFIG_ITEM.save(storage, &state)?;
+    Ok(vec![set_viewing_key_msg(
+        state.viewing_key.unwrap().to_string(),
+        None,
+        BLOCK_SIZE,
         state.reward_contract.code_hash,
         state.reward_contract.address.to_string(),
-    )?))
+    )?])
+}
+
+fn try_remove_rewards(deps: DepsMut, sender: &Addr) -> Result<Response, ContractError> {
+    let mut state = CONFIG_ITEM.load(deps.storage)?;
+
+    if sender.clone() != state.owner {
+        return Err(ContractError::CustomError {
+            val: "You don't have the permissions to execute this command".to_string(),
+        });
+    }
+
+    let cosmos_msg = transfer_msg(
+        sender.to_string(),
+        state.total_rewards.clone(),
+        None,
+        None,
+        BLOCK_SIZE,
+        state.reward_contract.code_hash.to_string(),
+        state.reward_contract.address.to_string(),
+    )?;
+
+    state.total_rewards = Uint128::from(0u128);
+    CONFIG_ITEM.save(deps.storage, &state)?;
+    Ok(Response::new().add_message(cosmos_msg))
+}
+
+pub fn try_set_viewing_key(
+    deps: DepsMut,
+    _env: Env,
+    sender: &Addr,
+    key: String,
+) -> Result<Response, ContractError> {
+    let sender_raw = deps.api.addr_canonicalize(sender.as_str())?;
+    USER_VIEWING_KEYS_STORE.insert(deps.storage, &sender_raw, &hash_viewing_key(&key))?;
+
+    Ok(Response::default())
+}
+
+pub fn try_create_viewing_key(
+    deps: DepsMut,
+    env: Env,
+    sender: &Addr,
+    entropy: String,
+) -> Result<Response, ContractError> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+    let key = new_viewing_key(deps.storage, &env, sender, &entropy)?;
+    let viewing_key = hash_viewing_key(&key);
+
+    let sender_raw = deps.api.addr_canonicalize(sender.as_str())?;
+    USER_VIEWING_KEYS_STORE.insert(deps.storage, &sender_raw, &viewing_key)?;
+
+    // keys the owner creates also unlock the admin queries, oldest key is dropped at the limit
+    if sender.clone() == state.owner {
+        let mut admin_keys = ADMIN_VIEWING_KEYS_ITEM
+            .may_load(deps.storage)?
+            .unwrap_or_default();
+        if admin_keys.len() >= MAX_ADMIN_VIEWING_KEYS {
+            admin_keys.remove(0);
+        }
+        admin_keys.push(ViewerInfo {
+            address: sender.to_string(),
+            viewing_key,
+        });
+        ADMIN_VIEWING_KEYS_ITEM.save(deps.storage, &admin_keys)?;
+    }
+
+    Ok(Response::new().set_data(to_binary(&ViewingKeyResponse { key })?))
+}
+
+pub fn try_revoke_admin_viewing_keys(
+    deps: DepsMut,
+    sender: &Addr,
+) -> Result<Response, ContractError> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+
+    if sender.clone() != state.owner {
+        return Err(ContractError::CustomError {
+            val: "You don't have the permissions to execute this command".to_string(),
+        });
+    }
+
+    ADMIN_VIEWING_KEYS_ITEM.remove(deps.storage);
+
+    Ok(Response::default())
+}
+
+/// Draws a viewing key from `Prng` seeded with the stored seed, then rotates the seed.
+fn new_viewing_key(
+    storage: &mut dyn Storage,
+    env: &Env,
+    sender: &Addr,
+    entropy: &str,
+) -> StdResult<String> {
+    let prng_seed = PRNG_SEED_ITEM.may_load(storage)?.unwrap_or_default();
+    let mut rng_entropy = entropy.as_bytes().to_vec();
+    rng_entropy.extend_from_slice(&env.block.time.nanos().to_be_bytes());
+    rng_entropy.extend_from_slice(sender.as_bytes());
+    if let Some(random) = &env.block.random {
+        rng_entropy.extend_from_slice(random.as_slice());
+    }
+
+    let mut rng = Prng::new(&prng_seed, &rng_entropy);
+    let key = format!(
+        "{}{}",
+        VIEWING_KEY_PREFIX,
+        STANDARD.encode(rng.rand_bytes())
+    );
+    PRNG_SEED_ITEM.save(storage, &rng.rand_bytes().to_vec())?;
+
+    Ok(key)
+}
+
+/// Derives the initial `Prng` seed from `entropy` and the block.
+fn new_prng_seed(env: &Env, entropy: &[u8]) -> Vec<u8> {
+    let mut seed_data = entropy.to_vec();
+    seed_data.extend_from_slice(&env.block.time.nanos().to_be_bytes());
+    seed_data.extend_from_slice(env.contract.address.as_bytes());
+    if let Some(random) = &env.block.random {
+        seed_data.extend_from_slice(random.as_slice());
+    }
+    sha_256(&seed_data).to_vec()
+}
+
+/// Viewing keys are only ever stored hashed.
+fn hash_viewing_key(key: &str) -> String {
+    STANDARD.encode(sha_256(STANDARD.encode(key).as_bytes()))
+}
+
+pub fn try_set_active_state(
+    deps: DepsMut,
+    _env: Env,
+    sender: &Addr,
+    is_active: bool,
+) -> Result<Response, ContractError> {
+    let mut state = CONFIG_ITEM.load(deps.storage)?;
+
+    check_role(deps.as_ref(), &state, sender, Role::Pauser)?;
+
+    if is_active && SHUTDOWN_ITEM.may_load(deps.storage)?.is_some() {
+        return Err(ContractError::CustomError {
+            val: "The contract has been shut down".to_string(),
+        });
+    }
+
+    state.is_active = is_active;
+
+    CONFIG_ITEM.save(deps.storage, &state)?;
+
+    Ok(Response::default())
+}
+
+pub fn try_set_pause_state(
+    deps: DepsMut,
+    _env: Env,
+    sender: &Addr,
+    operation: PauseOperation,
+    is_paused: bool,
+    resume_time: Option<u64>,
+    reason: Option<String>,
+) -> Result<Response, ContractError> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+
+    check_role(deps.as_ref(), &state, sender, Role::Pauser)?;
+
+    let mut pause_flags = PAUSE_ITEM.may_load(deps.storage)?.unwrap_or_default();
+    let status = match operation {
+        PauseOperation::Staking => &mut pause_flags.staking,
+        PauseOperation::Claiming => &mut pause_flags.claiming,
+        PauseOperation::Withdrawing => &mut pause_flags.withdrawing,
+        PauseOperation::RewardFunding => &mut pause_flags.reward_funding,
+    };
+    *status = if is_paused {
+        PauseStatus {
+            is_paused,
+            resume_time,
+            reason,
+        }
+    } else {
+        PauseStatus::default()
+    };
+
+    PAUSE_ITEM.save(deps.storage, &pause_flags)?;
+
+    Ok(Response::default())
+}
+
+pub fn try_propose_owner(
+    deps: DepsMut,
+    sender: &Addr,
+    new_owner: Addr,
+) -> Result<Response, ContractError> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+
+    if sender.clone() != state.owner {
+        return Err(ContractError::CustomError {
+            val: "You don't have the permissions to execute this command".to_string(),
+        });
+    }
+
+    let new_owner = deps.api.addr_validate(new_owner.as_str())?;
+    PENDING_OWNER_ITEM.save(deps.storage, &new_owner)?;
+
+    Ok(Response::default())
+}
+
+pub fn try_accept_owner(deps: DepsMut, sender: &Addr) -> Result<Response, ContractError> {
+    let mut state = CONFIG_ITEM.load(deps.storage)?;
+
+    if PENDING_OWNER_ITEM.may_load(deps.storage)?.as_ref() != Some(sender) {
+        return Err(ContractError::CustomError {
+            val: "You are not the pending owner".to_string(),
+        });
+    }
+
+    state.owner = sender.clone();
+    CONFIG_ITEM.save(deps.storage, &state)?;
+    PENDING_OWNER_ITEM.remove(deps.storage);
+    // the admin viewing keys were created by the previous owner
+    ADMIN_VIEWING_KEYS_ITEM.remove(deps.storage);
+
+    Ok(Response::default())
+}
+
+pub fn try_set_role(
+    deps: DepsMut,
+    sender: &Addr,
+    address: Addr,
+    role: Role,
+    grant: bool,
+) -> Result<Response, ContractError> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+
+    if sender.clone() != state.owner {
+        return Err(ContractError::CustomError {
+            val: "You don't have the permissions to execute this command".to_string(),
+        });
+    }
+
+    let address_raw = deps.api.addr_canonicalize(address.as_str())?;
+    let mut roles = ROLES_STORE
+        .get(deps.storage, &address_raw)
+        .unwrap_or_default();
+    roles.retain(|x| *x != role);
+    if grant {
+        roles.push(role);
+    }
+
+    if roles.is_empty() {
+        ROLES_STORE.remove(deps.storage, &address_raw)?;
+    } else {
+        ROLES_STORE.insert(deps.storage, &address_raw, &roles)?;
+    }
+
+    Ok(Response::default())
+}
+
+/// Passes if `sender` is the owner or has been granted `role`.
+fn check_role(deps: Deps, state: &State, sender: &Addr, role: Role) -> Result<(), ContractError> {
+    if sender.clone() == state.owner {
+        return Ok(());
+    }
+
+    let sender_raw = deps.api.addr_canonicalize(sender.as_str())?;
+    let roles = ROLES_STORE
+        .get(deps.storage, &sender_raw)
+        .unwrap_or_default();
+    if !roles.contains(&role) {
+        return Err(ContractError::CustomError {
+            val: "You don't have the permissions to execute this command".to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+fn pause_status(
+    storage: &dyn Storage,
+    operation: PauseOperation,
+) -> StdResult<(&'static str, PauseStatus)> {
+    let pause_flags = PAUSE_ITEM.may_load(storage)?.unwrap_or_default();
+    Ok(match operation {
+        PauseOperation::Staking => ("Staking", pause_flags.staking),
+        PauseOperation::Claiming => ("Claiming", pause_flags.claiming),
+        PauseOperation::Withdrawing => ("Withdrawing", pause_flags.withdrawing),
+        PauseOperation::RewardFunding => ("Reward funding", pause_flags.reward_funding),
+    })
+}
+
+/// Whether `operation` is paused and its resume time, if any, hasn't been reached yet.
+fn is_paused(
+    storage: &dyn Storage,
+    operation: PauseOperation,
+    current_time: u64,
+) -> StdResult<bool> {
+    let (_, status) = pause_status(storage, operation)?;
+    Ok(status.is_paused && !matches!(status.resume_time, Some(x) if current_time >= x))
+}
+
+/// Errors if `operation` is paused and its resume time, if any, hasn't been reached yet.
+fn check_not_paused(
+    storage: &dyn Storage,
+    operation: PauseOperation,
+    current_time: u64,
+) -> Result<(), ContractError> {
+    if is_paused(storage, operation.clone(), current_time)? {
+        let (name, status) = pause_status(storage, operation)?;
+        return Err(ContractError::CustomError {
+            val: match status.reason {
+                Some(reason) => format!("{} is paused: {}", name, reason),
+                None => format!("{} is paused", name),
+            },
+        });
+    }
+
+    Ok(())
+}
+
+pub fn try_eject(
+    deps: DepsMut,
+    _env: Env,
+    sender: &Addr,
+    staker: &Addr,
+) -> Result<Response, ContractError> {
+    let mut state = CONFIG_ITEM.load(deps.storage)?;
+    if sender.clone() != state.owner {
+        return Err(ContractError::CustomError {
+            val: "You don't have the permissions to execute this command".to_string(),
+        });
+    }
+
+    let staker_raw = deps.api.addr_canonicalize(staker.as_str())?;
+    let staked = STAKED_STORE
+        .get(deps.storage, &staker_raw)
+        .ok_or_else(|| StdError::generic_err("Address isn't staked"))?;
+
+    if staked.staked_amount == Uint128::from(0u128) {
+        return Err(ContractError::CustomError {
+            val: "There is nothing to withdraw".to_string(),
+        });
+    }
+
+    let current_time = _env.block.time.seconds();
+    let response_msgs = return_stake(
+        deps.storage,
+        &mut state,
+        &staker_raw,
+        staker,
+        &staked,
+        current_time,
+        HistoryAction::Eject,
+    )?;
+    CONFIG_ITEM.save(deps.storage, &state)?;
+
+    Ok(Response::new().add_messages(response_msgs))
+}
+
+/// Stops new stakes and freezes reward accrual at the current block time so
+/// `ReturnStakes` can pay everyone back.
+pub fn try_emergency_shutdown(
+    deps: DepsMut,
+    _env: Env,
+    sender: &Addr,
+) -> Result<Response, ContractError> {
+    let mut state = CONFIG_ITEM.load(deps.storage)?;
+    if sender.clone() != state.owner {
+        return Err(ContractError::CustomError {
+            val: "You don't have the permissions to execute this command".to_string(),
+        });
+    }
+
+    if SHUTDOWN_ITEM.may_load(deps.storage)?.is_some() {
+        return Err(ContractError::CustomError {
+            val: "The contract has already been shut down".to_string(),
+        });
+    }
+
+    SHUTDOWN_ITEM.save(
+        deps.storage,
+        &Shutdown {
+            shutdown_time: _env.block.time.seconds(),
+            cursor: 0,
+        },
+    )?;
+    state.is_active = false;
+    CONFIG_ITEM.save(deps.storage, &state)?;
+
+    Ok(Response::default())
+}
+
+/// Pays principal plus rewards accrued up to the shutdown back to the next `limit`
+/// stakers. Anyone can act as the keeper here since funds only go to their owners.
+pub fn try_return_stakes(
+    deps: DepsMut,
+    _env: Env,
+    limit: Option<u32>,
+) -> Result<Response, ContractError> {
+    let mut state = CONFIG_ITEM.load(deps.storage)?;
+    let mut shutdown = SHUTDOWN_ITEM
+        .may_load(deps.storage)?
+        .ok_or_else(|| StdError::generic_err("The contract has not been shut down"))?;
+
+    if shutdown.cursor >= STAKED_STORE.get_len(deps.storage)? {
+        return Err(ContractError::CustomError {
+            val: "All stakes have already been returned".to_string(),
+        });
+    }
+
+    let limit = limit.unwrap_or(DEFAULT_RETURN_LIMIT).min(MAX_RETURN_LIMIT);
+    let stakers = STAKED_STORE
+        .iter(deps.storage)?
+        .skip(shutdown.cursor as usize)
+        .take(limit as usize)
+        .collect::<StdResult<Vec<(CanonicalAddr, Staked)>>>()?;
+
+    let current_time = _env.block.time.seconds();
+    let mut response_msgs: Vec<CosmosMsg> = Vec::new();
+    for (staker_raw, staked) in stakers.iter() {
+        if staked.staked_amount > Uint128::from(0u128) {
+            let staker = deps.api.addr_humanize(staker_raw)?;
+            response_msgs.extend(return_stake(
+                deps.storage,
+                &mut state,
+                staker_raw,
+                &staker,
+                staked,
+                current_time,
+                HistoryAction::Withdraw,
+            )?);
+        }
+    }
+
+    shutdown.cursor += stakers.len() as u32;
+    SHUTDOWN_ITEM.save(deps.storage, &shutdown)?;
+    CONFIG_ITEM.save(deps.storage, &state)?;
+
+    Ok(Response::new().add_messages(response_msgs))
+}
+
+/// Sends a staker their principal and accrued rewards and clears their position.
+/// With a receipt token the principal stays in the pool for whoever redeems the
+/// receipts. The entry is zeroed rather than removed so `STAKED_STORE` indexes
+/// stay stable.
+fn return_stake(
+    storage: &mut dyn Storage,
+    state: &mut State,
+    staker_raw: &CanonicalAddr,
+    staker: &Addr,
+    staked: &Staked,
+    current_time: u64,
+    action: HistoryAction,
+) -> StdResult<Vec<CosmosMsg>> {
+    let history_store = user_history_store(staker_raw);
+    let mut response_msgs: Vec<CosmosMsg> = Vec::new();
+    if state.receipt_contract.is_some() {
+        let redeemable = REDEEMABLE_ITEM.may_load(storage)?.unwrap_or_default();
+        REDEEMABLE_ITEM.save(storage, &(redeemable + staked.staked_amount))?;
+    } else {
+        response_msgs.push(transfer_msg(
+            staker.to_string(),
+            staked.staked_amount,
+            None,
+            None,
+            BLOCK_SIZE,
+            state.staking_contract.code_hash.to_string(),
+            state.staking_contract.address.to_string(),
+        )?);
+    }
+
+    let accrual_time = get_accrual_time(storage, current_time)?;
+    let rewards_to_claim =
+        get_estimated_rewards(staked, &accrual_time, state)?.min(state.total_rewards);
+    if rewards_to_claim > Uint128::from(0u128) {
+        let claim_history: History = {
+            History {
+                amount: rewards_to_claim,
+                date: current_time,
+                action: HistoryAction::Claim,
+                reward_token: Some(state.reward_contract.address.clone()),
+                token_ids: None,
+            }
+        };
+
+        history_store.push(storage, &claim_history)?;
+        response_msgs.extend(reward_msg(
+            storage,
+            staker_raw,
+            staker,
+            rewards_to_claim,
+            &state.reward_contract.address,
+            &state.reward_contract.code_hash,
+            current_time,
+        )?);
+        state.total_rewards -= rewards_to_claim;
+    }
+
+    settle_dividends(
+        storage,
+        staker_raw,
+        staked.staked_amount,
+        state.total_staked_amount,
+        current_time,
+    )?;
+    state.total_staked_amount -= staked.staked_amount;
+    STAKED_STORE.insert(
+        storage,
+        staker_raw,
+        &Staked {
+            last_claimed_date: None,
+            staked_amount: Uint128::from(0u128),
+            last_staked_date: None,
+        },
+    )?;
+    checkpoint_stake(
+        storage,
+        staker_raw,
+        Uint128::zero(),
+        state.total_staked_amount,
+        current_time,
+    )?;
+
+    let stake_history: History = {
+        History {
+            amount: staked.staked_amount,
+            date: current_time,
+            action,
+            reward_token: None,
+            token_ids: None,
+        }
+    };
+    history_store.push(storage, &stake_history)?;
+
+    Ok(response_msgs)
+}
+
+/// Rewards stop accruing once the contract has been shut down.
+fn get_accrual_time(storage: &dyn Storage, current_time: u64) -> StdResult<u64> {
+    Ok(match SHUTDOWN_ITEM.may_load(storage)? {
+        Some(shutdown) => current_time.min(shutdown.shutdown_time),
+        None => current_time,
+    })
+}
+
+fn get_estimated_rewards(staked: &Staked, current_time: &u64, state: &State) -> StdResult<Uint128> {
+    let mut estimated_rewards = Uint128::from(0u128);
+    if staked.staked_amount > Uint128::from(0u128)
+        && state.total_staked_amount > Uint128::from(0u128)
+    {
+        let date = if staked.last_claimed_date.is_some() {
+            staked.last_claimed_date.unwrap()
+        } else {
+            staked.last_staked_date.unwrap()
+        };
+
+        let user_reward_percentage =
+            Decimal::from_ratio(staked.staked_amount, state.total_staked_amount);
+        let elapsed_seconds = current_time.saturating_sub(date);
+        let rewards_per_second =
+            Decimal::from_ratio(state.reward_contract.rewards_per_day, 24u64 * 60u64 * 60u64);
+        let est_rewards = user_reward_percentage
+            * rewards_per_second
+            * Decimal::from_atomics(elapsed_seconds, 0).unwrap();
+
+        let without_decimals = Decimal::from(
+            est_rewards * Decimal::from_ratio(1u128, 10u128.pow(est_rewards.decimal_places())),
+        );
+        estimated_rewards = without_decimals.atomics();
+    }
+
+    return Ok(estimated_rewards);
+}
+
+/// Raffle prizes are held next to the pool's own funds, so tokens the pool pays
+/// out in any other way can't be raffled.
+fn check_raffle_token(
+    storage: &dyn Storage,
+    state: &State,
+    token: &Addr,
+) -> Result<(), ContractError> {
+    let is_pool_token = *token == state.staking_contract.address
+        || *token == state.reward_contract.address
+        || DIVIDEND_POOLS_ITEM
+            .may_load(storage)?
+            .unwrap_or_default()
+            .iter()
+            .any(|x| x.reward_token == *token)
+        || INCENTIVES_ITEM
+            .may_load(storage)?
+            .unwrap_or_default()
+            .iter()
+            .any(|x| x.token == *token)
+        || VESTING_TOTALS_ITEM
+            .may_load(storage)?
+            .unwrap_or_default()
+            .iter()
+            .any(|x| x.reward_token == *token);
+    if is_pool_token {
+        return Err(ContractError::CustomError {
+            val: "Staked, reward, dividend, incentive and vesting tokens can't be raffled"
+                .to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn deposit_raffle_tokens(
+    storage: &mut dyn Storage,
+    state: &State,
+    token: &Addr,
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    check_raffle_token(storage, state, token)?;
+    let deposited = RAFFLE_TOKEN_PRIZES_STORE
+        .get(storage, token)
+        .unwrap_or_default();
+    RAFFLE_TOKEN_PRIZES_STORE.insert(storage, token, &(deposited + amount))?;
+    Ok(())
+}
+
+fn deposit_raffle_nfts(
+    storage: &mut dyn Storage,
+    state: &State,
+    collection: &Addr,
+    token_ids: Vec<String>,
+) -> Result<(), ContractError> {
+    check_raffle_token(storage, state, collection)?;
+    let mut deposited = RAFFLE_NFT_PRIZES_STORE
+        .get(storage, collection)
+        .unwrap_or_default();
+    deposited.extend(token_ids);
+    RAFFLE_NFT_PRIZES_STORE.insert(storage, collection, &deposited)?;
+    Ok(())
+}
+
+/// Takes `prize` out of the deposited raffle prizes, failing if it wasn't deposited.
+fn take_raffle_prize(storage: &mut dyn Storage, prize: &RafflePrize) -> Result<(), ContractError> {
+    match prize {
+        RafflePrize::Token {
+            address,
+            amount,
+            winners,
+            ..
+        } => {
+            let deposited = RAFFLE_TOKEN_PRIZES_STORE
+                .get(storage, address)
+                .unwrap_or_default();
+            let total = amount
+                .checked_mul(Uint128::from(*winners))
+                .map_err(StdError::from)?;
+            if total > deposited {
+                return Err(ContractError::CustomError {
+                    val: format!(
+                        "Only {} of the {} prize has been deposited",
+                        deposited, total
+                    ),
+                });
+            }
+            RAFFLE_TOKEN_PRIZES_STORE.insert(storage, address, &(deposited - total))?;
+        }
+        RafflePrize::Nft {
+            address, token_ids, ..
+        } => {
+            let mut deposited = RAFFLE_NFT_PRIZES_STORE
+                .get(storage, address)
+                .unwrap_or_default();
+            for token_id in token_ids.iter() {
+                let index = deposited
+                    .iter()
+                    .position(|x| x == token_id)
+                    .ok_or_else(|| ContractError::CustomError {
+                        val: format!("Token {} hasn't been deposited as a prize", token_id),
+                    })?;
+                deposited.swap_remove(index);
+            }
+            RAFFLE_NFT_PRIZES_STORE.insert(storage, address, &deposited)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn try_run_raffle(
+    deps: DepsMut,
+    env: Env,
+    sender: &Addr,
+    prize: RafflePrize,
+) -> Result<Response, ContractError> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+    check_role(deps.as_ref(), &state, sender, Role::RewardManager)?;
+
+    let (prize_address, num_winners) = match &prize {
+        RafflePrize::Token {
+            address, winners, ..
+        } => (address, *winners as usize),
+        RafflePrize::Nft {
+            address, token_ids, ..
+        } => (address, token_ids.len()),
+    };
+    check_raffle_token(deps.storage, &state, prize_address)?;
+    take_raffle_prize(deps.storage, &prize)?;
+
+    let mut entries: Vec<(CanonicalAddr, u128)> = Vec::new();
+    for item in STAKED_STORE.iter(deps.storage)? {
+        let (staker_raw, staked) = item?;
+        if !staked.staked_amount.is_zero() {
+            entries.push((staker_raw, staked.staked_amount.u128()));
+        }
+    }
+    if num_winners == 0 || entries.len() < num_winners {
+        return Err(ContractError::CustomError {
+            val: format!("Not enough stakers to draw {} winners", num_winners),
+        });
+    }
+
+    let prng_seed = PRNG_SEED_ITEM.may_load(deps.storage)?.unwrap_or_default();
+    let mut rng_entropy = env.block.time.nanos().to_be_bytes().to_vec();
+    if let Some(random) = &env.block.random {
+        rng_entropy.extend_from_slice(random.as_slice());
+    }
+    let mut rng = Prng::new(&prng_seed, &rng_entropy);
+    let winners_raw = draw_winners(&mut rng, entries, num_winners);
+    PRNG_SEED_ITEM.save(deps.storage, &rng.rand_bytes().to_vec())?;
+
+    let current_time = env.block.time.seconds();
+    let mut winners: Vec<Addr> = Vec::new();
+    let mut response_msgs: Vec<CosmosMsg> = Vec::new();
+    let mut transfers: Vec<Transfer> = Vec::new();
+    for (index, winner_raw) in winners_raw.iter().enumerate() {
+        let winner = deps.api.addr_humanize(winner_raw)?;
+        let win_history = match &prize {
+            RafflePrize::Token {
+                address,
+                code_hash,
+                amount,
+                ..
+            } => {
+                response_msgs.push(transfer_msg(
+                    winner.to_string(),
+                    *amount,
+                    None,
+                    None,
+                    BLOCK_SIZE,
+                    code_hash.to_string(),
+                    address.to_string(),
+                )?);
+                History {
+                    amount: *amount,
+                    date: current_time,
+                    action: HistoryAction::RaffleWin,
+                    reward_token: Some(address.clone()),
+                    token_ids: None,
+                }
+            }
+            RafflePrize::Nft {
+                address, token_ids, ..
+            } => {
+                transfers.push(Transfer {
+                    recipient: winner.to_string(),
+                    token_ids: vec![token_ids[index].clone()],
+                    memo: None,
+                });
+                History {
+                    amount: Uint128::from(1u128),
+                    date: current_time,
+                    action: HistoryAction::RaffleWin,
+                    reward_token: Some(address.clone()),
+                    token_ids: Some(vec![token_ids[index].clone()]),
+                }
+            }
+        };
+        user_history_store(winner_raw).push(deps.storage, &win_history)?;
+        winners.push(winner);
+    }
+    if let RafflePrize::Nft {
+        address, code_hash, ..
+    } = &prize
+    {
+        response_msgs.push(batch_transfer_nft_msg(
+            transfers,
+            None,
+            BLOCK_SIZE,
+            code_hash.to_string(),
+            address.to_string(),
+        )?);
+    }
+
+    let raffle = Raffle {
+        prize,
+        winners,
+        date: current_time,
+    };
+    RAFFLES_STORE.push(deps.storage, &raffle)?;
+
+    Ok(Response::new().add_messages(response_msgs))
+}
+
+/// Draws `count` distinct stakers, each with odds proportional to their weight.
+fn draw_winners(
+    rng: &mut Prng,
+    mut entries: Vec<(CanonicalAddr, u128)>,
+    count: usize,
+) -> Vec<CanonicalAddr> {
+    let mut total: u128 = entries.iter().map(|x| x.1).sum();
+    let mut winners: Vec<CanonicalAddr> = Vec::new();
+    while winners.len() < count && total > 0 {
+        let mut bytes = [0u8; 16];
+        bytes.copy_from_slice(&rng.rand_bytes()[..16]);
+        let mut pick = u128::from_be_bytes(bytes) % total;
+        let index = entries
+            .iter()
+            .position(|(_, weight)| {
+                if pick < *weight {
+                    return true;
+                }
+                pick -= *weight;
+                false
+            })
+            .unwrap_or(entries.len() - 1);
+        let (winner_raw, weight) = entries.swap_remove(index);
+        total -= weight;
+        winners.push(winner_raw);
+    }
+    winners
+}
+
+fn try_claim_dividends(deps: DepsMut, env: Env, sender: &Addr) -> Result<Response, ContractError> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+    let current_time = env.block.time.seconds();
+    check_not_paused(deps.storage, PauseOperation::Claiming, current_time)?;
+    let sender_raw = deps.api.addr_canonicalize(sender.as_str())?;
+    let staked_amount = STAKED_STORE
+        .get(deps.storage, &sender_raw)
+        .map_or(Uint128::zero(), |x| x.staked_amount);
+
+    let response_msgs = pay_dividends(
+        deps.storage,
+        &sender_raw,
+        sender,
+        staked_amount,
+        state.total_staked_amount,
+        current_time,
+    )?;
+    if response_msgs.is_empty() {
+        return Err(ContractError::CustomError {
+            val: "There are no dividends to claim".to_string(),
+        });
+    }
+
+    Ok(Response::new().add_messages(response_msgs))
+}
+
+/// Splits `amount` between everything currently staked by bumping the reward
+/// token's per-share accumulator, so no staker has to be visited.
+fn add_dividend(
+    storage: &mut dyn Storage,
+    total_staked_amount: Uint128,
+    reward_token: &Addr,
+    code_hash: &str,
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    if total_staked_amount.is_zero() {
+        return Err(ContractError::CustomError {
+            val: "Nothing is staked to pay dividends to".to_string(),
+        });
+    }
+
+    let mut pools = DIVIDEND_POOLS_ITEM.may_load(storage)?.unwrap_or_default();
+    add_to_pool(
+        &mut pools,
+        reward_token,
+        code_hash,
+        amount,
+        total_staked_amount,
+    );
+    DIVIDEND_POOLS_ITEM.save(storage, &pools)?;
+    Ok(())
+}
+
+fn add_to_pool(
+    pools: &mut Vec<DividendPool>,
+    reward_token: &Addr,
+    code_hash: &str,
+    amount: Uint128,
+    total_staked_amount: Uint128,
+) {
+    let index = match pools.iter().position(|x| x.reward_token == *reward_token) {
+        Some(index) => index,
+        None => {
+            pools.push(DividendPool {
+                reward_token: reward_token.clone(),
+                code_hash: code_hash.to_string(),
+                acc_per_share: Uint128::zero(),
+            });
+            pools.len() - 1
+        }
+    };
+    pools[index].acc_per_share += amount.multiply_ratio(DIVIDEND_SCALE, total_staked_amount);
+}
+
+/// Dividend pools with every sponsor incentive streamed in up to `current_time`.
+/// Time with nothing staked is set aside for the sponsor to take back.
+fn get_dripped_pools(
+    storage: &dyn Storage,
+    total_staked_amount: Uint128,
+    current_time: u64,
+) -> StdResult<(Vec<DividendPool>, Vec<Incentive>)> {
+    let mut pools = DIVIDEND_POOLS_ITEM.may_load(storage)?.unwrap_or_default();
+    let mut incentives = INCENTIVES_ITEM.may_load(storage)?.unwrap_or_default();
+    for incentive in incentives.iter_mut() {
+        let from = incentive.last_update.max(incentive.start);
+        let to = current_time.min(incentive.end);
+        if to <= from {
+            continue;
+        }
+        // the last stretch takes whatever rounding left over
+        let due = if to == incentive.end {
+            incentive.amount - incentive.distributed - incentive.unused
+        } else {
+            incentive
+                .amount
+                .multiply_ratio(to - from, incentive.end - incentive.start)
+        };
+        incentive.last_update = to;
+        if total_staked_amount.is_zero() {
+            incentive.unused += due;
+        } else {
+            add_to_pool(
+                &mut pools,
+                &incentive.token,
+                &incentive.code_hash,
+                due,
+                total_staked_amount,
+            );
+            incentive.distributed += due;
+        }
+    }
+    // nothing is left to stream or refund
+    incentives.retain(|x| x.last_update < x.end || !x.unused.is_zero());
+    Ok((pools, incentives))
+}
+
+/// Has to run before the pool's total staked amount changes.
+fn drip_incentives(
+    storage: &mut dyn Storage,
+    total_staked_amount: Uint128,
+    current_time: u64,
+) -> StdResult<Vec<DividendPool>> {
+    let (pools, incentives) = get_dripped_pools(storage, total_staked_amount, current_time)?;
+    if INCENTIVES_ITEM.may_load(storage)?.is_some() {
+        DIVIDEND_POOLS_ITEM.save(storage, &pools)?;
+        INCENTIVES_ITEM.save(storage, &incentives)?;
+    }
+    Ok(pools)
+}
+
+/// A staker's dividends brought up to date for `staked_amount`. A staker without a
+/// record for a pool has been staked since before its first dividend, as every
+/// stake change settles first.
+fn get_user_dividends(
+    storage: &dyn Storage,
+    pools: &[DividendPool],
+    staker_raw: &CanonicalAddr,
+    staked_amount: Uint128,
+) -> StdResult<Vec<UserDividend>> {
+    let mut user_dividends = USER_DIVIDENDS_STORE
+        .get(storage, staker_raw)
+        .unwrap_or_default();
+    for pool in pools.iter() {
+        let index = match user_dividends
+            .iter()
+            .position(|x| x.reward_token == pool.reward_token)
+        {
+            Some(index) => index,
+            None => {
+                user_dividends.push(UserDividend {
+                    reward_token: pool.reward_token.clone(),
+                    paid_per_share: Uint128::zero(),
+                    owed: Uint128::zero(),
+                });
+                user_dividends.len() - 1
+            }
+        };
+        let user_dividend = &mut user_dividends[index];
+        user_dividend.owed += staked_amount.multiply_ratio(
+            pool.acc_per_share - user_dividend.paid_per_share,
+            DIVIDEND_SCALE,
+        );
+        user_dividend.paid_per_share = pool.acc_per_share;
+    }
+    Ok(user_dividends)
+}
+
+/// Has to run before a staker's staked amount changes.
+fn settle_dividends(
+    storage: &mut dyn Storage,
+    staker_raw: &CanonicalAddr,
+    staked_amount: Uint128,
+    total_staked_amount: Uint128,
+    current_time: u64,
+) -> StdResult<()> {
+    let pools = drip_incentives(storage, total_staked_amount, current_time)?;
+    let user_dividends = get_user_dividends(storage, &pools, staker_raw, staked_amount)?;
+    if !user_dividends.is_empty() {
+        USER_DIVIDENDS_STORE.insert(storage, staker_raw, &user_dividends)?;
+    }
+    Ok(())
+}
+
+/// Settles and pays out everything a staker is owed in dividends. Only claims pay
+/// out, stake changes just settle so a sponsor token can't block a withdrawal.
+fn pay_dividends(
+    storage: &mut dyn Storage,
+    staker_raw: &CanonicalAddr,
+    staker: &Addr,
+    staked_amount: Uint128,
+    total_staked_amount: Uint128,
+    current_time: u64,
+) -> StdResult<Vec<CosmosMsg>> {
+    let pools = drip_incentives(storage, total_staked_amount, current_time)?;
+    let mut user_dividends = get_user_dividends(storage, &pools, staker_raw, staked_amount)?;
+    let history_store = user_history_store(staker_raw);
+    let mut response_msgs: Vec<CosmosMsg> = Vec::new();
+    for user_dividend in user_dividends.iter_mut() {
+        let pool = pools
+            .iter()
+            .find(|x| x.reward_token == user_dividend.reward_token);
+        if let (Some(pool), false) = (pool, user_dividend.owed.is_zero()) {
+            response_msgs.push(transfer_msg(
+                staker.to_string(),
+                user_dividend.owed,
+                None,
+                None,
+                BLOCK_SIZE,
+                pool.code_hash.to_string(),
+                pool.reward_token.to_string(),
+            )?);
+            let dividend_history = History {
+                amount: user_dividend.owed,
+                date: current_time,
+                action: HistoryAction::Dividend,
+                reward_token: Some(pool.reward_token.clone()),
+                token_ids: None,
+            };
+            history_store.push(storage, &dividend_history)?;
+            user_dividend.owed = Uint128::zero();
+        }
+    }
+    if !user_dividends.is_empty() {
+        USER_DIVIDENDS_STORE.insert(storage, staker_raw, &user_dividends)?;
+    }
+    Ok(response_msgs)
+}
+
+pub fn try_set_sponsor_list(
+    deps: DepsMut,
+    sender: &Addr,
+    list: SponsorList,
+) -> Result<Response, ContractError> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+    check_role(deps.as_ref(), &state, sender, Role::RewardManager)?;
+    SPONSOR_LIST_ITEM.save(deps.storage, &list)?;
+    Ok(Response::default())
 }
 
-fn try_remove_rewards(deps: DepsMut, sender: &Addr) -> Result<Response, ContractError> {
-    let mut state = CONFIG_ITEM.load(deps.storage)?;
+/// Anyone who isn't denied can sponsor the pool by sending an allowed SNIP-20 with a
+/// schedule, which is then streamed to stakers between `start` and `end`. The token is
+/// paid out with the code hash it was allowed with.
+fn add_incentive(
+    storage: &mut dyn Storage,
+    token: &Addr,
+    sponsor: &Addr,
+    amount: Uint128,
+    start: u64,
+    end: u64,
+    current_time: u64,
+) -> Result<(), ContractError> {
+    let list = SPONSOR_LIST_ITEM.may_load(storage)?.unwrap_or_default();
+    if list.denied.contains(sponsor) {
+        return Err(ContractError::CustomError {
+            val: "This address can't sponsor the pool".to_string(),
+        });
+    }
+    let sponsor_token = list
+        .allowed
+        .into_iter()
+        .find(|x| x.address == *token)
+        .ok_or_else(|| ContractError::CustomError {
+            val: "This token can't sponsor the pool".to_string(),
+        })?;
+    if amount.is_zero() || end <= start {
+        return Err(ContractError::CustomError {
+            val: "An incentive needs an amount and has to end after it starts".to_string(),
+        });
+    }
+    // a past start would hand out the elapsed share all at once
+    if start < current_time {
+        return Err(ContractError::CustomError {
+            val: "An incentive can't start in the past".to_string(),
+        });
+    }
 
-    if sender.clone() != state.owner {
+    let mut incentives = INCENTIVES_ITEM.may_load(storage)?.unwrap_or_default();
+    if incentives.iter().filter(|x| x.end > current_time).count() >= MAX_ACTIVE_INCENTIVES {
+        return Err(ContractError::CustomError {
+            val: format!("Max of {} active incentives allowed", MAX_ACTIVE_INCENTIVES),
+        });
+    }
+    let id = INCENTIVE_ID_ITEM.may_load(storage)?.unwrap_or_default();
+    INCENTIVE_ID_ITEM.save(storage, &(id + 1))?;
+    incentives.push(Incentive {
+        id,
+        sponsor: sponsor.clone(),
+        token: token.clone(),
+        code_hash: sponsor_token.code_hash,
+        amount,
+        start,
+        end,
+        last_update: start,
+        distributed: Uint128::zero(),
+        unused: Uint128::zero(),
+    });
+    INCENTIVES_ITEM.save(storage, &incentives)?;
+    Ok(())
+}
+
+/// Returns the part of an ended incentive that streamed while nothing was staked.
+pub fn try_refund_incentive(
+    deps: DepsMut,
+    env: Env,
+    sender: &Addr,
+    id: u64,
+) -> Result<Response, ContractError> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+    let current_time = env.block.time.seconds();
+    drip_incentives(deps.storage, state.total_staked_amount, current_time)?;
+
+    let mut incentives = INCENTIVES_ITEM.may_load(deps.storage)?.unwrap_or_default();
+    let index = incentives
+        .iter()
+        .position(|x| x.id == id)
+        .ok_or_else(|| StdError::generic_err("Incentive not found or nothing left to refund"))?;
+    if incentives[index].sponsor != *sender {
         return Err(ContractError::CustomError {
             val: "You don't have the permissions to execute this command".to_string(),
         });
     }
+    if current_time < incentives[index].end {
+        return Err(ContractError::CustomError {
+            val: "The incentive hasn't ended yet".to_string(),
+        });
+    }
 
+    let incentive = incentives.remove(index);
+    INCENTIVES_ITEM.save(deps.storage, &incentives)?;
     let cosmos_msg = transfer_msg(
         sender.to_string(),
-        state.total_rewards.clone(),
+        incentive.unused,
         None,
         None,
         BLOCK_SIZE,
-        state.reward_contract.code_hash.to_string(),
-        state.reward_contract.address.to_string(),
+        incentive.code_hash,
+        incentive.token.to_string(),
     )?;
-
-    state.total_rewards = Uint128::from(0u128);
-    CONFIG_ITEM.save(deps.storage, &state)?;
     Ok(Response::new().add_message(cosmos_msg))
 }
 
-pub fn try_set_viewing_key(
+fn try_set_vesting(
     deps: DepsMut,
-    _env: Env,
+    env: Env,
     sender: &Addr,
-    key: String,
+    vesting: Option<VestingConfig>,
 ) -> Result<Response, ContractError> {
-    let state = CONFIG_ITEM.load(deps.storage)?;
-    let prng_seed: Vec<u8> = sha_256(base64::encode(key).as_bytes()).to_vec();
-    let viewing_key = base64::encode(&prng_seed);
+    if let Some(vesting) = &vesting {
+        if vesting.duration == 0 || vesting.cliff > vesting.duration {
+            return Err(ContractError::CustomError {
+                val: "Vesting needs a duration and a cliff no longer than it".to_string(),
+            });
+        }
+    }
 
-    let vk: ViewerInfo = {
-        ViewerInfo {
-            address: sender.to_string(),
-            viewing_key: viewing_key,
+    try_queue_config_change(deps, env, sender, ConfigChange::SetVesting { vesting })
+}
+
+/// Pays out claimed rewards, or locks them in a new vesting schedule while vesting is on.
+fn reward_msg(
+    storage: &mut dyn Storage,
+    staker_raw: &CanonicalAddr,
+    staker: &Addr,
+    amount: Uint128,
+    reward_token: &Addr,
+    code_hash: &str,
+    current_time: u64,
+) -> StdResult<Option<CosmosMsg>> {
+    // while claims are paused the reward is held as an already vested schedule,
+    // which WithdrawVested pays out once claims resume
+    let vesting = match VESTING_ITEM.may_load(storage)? {
+        Some(vesting) => vesting,
+        None if is_paused(storage, PauseOperation::Claiming, current_time)? => VestingConfig {
+            duration: 0,
+            cliff: 0,
+        },
+        None => {
+            return Ok(Some(transfer_msg(
+                staker.to_string(),
+                amount,
+                None,
+                None,
+                BLOCK_SIZE,
+                code_hash.to_string(),
+                reward_token.to_string(),
+            )?));
         }
     };
 
-    if sender.clone() == state.owner {
-        ADMIN_VIEWING_KEY_ITEM.save(deps.storage, &vk)?;
-    } else {
-        return Err(ContractError::CustomError {
-            val: "You don't have the permissions to execute this command".to_string(),
-        });
-    }
-    Ok(Response::default())
+    let mut schedules = USER_VESTING_STORE
+        .get(storage, staker_raw)
+        .unwrap_or_default();
+    schedules.push(VestingSchedule {
+        reward_token: reward_token.clone(),
+        code_hash: code_hash.to_string(),
+        amount,
+        released: Uint128::zero(),
+        start: current_time,
+        cliff: current_time + vesting.cliff,
+        end: current_time + vesting.duration,
+    });
+    USER_VESTING_STORE.insert(storage, staker_raw, &schedules)?;
+    update_vesting_totals(storage, reward_token, amount, Uint128::zero())?;
+    Ok(None)
 }
 
-pub fn try_set_active_state(
-    deps: DepsMut,
-    _env: Env,
-    sender: &Addr,
-    is_active: bool,
-) -> Result<Response, ContractError> {
-    let mut state = CONFIG_ITEM.load(deps.storage)?;
-
-    state.is_active = is_active;
+/// Moves `released` of a reward token from locked to released after adding
+/// `locked` newly claimed rewards.
+fn update_vesting_totals(
+    storage: &mut dyn Storage,
+    reward_token: &Addr,
+    locked: Uint128,
+    released: Uint128,
+) -> StdResult<()> {
+    let mut totals = VESTING_TOTALS_ITEM.may_load(storage)?.unwrap_or_default();
+    let index = match totals.iter().position(|x| x.reward_token == *reward_token) {
+        Some(index) => index,
+        None => {
+            totals.push(VestingTotal {
+                reward_token: reward_token.clone(),
+                locked: Uint128::zero(),
+                released: Uint128::zero(),
+            });
+            totals.len() - 1
+        }
+    };
+    totals[index].locked = totals[index].locked + locked - released;
+    totals[index].released += released;
+    VESTING_TOTALS_ITEM.save(storage, &totals)
+}
 
-    CONFIG_ITEM.save(deps.storage, &state)?;
+/// Part of `schedule` vested by `current_time`, whether released yet or not.
+fn vested_amount(schedule: &VestingSchedule, current_time: u64) -> Uint128 {
+    if current_time < schedule.cliff {
+        return Uint128::zero();
+    }
+    if current_time >= schedule.end {
+        return schedule.amount;
+    }
+    schedule
+        .amount
+        .multiply_ratio(current_time - schedule.start, schedule.end - schedule.start)
+}
 
-    Ok(Response::default())
+/// Vested amounts of `schedules` that haven't been released yet, per reward token.
+fn get_withdrawable_vesting(schedules: &[VestingSchedule], current_time: u64) -> Vec<VestedReward> {
+    let mut withdrawable: Vec<VestedReward> = Vec::new();
+    for schedule in schedules.iter() {
+        let amount = vested_amount(schedule, current_time) - schedule.released;
+        if amount.is_zero() {
+            continue;
+        }
+        match withdrawable
+            .iter_mut()
+            .find(|x| x.reward_token == schedule.reward_token)
+        {
+            Some(vested) => vested.amount += amount,
+            None => withdrawable.push(VestedReward {
+                reward_token: schedule.reward_token.clone(),
+                amount,
+            }),
+        }
+    }
+    withdrawable
 }
 
-fn get_estimated_rewards(staked: &Staked, current_time: &u64, state: &State) -> StdResult<Uint128> {
-    let mut estimated_rewards = Uint128::from(0u128);
-    if staked.staked_amount > Uint128::from(0u128)
-        && state.total_staked_amount > Uint128::from(0u128)
-    {
-        let date = if staked.last_claimed_date.is_some() {
-            staked.last_claimed_date.unwrap()
-        } else {
-            staked.last_staked_date.unwrap()
+fn try_withdraw_vested(deps: DepsMut, env: Env, sender: &Addr) -> Result<Response, ContractError> {
+    let current_time = env.block.time.seconds();
+    check_not_paused(deps.storage, PauseOperation::Claiming, current_time)?;
+    let sender_raw = deps.api.addr_canonicalize(sender.as_str())?;
+    let mut schedules = USER_VESTING_STORE
+        .get(deps.storage, &sender_raw)
+        .unwrap_or_default();
+
+    let withdrawable = get_withdrawable_vesting(&schedules, current_time);
+    if withdrawable.is_empty() {
+        return Err(ContractError::CustomError {
+            val: "There are no vested rewards to withdraw".to_string(),
+        });
+    }
+
+    let history_store = user_history_store(&sender_raw);
+    let mut response_msgs: Vec<CosmosMsg> = Vec::new();
+    for vested in withdrawable.iter() {
+        let code_hash = schedules
+            .iter()
+            .find(|x| x.reward_token == vested.reward_token)
+            .map_or(String::new(), |x| x.code_hash.clone());
+        response_msgs.push(transfer_msg(
+            sender.to_string(),
+            vested.amount,
+            None,
+            None,
+            BLOCK_SIZE,
+            code_hash,
+            vested.reward_token.to_string(),
+        )?);
+        let vested_history = History {
+            amount: vested.amount,
+            date: current_time,
+            action: HistoryAction::VestedRelease,
+            reward_token: Some(vested.reward_token.clone()),
+            token_ids: None,
         };
+        history_store.push(deps.storage, &vested_history)?;
+        update_vesting_totals(
+            deps.storage,
+            &vested.reward_token,
+            Uint128::zero(),
+            vested.amount,
+        )?;
+    }
 
-        let user_reward_percentage =
-            Decimal::from_ratio(staked.staked_amount, state.total_staked_amount);
-        let elapsed_seconds = current_time - date;
-        let rewards_per_second =
-            Decimal::from_ratio(state.reward_contract.rewards_per_day, 24u64 * 60u64 * 60u64);
-        let est_rewards = user_reward_percentage
-            * rewards_per_second
-            * Decimal::from_atomics(elapsed_seconds, 0).unwrap();
+    for schedule in schedules.iter_mut() {
+        schedule.released = vested_amount(schedule, current_time);
+    }
+    schedules.retain(|x| x.released < x.amount);
+    if schedules.is_empty() {
+        USER_VESTING_STORE.remove(deps.storage, &sender_raw)?;
+    } else {
+        USER_VESTING_STORE.insert(deps.storage, &sender_raw, &schedules)?;
+    }
 
-        let without_decimals = Decimal::from(
-            est_rewards * Decimal::from_ratio(1u128, 10u128.pow(est_rewards.decimal_places())),
-        );
-        estimated_rewards = without_decimals.atomics();
+    Ok(Response::new().add_messages(response_msgs))
+}
+
+/// Records a staker's new amount and the pool total after a stake or withdraw.
+fn checkpoint_stake(
+    storage: &mut dyn Storage,
+    staker_raw: &CanonicalAddr,
+    staked_amount: Uint128,
+    total_staked_amount: Uint128,
+    time: u64,
+) -> StdResult<()> {
+    write_checkpoint(
+        storage,
+        &user_checkpoint_store(staker_raw),
+        time,
+        staked_amount,
+    )?;
+    write_checkpoint(storage, &TOTAL_CHECKPOINTS_STORE, time, total_staked_amount)
+}
+
+/// Only the last amount within a block is kept.
+fn write_checkpoint(
+    storage: &mut dyn Storage,
+    store: &AppendStore<Checkpoint>,
+    time: u64,
+    amount: Uint128,
+) -> StdResult<()> {
+    let checkpoint = Checkpoint { time, amount };
+    let len = store.get_len(storage)?;
+    if len > 0 && store.get_at(storage, len - 1)?.time == time {
+        return store.set_at(storage, len - 1, &checkpoint);
     }
+    store.push(storage, &checkpoint)
+}
 
-    return Ok(estimated_rewards);
+/// Binary searches for the last checkpoint at or before `time`.
+fn checkpoint_at(
+    storage: &dyn Storage,
+    store: &AppendStore<Checkpoint>,
+    time: u64,
+) -> StdResult<Uint128> {
+    let mut low = 0u32;
+    let mut high = store.get_len(storage)?;
+    while low < high {
+        let mid = (low + high) / 2;
+        if store.get_at(storage, mid)?.time <= time {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    if low == 0 {
+        return Ok(Uint128::zero());
+    }
+    Ok(store.get_at(storage, low - 1)?.amount)
 }
 
 #[entry_point]
 pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
     match msg {
         QueryMsg::GetStakedInfo {} => to_binary(&query_staked(deps)?),
-        QueryMsg::GetMyStakedInfo { permit } => to_binary(&query_my_staked(deps, _env, permit)?),
-        QueryMsg::GetNumUserHistory { permit } => {
-            to_binary(&query_num_user_history(deps, _env, permit)?)
+        QueryMsg::GetMyStakedInfo { permit, viewer } => {
+            let user_raw = get_querier(deps, permit, viewer, _env.contract.address.clone())?;
+            to_binary(&query_my_staked(deps, _env, user_raw)?)
+        }
+        QueryMsg::GetNumUserHistory { permit, viewer } => {
+            let user_raw = get_querier(deps, permit, viewer, _env.contract.address)?;
+            to_binary(&query_num_user_history(deps, user_raw)?)
         }
         QueryMsg::GetUserHistory {
             permit,
+            viewer,
             start_page,
             page_size,
-        } => to_binary(&query_user_history(
-            deps, _env, permit, start_page, page_size,
-        )?),
+            filter,
+        } => {
+            let user_raw = get_querier(deps, permit, viewer, _env.contract.address)?;
+            to_binary(&query_user_history(
+                deps, user_raw, start_page, page_size, filter,
+            )?)
+        }
         QueryMsg::GetRewardBalance { viewer } => {
             to_binary(&query_reward_balance(deps, _env, viewer)?)
         }
         QueryMsg::GetStakedBalance { viewer } => {
             to_binary(&query_staked_balance(deps, _env, viewer)?)
         }
+        QueryMsg::GetPauseState {} => to_binary(&query_pause_state(deps)?),
+        QueryMsg::GetAdmins {} => to_binary(&query_admins(deps)?),
+        QueryMsg::GetPendingConfigChanges {} => to_binary(&query_pending_config_changes(deps)?),
+        QueryMsg::GetAdminLog {
+            viewer,
+            start_page,
+            page_size,
+        } => to_binary(&query_admin_log(deps, viewer, start_page, page_size)?),
+        QueryMsg::GetStakerInfo { viewer, staker } => {
+            to_binary(&query_staker_info(deps, _env, viewer, staker)?)
+        }
+        QueryMsg::GetStakerHistory {
+            viewer,
+            staker,
+            page,
+            page_size,
+        } => to_binary(&query_staker_history(
+            deps, viewer, staker, page, page_size,
+        )?),
+        QueryMsg::ListStakers {
+            viewer,
+            start_after,
+            limit,
+        } => to_binary(&query_list_stakers(deps, _env, viewer, start_after, limit)?),
+        QueryMsg::GetStakedAt {
+            permit,
+            viewer,
+            address,
+            time,
+        } => {
+            let staker_raw =
+                get_authorized_staker(deps, permit, viewer, address, _env.contract.address)?;
+            to_binary(&checkpoint_at(
+                deps.storage,
+                &user_checkpoint_store(&staker_raw),
+                time,
+            )?)
+        }
+        QueryMsg::GetVotingPower {
+            permit,
+            viewer,
+            address,
+            time,
+        } => {
+            let staker_raw = get_authorized_staker(
+                deps,
+                permit,
+                viewer,
+                address,
+                _env.contract.address.clone(),
+            )?;
+            to_binary(&query_voting_power(deps, _env, staker_raw, time)?)
+        }
+        QueryMsg::GetMyDividends { permit, viewer } => {
+            let user_raw = get_querier(deps, permit, viewer, _env.contract.address.clone())?;
+            to_binary(&query_my_dividends(deps, _env, user_raw)?)
+        }
+        QueryMsg::GetMyOperator { permit, viewer } => {
+            let user_raw = get_querier(deps, permit, viewer, _env.contract.address.clone())?;
+            to_binary(&OPERATORS_STORE.get(deps.storage, &user_raw))
+        }
+        QueryMsg::GetMyVesting { permit, viewer } => {
+            let user_raw = get_querier(deps, permit, viewer, _env.contract.address.clone())?;
+            let schedules = USER_VESTING_STORE
+                .get(deps.storage, &user_raw)
+                .unwrap_or_default();
+            to_binary(&MyVestingResponse {
+                withdrawable: get_withdrawable_vesting(&schedules, _env.block.time.seconds()),
+                schedules,
+            })
+        }
+        QueryMsg::GetVestingInfo {} => to_binary(&VestingInfoResponse {
+            vesting: VESTING_ITEM.may_load(deps.storage)?,
+            totals: VESTING_TOTALS_ITEM
+                .may_load(deps.storage)?
+                .unwrap_or_default(),
+        }),
+        QueryMsg::GetIncentives {} => to_binary(&query_incentives(deps, _env)?),
+        QueryMsg::GetBeneficiaryAllowList {} => {
+            to_binary(&BENEFICIARY_ALLOW_LIST_ITEM.may_load(deps.storage)?)
+        }
+        QueryMsg::GetSponsorList {} => to_binary(
+            &SPONSOR_LIST_ITEM
+                .may_load(deps.storage)?
+                .unwrap_or_default(),
+        ),
+        QueryMsg::GetRaffles {
+            viewer,
+            start_page,
+            page_size,
+        } => to_binary(&query_raffles(deps, viewer, start_page, page_size)?),
+        QueryMsg::GetTotalStakedAt { time } => to_binary(&checkpoint_at(
+            deps.storage,
+            &TOTAL_CHECKPOINTS_STORE,
+            time,
+        )?),
     }
 }
 
 fn query_staked(deps: Deps) -> StdResult<StakedInfoResponse> {
     let state = CONFIG_ITEM.load(deps.storage)?;
+    let shutdown = SHUTDOWN_ITEM.may_load(deps.storage)?;
     Ok(StakedInfoResponse {
         total_staked_amount: state.total_staked_amount,
         total_rewards: state.total_rewards,
         staking_contract: state.staking_contract,
         reward_contract: state.reward_contract,
-        trait_restriction: None
+        trait_restriction: None,
+        is_active: Some(state.is_active),
+        pause_flags: Some(PAUSE_ITEM.may_load(deps.storage)?.unwrap_or_default()),
+        shutdown_time: shutdown.map(|x| x.shutdown_time),
+        receipt_contract: state.receipt_contract,
+    })
+}
+
+fn query_pause_state(deps: Deps) -> StdResult<PauseFlags> {
+    Ok(PAUSE_ITEM.may_load(deps.storage)?.unwrap_or_default())
+}
+
+fn query_admins(deps: Deps) -> StdResult<AdminsResponse> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+    let mut roles: Vec<RoleAssignment> = Vec::new();
+    for entry in ROLES_STORE.iter(deps.storage)? {
+        let (address_raw, address_roles) = entry?;
+        roles.push(RoleAssignment {
+            address: deps.api.addr_humanize(&address_raw)?,
+            roles: address_roles,
+        });
+    }
+
+    Ok(AdminsResponse {
+        owner: state.owner,
+        pending_owner: PENDING_OWNER_ITEM.may_load(deps.storage)?,
+        roles,
+    })
+}
+
+fn query_pending_config_changes(deps: Deps) -> StdResult<PendingConfigChangesResponse> {
+    let mut changes: Vec<PendingConfigChange> = Vec::new();
+    for entry in CONFIG_CHANGES_STORE.iter(deps.storage)? {
+        let (_, pending_change) = entry?;
+        changes.push(pending_change);
+    }
+
+    Ok(PendingConfigChangesResponse {
+        timelock_delay: get_timelock_delay(deps.storage)?,
+        changes,
     })
 }
 
-fn query_my_staked(deps: Deps, env: Env, permit: Permit) -> StdResult<MyStakedInfoResponse> {
-    let user_raw = get_querier(deps, permit, env.contract.address)?;
+fn query_my_staked(
+    deps: Deps,
+    env: Env,
+    user_raw: CanonicalAddr,
+) -> StdResult<MyStakedInfoResponse> {
     let state = CONFIG_ITEM.load(deps.storage)?;
 
     let staked = STAKED_STORE.get(deps.storage, &user_raw).unwrap_or(Staked {
@@ -587,31 +3166,75 @@ fn query_my_staked(deps: Deps, env: Env, permit: Permit) -> StdResult<MyStakedIn
         last_staked_date: None,
     });
 
-    let current_time = env.block.time.seconds();
+    let current_time = get_accrual_time(deps.storage, env.block.time.seconds())?;
     let estimated_rewards = get_estimated_rewards(&staked, &current_time, &state)?;
     Ok(MyStakedInfoResponse {
         staked: staked,
         estimated_rewards: estimated_rewards,
+        auto_compound: auto_compounds(deps.storage, &state, &user_raw),
     })
 }
 
-fn query_num_user_history(deps: Deps, env: Env, permit: Permit) -> StdResult<u32> {
-    let user_raw = get_querier(deps, permit, env.contract.address)?;
-    let history_store = HISTORY_STORE.add_suffix(&user_raw);
+fn query_num_user_history(deps: Deps, user_raw: CanonicalAddr) -> StdResult<u32> {
+    let history_store = user_history_store(&user_raw);
     let num = history_store.get_len(deps.storage)?;
     Ok(num)
 }
 
+/// `AppendStore::paging` works out the page bounds in u32, so pages past them are
+/// returned empty instead of overflowing.
+fn page_store<T: Serialize + DeserializeOwned>(
+    storage: &dyn Storage,
+    store: &AppendStore<T>,
+    start_page: u32,
+    page_size: u32,
+) -> StdResult<Vec<T>> {
+    match start_page
+        .checked_mul(page_size)
+        .and_then(|x| x.checked_add(page_size))
+    {
+        Some(_) => store.paging(storage, start_page, page_size),
+        None => Ok(Vec::new()),
+    }
+}
+
 fn query_user_history(
     deps: Deps,
-    env: Env,
-    permit: Permit,
+    user_raw: CanonicalAddr,
     start_page: u32,
     page_size: u32,
+    filter: Option<HistoryFilter>,
 ) -> StdResult<Vec<History>> {
-    let user_raw = get_querier(deps, permit, env.contract.address)?;
-    let history_store = HISTORY_STORE.add_suffix(&user_raw);
-    let history = history_store.paging(deps.storage, start_page, page_size)?;
+    let history_store = user_history_store(&user_raw);
+    let filter = match filter {
+        Some(filter) => filter,
+        None => return page_store(deps.storage, &history_store, start_page, page_size),
+    };
+
+    let is_match = |history: &History| {
+        !matches!(&filter.action, Some(x) if *x != history.action)
+            && !matches!(filter.start_time, Some(x) if history.date < x)
+            && !matches!(filter.end_time, Some(x) if history.date > x)
+    };
+    let len = history_store.get_len(deps.storage)?;
+    let newest_first = filter.newest_first.unwrap_or(false);
+    let mut to_skip = (start_page as usize).saturating_mul(page_size as usize);
+    let mut history: Vec<History> = Vec::new();
+    for i in 0..len {
+        if history.len() >= page_size as usize {
+            break;
+        }
+        let pos = if newest_first { len - 1 - i } else { i };
+        let entry = history_store.get_at(deps.storage, pos)?;
+        if !is_match(&entry) {
+            continue;
+        }
+        if to_skip > 0 {
+            to_skip -= 1;
+            continue;
+        }
+        history.push(entry);
+    }
     Ok(history)
 }
 
@@ -629,36 +3252,234 @@ fn query_reward_balance(deps: Deps, env: Env, viewer: ViewerInfo) -> StdResult<B
     Ok(balance.unwrap())
 }
 
-fn query_staked_balance(deps: Deps, env: Env, viewer: ViewerInfo) -> StdResult<Balance> {
-    check_admin_key(deps, viewer)?;
+fn query_staked_balance(deps: Deps, env: Env, viewer: ViewerInfo) -> StdResult<Balance> {
+    check_admin_key(deps, viewer)?;
+    let state = CONFIG_ITEM.load(deps.storage)?;
+    let balance = balance_query(
+        deps.querier,
+        env.contract.address.to_string(),
+        state.viewing_key.unwrap(),
+        BLOCK_SIZE,
+        state.staking_contract.code_hash,
+        state.staking_contract.address.to_string(),
+    );
+    Ok(balance.unwrap())
+}
+
+fn query_admin_log(
+    deps: Deps,
+    viewer: ViewerInfo,
+    start_page: u32,
+    page_size: u32,
+) -> StdResult<AdminLogResponse> {
+    check_admin_key(deps, viewer)?;
+    let entries = page_store(deps.storage, &ADMIN_LOG_STORE, start_page, page_size)?;
+    let total = ADMIN_LOG_STORE.get_len(deps.storage)?;
+    Ok(AdminLogResponse { entries, total })
+}
+
+fn query_staker_info(
+    deps: Deps,
+    env: Env,
+    viewer: ViewerInfo,
+    staker: String,
+) -> StdResult<MyStakedInfoResponse> {
+    check_admin_key(deps, viewer)?;
+    let staker_raw = deps.api.addr_canonicalize(&staker)?;
+    query_my_staked(deps, env, staker_raw)
+}
+
+fn query_staker_history(
+    deps: Deps,
+    viewer: ViewerInfo,
+    staker: String,
+    page: u32,
+    page_size: u32,
+) -> StdResult<Vec<History>> {
+    check_admin_key(deps, viewer)?;
+    let staker_raw = deps.api.addr_canonicalize(&staker)?;
+    query_user_history(deps, staker_raw, page, page_size, None)
+}
+
+/// Walks `STAKED_STORE` in index order. Staked entries are zeroed rather than removed,
+/// so an index stays a stable cursor between pages.
+fn query_list_stakers(
+    deps: Deps,
+    env: Env,
+    viewer: ViewerInfo,
+    start_after: Option<u32>,
+    limit: Option<u32>,
+) -> StdResult<StakersResponse> {
+    check_admin_key(deps, viewer)?;
+    let state = CONFIG_ITEM.load(deps.storage)?;
+    let current_time = get_accrual_time(deps.storage, env.block.time.seconds())?;
+    let limit = limit.unwrap_or(DEFAULT_RETURN_LIMIT).min(MAX_RETURN_LIMIT);
+    // nothing comes after the last u32 index
+    let start = match start_after.map_or(Some(0), |i| i.checked_add(1)) {
+        Some(start) => start,
+        None => {
+            return Ok(StakersResponse {
+                stakers: Vec::new(),
+                next: None,
+            })
+        }
+    };
+    let total = STAKED_STORE.get_len(deps.storage)?;
+
+    let mut stakers: Vec<StakerEntry> = Vec::new();
+    let mut last_index: Option<u32> = None;
+    for (index, item) in STAKED_STORE
+        .iter(deps.storage)?
+        .enumerate()
+        .skip(start as usize)
+    {
+        if stakers.len() as u32 >= limit {
+            break;
+        }
+        let (staker_raw, staked) = item?;
+        last_index = Some(index as u32);
+        if staked.staked_amount.is_zero() {
+            continue;
+        }
+        stakers.push(StakerEntry {
+            address: deps.api.addr_humanize(&staker_raw)?,
+            estimated_rewards: get_estimated_rewards(&staked, &current_time, &state)?,
+            staked,
+        });
+    }
+
+    Ok(StakersResponse {
+        stakers,
+        next: last_index.filter(|i| i + 1 < total),
+    })
+}
+
+fn check_admin_key(deps: Deps, viewer: ViewerInfo) -> StdResult<()> {
+    let admin_keys = ADMIN_VIEWING_KEYS_ITEM
+        .may_load(deps.storage)?
+        .unwrap_or_default();
+    let vk = hash_viewing_key(&viewer.viewing_key);
+
+    if !admin_keys
+        .iter()
+        .any(|x| x.viewing_key == vk && x.address == viewer.address)
+    {
+        return Err(StdError::generic_err(
+            "Wrong viewing key for this address or viewing key not set",
+        ));
+    }
+
+    return Ok(());
+}
+
+/// Voting power is the staked amount as of `time` (defaults to now).
+fn query_voting_power(
+    deps: Deps,
+    env: Env,
+    staker_raw: CanonicalAddr,
+    time: Option<u64>,
+) -> StdResult<VotingPowerResponse> {
+    let time = get_voting_time(&env, time)?;
+    Ok(VotingPowerResponse {
+        power: checkpoint_at(deps.storage, &user_checkpoint_store(&staker_raw), time)?,
+        total_power: checkpoint_at(deps.storage, &TOTAL_CHECKPOINTS_STORE, time)?,
+        time,
+    })
+}
+
+/// Balances after the current block aren't known yet, so only past and present
+/// times are answered.
+fn get_voting_time(env: &Env, time: Option<u64>) -> StdResult<u64> {
+    let current_time = env.block.time.seconds();
+    let time = time.unwrap_or(current_time);
+    if time > current_time {
+        return Err(StdError::generic_err(
+            "Voting power is only known up to the current block time",
+        ));
+    }
+    Ok(time)
+}
+
+fn query_my_dividends(
+    deps: Deps,
+    env: Env,
+    user_raw: CanonicalAddr,
+) -> StdResult<Vec<PendingDividend>> {
+    let state = CONFIG_ITEM.load(deps.storage)?;
+    let staked_amount = STAKED_STORE
+        .get(deps.storage, &user_raw)
+        .map_or(Uint128::zero(), |x| x.staked_amount);
+    let (pools, _) = get_dripped_pools(
+        deps.storage,
+        state.total_staked_amount,
+        env.block.time.seconds(),
+    )?;
+    let user_dividends = get_user_dividends(deps.storage, &pools, &user_raw, staked_amount)?;
+    Ok(user_dividends
+        .into_iter()
+        .map(|x| PendingDividend {
+            reward_token: x.reward_token,
+            amount: x.owed,
+        })
+        .collect())
+}
+
+fn query_incentives(deps: Deps, env: Env) -> StdResult<Vec<Incentive>> {
     let state = CONFIG_ITEM.load(deps.storage)?;
-    let balance = balance_query(
-        deps.querier,
-        env.contract.address.to_string(),
-        state.viewing_key.unwrap(),
-        BLOCK_SIZE,
-        state.staking_contract.code_hash,
-        state.staking_contract.address.to_string(),
-    );
-    Ok(balance.unwrap())
+    let (_, incentives) = get_dripped_pools(
+        deps.storage,
+        state.total_staked_amount,
+        env.block.time.seconds(),
+    )?;
+    Ok(incentives)
 }
 
-fn check_admin_key(deps: Deps, viewer: ViewerInfo) -> StdResult<()> {
-    let admin_viewing_key = ADMIN_VIEWING_KEY_ITEM.load(deps.storage)?;
-    let prng_seed: Vec<u8> = sha_256(base64::encode(viewer.viewing_key).as_bytes()).to_vec();
-    let vk = base64::encode(&prng_seed);
+fn query_raffles(
+    deps: Deps,
+    viewer: ViewerInfo,
+    start_page: u32,
+    page_size: u32,
+) -> StdResult<RafflesResponse> {
+    check_admin_key(deps, viewer)?;
+    let raffles = page_store(deps.storage, &RAFFLES_STORE, start_page, page_size)?;
+    let total = RAFFLES_STORE.get_len(deps.storage)?;
+    Ok(RafflesResponse { raffles, total })
+}
 
-    if vk != admin_viewing_key.viewing_key || viewer.address != admin_viewing_key.address {
-        return Err(StdError::generic_err(
-            "Wrong viewing key for this address or viewing key not set",
-        ));
+/// A staker can read their own data with a permit or viewing key. A `Governance`
+/// role holder can read anyone's with its own, and so can an admin key.
+fn get_authorized_staker(
+    deps: Deps,
+    permit: Option<Permit>,
+    viewer: Option<ViewerInfo>,
+    address: String,
+    contract_address: Addr,
+) -> StdResult<CanonicalAddr> {
+    let staker_raw = deps.api.addr_canonicalize(&address)?;
+    if let Some(admin) = viewer.clone() {
+        if check_admin_key(deps, admin).is_ok() {
+            return Ok(staker_raw);
+        }
     }
-
-    return Ok(());
+    let querier_raw = get_querier(deps, permit, viewer, contract_address)?;
+    if querier_raw != staker_raw {
+        let state = CONFIG_ITEM.load(deps.storage)?;
+        let querier = deps.api.addr_humanize(&querier_raw)?;
+        if check_role(deps, &state, &querier, Role::Governance).is_err() {
+            return Err(StdError::generic_err("Not authorized to view this address"));
+        }
+    }
+    Ok(staker_raw)
 }
 
-fn get_querier(deps: Deps, permit: Permit, contract_address: Addr) -> StdResult<CanonicalAddr> {
-    if let pmt = permit {
+/// Authenticates a user query with either a permit or an address and viewing key.
+fn get_querier(
+    deps: Deps,
+    permit: Option<Permit>,
+    viewer: Option<ViewerInfo>,
+    contract_address: Addr,
+) -> StdResult<CanonicalAddr> {
+    if let Some(pmt) = permit {
         let querier = deps.api.addr_canonicalize(&validate(
             deps,
             PREFIX_REVOKED_PERMITS,
@@ -674,13 +3495,26 @@ fn get_querier(deps: Deps, permit: Permit, contract_address: Addr) -> StdResult<
         }
         return Ok(querier);
     }
+    if let Some(viewer) = viewer {
+        let querier = deps.api.addr_canonicalize(&viewer.address)?;
+        let viewing_key = USER_VIEWING_KEYS_STORE.get(deps.storage, &querier);
+        if viewing_key != Some(hash_viewing_key(&viewer.viewing_key)) {
+            return Err(StdError::generic_err(
+                "Wrong viewing key for this address or viewing key not set",
+            ));
+        }
+        return Ok(querier);
+    }
     return Err(StdError::generic_err("Unauthorized"));
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::msg::ContractInfo;
+    use crate::msg::{ContractInfo, SponsorToken};
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+    use cosmwasm_std::Api;
+    use cosmwasm_std::Timestamp;
 
     #[test]
     fn decimal_new() {
@@ -717,6 +3551,7 @@ mod tests {
                 viewing_key: None,
                 total_staked_amount: Uint128::from(1004000000u128),
                 total_rewards: Uint128::from(10000000000000u128),
+                receipt_contract: None,
             }
         };
         let x = get_estimated_rewards(&staked, &current_time, &state);
@@ -729,4 +3564,1155 @@ mod tests {
 
         //2.997014925373134
     }
+
+    #[test]
+    fn shutdown_freezes_accrual() {
+        let mut deps = mock_dependencies();
+        let current_time = 1686675096;
+        assert_eq!(get_accrual_time(&deps.storage, current_time).unwrap(), current_time);
+
+        let shutdown = Shutdown {
+            shutdown_time: 1686600000,
+            cursor: 0,
+        };
+        SHUTDOWN_ITEM.save(&mut deps.storage, &shutdown).unwrap();
+        let frozen_time = get_accrual_time(&deps.storage, current_time).unwrap();
+        assert_eq!(frozen_time, shutdown.shutdown_time);
+        let earlier_time = get_accrual_time(&deps.storage, 1686500000).unwrap();
+        assert_eq!(earlier_time, 1686500000);
+    }
+
+    #[test]
+    fn roles_and_ownership_transfer() {
+        let mut deps = mock_dependencies();
+        let owner = Addr::unchecked("owner");
+        let pauser = Addr::unchecked("pauser");
+        let new_owner = Addr::unchecked("new_owner");
+        let state = State {
+            owner: owner.clone(),
+            is_active: true,
+            staking_contract: ContractInfo {
+                code_hash: "".to_string(),
+                address: Addr::unchecked(""),
+                name: "".to_string(),
+                stake_type: "".to_string(),
+            },
+            reward_contract: RewardsContractInfo {
+                code_hash: "".to_string(),
+                address: Addr::unchecked(""),
+                rewards_per_day: Uint128::from(0u128),
+                name: "".to_string(),
+            },
+            viewing_key: None,
+            total_staked_amount: Uint128::from(0u128),
+            total_rewards: Uint128::from(0u128),
+            receipt_contract: None,
+        };
+        CONFIG_ITEM.save(&mut deps.storage, &state).unwrap();
+
+        try_set_role(deps.as_mut(), &owner, pauser.clone(), Role::Pauser, true).unwrap();
+        assert!(check_role(deps.as_ref(), &state, &pauser, Role::Pauser).is_ok());
+        assert!(check_role(deps.as_ref(), &state, &pauser, Role::RewardManager).is_err());
+
+        try_propose_owner(deps.as_mut(), &owner, new_owner.clone()).unwrap();
+        assert!(try_accept_owner(deps.as_mut(), &pauser).is_err());
+        try_accept_owner(deps.as_mut(), &new_owner).unwrap();
+        let admins = query_admins(deps.as_ref()).unwrap();
+        assert_eq!(admins.owner, new_owner);
+        assert_eq!(admins.pending_owner, None);
+        assert_eq!(admins.roles.len(), 1);
+
+        // reward managers fund the pool but can't pull the rewards back out
+        let reward_manager = Addr::unchecked("reward_manager");
+        try_set_role(
+            deps.as_mut(),
+            &new_owner,
+            reward_manager.clone(),
+            Role::RewardManager,
+            true,
+        )
+        .unwrap();
+        assert!(try_remove_rewards(deps.as_mut(), &reward_manager).is_err());
+    }
+
+    #[test]
+    fn migrate_moves_legacy_history() {
+        let mut deps = mock_dependencies();
+        let staker = Addr::unchecked("staker");
+        let staker_raw = deps.api.addr_canonicalize(staker.as_str()).unwrap();
+        let staked = Staked {
+            staked_amount: Uint128::from(5u128),
+            last_claimed_date: None,
+            last_staked_date: Some(1686588696),
+        };
+        STAKED_STORE
+            .insert(&mut deps.storage, &staker_raw, &staked)
+            .unwrap();
+        let legacy = LegacyHistory {
+            amount: Uint128::from(5u128),
+            date: 1686588696,
+            action: "stake".to_string(),
+        };
+        let legacy_store = LEGACY_HISTORY_STORE.add_suffix(staker.as_bytes());
+        legacy_store.push(&mut deps.storage, &legacy).unwrap();
+
+        let owner = Addr::unchecked("owner");
+        CONFIG_ITEM
+            .save(&mut deps.storage, &test_state(&owner))
+            .unwrap();
+        let migration = Migration {
+            time: 1686600000,
+            cursor: 0,
+        };
+        MIGRATION_ITEM.save(&mut deps.storage, &migration).unwrap();
+
+        assert!(try_migrate_history(deps.as_mut(), &staker, None, None).is_err());
+        try_migrate_history(deps.as_mut(), &owner, None, Some(1)).unwrap();
+
+        let history = user_history_store(&staker_raw)
+            .paging(&deps.storage, 0, 10)
+            .unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].action, HistoryAction::Stake);
+        assert_eq!(history[0].amount, legacy.amount);
+        assert_eq!(legacy_store.get_len(&deps.storage).unwrap(), 0);
+        let checkpoints = user_checkpoint_store(&staker_raw);
+        assert_eq!(
+            checkpoint_at(&deps.storage, &checkpoints, migration.time).unwrap(),
+            staked.staked_amount
+        );
+
+        // the cursor moved past the only staker
+        assert!(try_migrate_history(deps.as_mut(), &owner, None, None).is_err());
+        try_migrate_history(deps.as_mut(), &owner, Some(0), None).unwrap();
+        assert_eq!(checkpoints.get_len(&deps.storage).unwrap(), 1);
+    }
+
+    #[test]
+    fn filtered_history_pages_newest_first() {
+        let mut deps = mock_dependencies();
+        let staker_raw = deps.api.addr_canonicalize("staker").unwrap();
+        let history_store = user_history_store(&staker_raw);
+        for date in 1..=5u64 {
+            let action = if date % 2 == 0 {
+                HistoryAction::Claim
+            } else {
+                HistoryAction::Stake
+            };
+            let history = History {
+                amount: Uint128::from(date as u128),
+                date,
+                action,
+                reward_token: None,
+                token_ids: None,
+            };
+            history_store.push(&mut deps.storage, &history).unwrap();
+        }
+
+        let filter = HistoryFilter {
+            action: Some(HistoryAction::Stake),
+            newest_first: Some(true),
+            ..Default::default()
+        };
+        let page = |start_page, page_size| {
+            query_user_history(
+                deps.as_ref(),
+                staker_raw.clone(),
+                start_page,
+                page_size,
+                Some(filter.clone()),
+            )
+            .unwrap()
+            .iter()
+            .map(|x| x.date)
+            .collect::<Vec<u64>>()
+        };
+        assert_eq!(page(0, 2), vec![5, 3]);
+        assert_eq!(page(1, 2), vec![1]);
+        assert!(page(u32::MAX, u32::MAX).is_empty());
+
+        let unfiltered =
+            query_user_history(deps.as_ref(), staker_raw.clone(), u32::MAX, 2, None).unwrap();
+        assert!(unfiltered.is_empty());
+    }
+
+    #[test]
+    fn list_stakers_pages_with_cursor() {
+        let mut deps = mock_dependencies();
+        let state = State {
+            owner: Addr::unchecked("owner"),
+            is_active: true,
+            staking_contract: ContractInfo {
+                code_hash: "".to_string(),
+                address: Addr::unchecked(""),
+                name: "".to_string(),
+                stake_type: "".to_string(),
+            },
+            reward_contract: RewardsContractInfo {
+                code_hash: "".to_string(),
+                address: Addr::unchecked(""),
+                rewards_per_day: Uint128::from(0u128),
+                name: "".to_string(),
+            },
+            viewing_key: None,
+            total_staked_amount: Uint128::from(12u128),
+            total_rewards: Uint128::from(0u128),
+            receipt_contract: None,
+        };
+        CONFIG_ITEM.save(&mut deps.storage, &state).unwrap();
+        let admin_key = ViewerInfo {
+            address: "owner".to_string(),
+            viewing_key: hash_viewing_key("key"),
+        };
+        ADMIN_VIEWING_KEYS_ITEM
+            .save(&mut deps.storage, &vec![admin_key])
+            .unwrap();
+
+        for (staker, amount) in [("alice", 5u128), ("bob", 0u128), ("carol", 7u128)] {
+            let staked = Staked {
+                staked_amount: Uint128::from(amount),
+                last_claimed_date: None,
+                last_staked_date: Some(1686588696),
+            };
+            let staker_raw = deps.api.addr_canonicalize(staker).unwrap();
+            STAKED_STORE
+                .insert(&mut deps.storage, &staker_raw, &staked)
+                .unwrap();
+        }
+        let viewer = ViewerInfo {
+            address: "owner".to_string(),
+            viewing_key: "key".to_string(),
+        };
+
+        let first =
+            query_list_stakers(deps.as_ref(), mock_env(), viewer.clone(), None, Some(1)).unwrap();
+        assert_eq!(first.stakers.len(), 1);
+        assert_eq!(first.stakers[0].address, Addr::unchecked("alice"));
+        assert_eq!(first.next, Some(0));
+
+        // bob has withdrawn, so the second page skips him and ends the listing
+        let second =
+            query_list_stakers(deps.as_ref(), mock_env(), viewer, first.next, Some(1)).unwrap();
+        assert_eq!(second.stakers.len(), 1);
+        assert_eq!(second.stakers[0].address, Addr::unchecked("carol"));
+        assert_eq!(second.next, None);
+    }
+
+    #[test]
+    fn checkpoints_return_amount_at_time() {
+        let mut deps = mock_dependencies();
+        let staker_raw = deps.api.addr_canonicalize("staker").unwrap();
+        let amount = Uint128::from(5u128);
+        checkpoint_stake(&mut deps.storage, &staker_raw, amount, amount, 100).unwrap();
+        // a second stake in the same block replaces the checkpoint
+        let amount = Uint128::from(7u128);
+        checkpoint_stake(&mut deps.storage, &staker_raw, amount, amount, 100).unwrap();
+        checkpoint_stake(
+            &mut deps.storage,
+            &staker_raw,
+            Uint128::zero(),
+            Uint128::zero(),
+            200,
+        )
+        .unwrap();
+
+        let store = user_checkpoint_store(&staker_raw);
+        assert_eq!(store.get_len(&deps.storage).unwrap(), 2);
+        assert_eq!(
+            checkpoint_at(&deps.storage, &store, 50).unwrap(),
+            Uint128::zero()
+        );
+        assert_eq!(checkpoint_at(&deps.storage, &store, 100).unwrap(), amount);
+        assert_eq!(checkpoint_at(&deps.storage, &store, 150).unwrap(), amount);
+        assert_eq!(
+            checkpoint_at(&deps.storage, &store, 250).unwrap(),
+            Uint128::zero()
+        );
+        let total = checkpoint_at(&deps.storage, &TOTAL_CHECKPOINTS_STORE, 150).unwrap();
+        assert_eq!(total, amount);
+    }
+
+    #[test]
+    fn raffle_draws_distinct_weighted_winners() {
+        let deps = mock_dependencies();
+        let alice = deps.api.addr_canonicalize("alice").unwrap();
+        let bob = deps.api.addr_canonicalize("bob").unwrap();
+        let carol = deps.api.addr_canonicalize("carol").unwrap();
+        let mut rng = Prng::new(b"seed", b"entropy");
+
+        let entries = vec![(alice.clone(), 0), (bob.clone(), 5)];
+        assert_eq!(draw_winners(&mut rng, entries, 1), vec![bob.clone()]);
+
+        let entries = vec![(alice.clone(), 3), (bob.clone(), 5), (carol.clone(), 7)];
+        let winners = draw_winners(&mut rng, entries, 3);
+        assert_eq!(winners.len(), 3);
+        assert!(winners.contains(&alice) && winners.contains(&bob) && winners.contains(&carol));
+    }
+
+    #[test]
+    fn raffle_pays_only_deposited_prizes() {
+        let mut deps = mock_dependencies();
+        let env = mock_env();
+        let owner = Addr::unchecked("owner");
+        let prize_token = Addr::unchecked("prize");
+        let state = test_state(&owner);
+        CONFIG_ITEM.save(&mut deps.storage, &state).unwrap();
+        for staker in ["alice", "bob"] {
+            let staked = Staked {
+                staked_amount: Uint128::from(10u128),
+                last_claimed_date: None,
+                last_staked_date: Some(1686588696),
+            };
+            let staker_raw = deps.api.addr_canonicalize(staker).unwrap();
+            STAKED_STORE
+                .insert(&mut deps.storage, &staker_raw, &staked)
+                .unwrap();
+        }
+        let prize = |amount: u128| RafflePrize::Token {
+            address: prize_token.clone(),
+            code_hash: "".to_string(),
+            amount: Uint128::from(amount),
+            winners: 2,
+        };
+
+        deposit_raffle_tokens(
+            &mut deps.storage,
+            &state,
+            &prize_token,
+            Uint128::from(100u128),
+        )
+        .unwrap();
+        let too_much = try_run_raffle(deps.as_mut(), env.clone(), &owner, prize(60));
+        assert!(too_much.is_err());
+        let response = try_run_raffle(deps.as_mut(), env.clone(), &owner, prize(50)).unwrap();
+        assert_eq!(response.messages.len(), 2);
+        assert_eq!(
+            RAFFLE_TOKEN_PRIZES_STORE.get(&deps.storage, &prize_token),
+            Some(Uint128::zero())
+        );
+        assert!(try_run_raffle(deps.as_mut(), env, &owner, prize(1)).is_err());
+
+        // tokens the pool already pays out can't be deposited as prizes
+        let dividend_token = Addr::unchecked("dividend");
+        add_dividend(
+            &mut deps.storage,
+            Uint128::from(20u128),
+            &dividend_token,
+            "",
+            Uint128::from(100u128),
+        )
+        .unwrap();
+        let deposit = deposit_raffle_tokens(
+            &mut deps.storage,
+            &state,
+            &dividend_token,
+            Uint128::from(100u128),
+        );
+        assert!(deposit.is_err());
+
+        // pages past u32 are empty rather than overflowing
+        assert!(
+            page_store(&deps.storage, &RAFFLES_STORE, u32::MAX, u32::MAX)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn dividends_split_pro_rata() {
+        let mut deps = mock_dependencies();
+        let reward_token = Addr::unchecked("reward");
+        let alice = deps.api.addr_canonicalize("alice").unwrap();
+        let bob = deps.api.addr_canonicalize("bob").unwrap();
+        let carol = deps.api.addr_canonicalize("carol").unwrap();
+        let one = Uint128::from(1u128);
+        let three = Uint128::from(3u128);
+        let four = Uint128::from(4u128);
+
+        add_dividend(
+            &mut deps.storage,
+            four,
+            &reward_token,
+            "",
+            Uint128::from(100u128),
+        )
+        .unwrap();
+        // carol stakes after the first dividend and only shares in the second
+        settle_dividends(&mut deps.storage, &carol, Uint128::zero(), four, 0).unwrap();
+        let eight = Uint128::from(8u128);
+        add_dividend(
+            &mut deps.storage,
+            eight,
+            &reward_token,
+            "",
+            Uint128::from(80u128),
+        )
+        .unwrap();
+
+        let pools = DIVIDEND_POOLS_ITEM.load(&deps.storage).unwrap();
+        let owed = |storage: &dyn Storage, staker_raw: &CanonicalAddr, staked_amount: Uint128| {
+            get_user_dividends(storage, &pools, staker_raw, staked_amount).unwrap()[0].owed
+        };
+        assert_eq!(owed(&deps.storage, &alice, one), Uint128::from(35u128));
+        assert_eq!(owed(&deps.storage, &bob, three), Uint128::from(105u128));
+        assert_eq!(owed(&deps.storage, &carol, four), Uint128::from(40u128));
+
+        let paid = pay_dividends(
+            &mut deps.storage,
+            &alice,
+            &Addr::unchecked("alice"),
+            one,
+            eight,
+            0,
+        );
+        assert_eq!(paid.unwrap().len(), 1);
+        assert_eq!(owed(&deps.storage, &alice, one), Uint128::zero());
+    }
+
+    #[test]
+    fn incentives_stream_and_keep_idle_time_for_sponsor() {
+        let mut deps = mock_dependencies();
+        let token = Addr::unchecked("sponsored");
+        let sponsor = Addr::unchecked("sponsor");
+        let denied = Addr::unchecked("denied");
+        let ten = Uint128::from(10u128);
+
+        // tokens that aren't allowed can't sponsor
+        let amount = Uint128::from(1000u128);
+        assert!(add_incentive(&mut deps.storage, &token, &sponsor, amount, 100, 200, 100).is_err());
+        SPONSOR_LIST_ITEM
+            .save(
+                &mut deps.storage,
+                &SponsorList {
+                    allowed: vec![SponsorToken {
+                        address: token.clone(),
+                        code_hash: "sponsored_hash".to_string(),
+                    }],
+                    denied: vec![denied.clone()],
+                },
+            )
+            .unwrap();
+        // denied sponsors can't sponsor with an allowed token
+        assert!(add_incentive(&mut deps.storage, &token, &denied, amount, 100, 200, 100).is_err());
+        // nor can an incentive start before it was sent
+        assert!(add_incentive(&mut deps.storage, &token, &sponsor, amount, 99, 200, 100).is_err());
+        add_incentive(&mut deps.storage, &token, &sponsor, amount, 100, 200, 100).unwrap();
+
+        // nothing staked for the first quarter
+        drip_incentives(&mut deps.storage, Uint128::zero(), 125).unwrap();
+        let pools = drip_incentives(&mut deps.storage, ten, 300).unwrap();
+        assert_eq!(pools[0].acc_per_share, Uint128::from(75 * DIVIDEND_SCALE));
+
+        let incentives = INCENTIVES_ITEM.load(&deps.storage).unwrap();
+        assert_eq!(incentives[0].code_hash, "sponsored_hash");
+        assert_eq!(incentives[0].distributed, Uint128::from(750u128));
+        assert_eq!(incentives[0].unused, Uint128::from(250u128));
+
+        // ended incentives don't count towards the cap
+        for _ in 0..MAX_ACTIVE_INCENTIVES {
+            add_incentive(&mut deps.storage, &token, &sponsor, amount, 300, 400, 300).unwrap();
+        }
+        assert!(add_incentive(&mut deps.storage, &token, &sponsor, amount, 300, 400, 300).is_err());
+    }
+
+    #[test]
+    fn vesting_releases_linearly_after_cliff() {
+        let mut deps = mock_dependencies();
+        let alice = deps.api.addr_canonicalize("alice").unwrap();
+        let reward_token = Addr::unchecked("reward");
+        let claimed = Uint128::from(3000u128);
+
+        // without vesting claims are transferred right away
+        let msg = reward_msg(
+            &mut deps.storage,
+            &alice,
+            &Addr::unchecked("alice"),
+            claimed,
+            &reward_token,
+            "",
+            0,
+        );
+        assert!(msg.unwrap().is_some());
+
+        let vesting = VestingConfig {
+            duration: 30,
+            cliff: 10,
+        };
+        VESTING_ITEM.save(&mut deps.storage, &vesting).unwrap();
+        let msg = reward_msg(
+            &mut deps.storage,
+            &alice,
+            &Addr::unchecked("alice"),
+            claimed,
+            &reward_token,
+            "",
+            100,
+        );
+        assert!(msg.unwrap().is_none());
+
+        let schedules = USER_VESTING_STORE.get(&deps.storage, &alice).unwrap();
+        assert!(get_withdrawable_vesting(&schedules, 109).is_empty());
+        assert_eq!(
+            get_withdrawable_vesting(&schedules, 110)[0].amount,
+            Uint128::from(1000u128)
+        );
+        assert_eq!(get_withdrawable_vesting(&schedules, 200)[0].amount, claimed);
+        assert_eq!(
+            VESTING_TOTALS_ITEM.load(&deps.storage).unwrap()[0].locked,
+            claimed
+        );
+    }
+
+    #[test]
+    fn claim_pause_defers_rewards_until_resume() {
+        let mut deps = mock_dependencies();
+        let alice = deps.api.addr_canonicalize("alice").unwrap();
+        let reward_token = Addr::unchecked("reward");
+        let pause_flags = PauseFlags {
+            claiming: PauseStatus {
+                is_paused: true,
+                resume_time: Some(200),
+                reason: Some("audit".to_string()),
+            },
+            ..Default::default()
+        };
+        PAUSE_ITEM.save(&mut deps.storage, &pause_flags).unwrap();
+
+        let paused = check_not_paused(&deps.storage, PauseOperation::Claiming, 100);
+        match paused {
+            Err(ContractError::CustomError { val }) => {
+                assert_eq!(val, "Claiming is paused: audit")
+            }
+            _ => panic!("claiming should be paused"),
+        }
+        assert!(check_not_paused(&deps.storage, PauseOperation::Staking, 100).is_ok());
+        assert!(check_not_paused(&deps.storage, PauseOperation::Claiming, 200).is_ok());
+
+        // a stake or withdraw during the pause settles the reward without paying it
+        let msg = reward_msg(
+            &mut deps.storage,
+            &alice,
+            &Addr::unchecked("alice"),
+            Uint128::from(500u128),
+            &reward_token,
+            "",
+            100,
+        );
+        assert!(msg.unwrap().is_none());
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(100);
+        assert!(
+            try_withdraw_vested(deps.as_mut(), env.clone(), &Addr::unchecked("alice")).is_err()
+        );
+
+        env.block.time = Timestamp::from_seconds(200);
+        let response = try_withdraw_vested(deps.as_mut(), env, &Addr::unchecked("alice")).unwrap();
+        assert_eq!(response.messages.len(), 1);
+        assert!(USER_VESTING_STORE.get(&deps.storage, &alice).is_none());
+    }
+
+    #[test]
+    fn operator_claims_until_expiry() {
+        let mut deps = mock_dependencies();
+        let mut env = mock_env();
+        let staker = Addr::unchecked("staker");
+        let keeper = Addr::unchecked("keeper");
+        let operator = Operator {
+            address: keeper.clone(),
+            scope: OperatorScope::Claim,
+            expires: Some(env.block.time.seconds() + 10),
+            recipient: None,
+        };
+        try_set_operator(deps.as_mut(), env.clone(), &staker, operator.clone()).unwrap();
+
+        assert_eq!(
+            get_operator(deps.as_ref(), &env, &keeper, &staker).unwrap(),
+            operator
+        );
+        assert!(get_operator(deps.as_ref(), &env, &staker, &keeper).is_err());
+        // compounding needs the restake scope
+        assert!(try_compound_for(deps.as_mut(), env.clone(), &keeper, staker.clone()).is_err());
+
+        env.block.time = env.block.time.plus_seconds(10);
+        assert!(get_operator(deps.as_ref(), &env, &keeper, &staker).is_err());
+    }
+
+    #[test]
+    fn compound_restakes_rewards_and_auto_compounds_on_stake() {
+        let mut deps = mock_dependencies();
+        let mut env = mock_env();
+        let alice = Addr::unchecked("alice");
+        let alice_raw = deps.api.addr_canonicalize(alice.as_str()).unwrap();
+        let staking_token = Addr::unchecked("staked");
+        let mut state = test_state(&Addr::unchecked("owner"));
+        CONFIG_ITEM.save(&mut deps.storage, &state).unwrap();
+        assert!(try_set_auto_compound(deps.as_mut(), &alice, true).is_err());
+
+        // one reward token per second, paid in the staking token
+        state.reward_contract.address = staking_token.clone();
+        state.reward_contract.rewards_per_day = Uint128::from(86400u128);
+        state.total_rewards = Uint128::from(1_000_000u128);
+        CONFIG_ITEM.save(&mut deps.storage, &state).unwrap();
+        let stake = |deps: DepsMut, env: Env, amount: u128| {
+            receive(
+                deps,
+                env,
+                &staking_token,
+                &alice,
+                &alice,
+                Uint128::from(amount),
+                to_binary(&HandleReceiveMsg::ReceiveStake { beneficiary: None }).ok(),
+            )
+        };
+        stake(deps.as_mut(), env.clone(), 100).unwrap();
+
+        env.block.time = env.block.time.plus_seconds(100);
+        try_compound(deps.as_mut(), env.clone(), &alice).unwrap();
+        assert!(try_compound(deps.as_mut(), env.clone(), &alice).is_err());
+        let staked = STAKED_STORE.get(&deps.storage, &alice_raw).unwrap();
+        assert_eq!(staked.staked_amount, Uint128::from(200u128));
+        let state = CONFIG_ITEM.load(&deps.storage).unwrap();
+        assert_eq!(state.total_staked_amount, Uint128::from(200u128));
+        assert_eq!(state.total_rewards, Uint128::from(999_900u128));
+
+        // with auto-compound on, staking more restakes the rewards instead of paying them
+        try_set_auto_compound(deps.as_mut(), &alice, true).unwrap();
+        env.block.time = env.block.time.plus_seconds(100);
+        let res = stake(deps.as_mut(), env, 50).unwrap();
+        assert!(res.messages.is_empty());
+        let staked = STAKED_STORE.get(&deps.storage, &alice_raw).unwrap();
+        assert_eq!(staked.staked_amount, Uint128::from(350u128));
+        let actions: Vec<HistoryAction> = user_history_store(&alice_raw)
+            .iter(&deps.storage)
+            .unwrap()
+            .map(|x| x.unwrap().action)
+            .collect();
+        assert_eq!(
+            actions,
+            vec![
+                HistoryAction::Stake,
+                HistoryAction::Compound,
+                HistoryAction::Compound,
+                HistoryAction::Stake
+            ]
+        );
+    }
+
+    #[test]
+    fn stakes_for_beneficiaries_follow_the_allow_list() {
+        let mut deps = mock_dependencies();
+        let env = mock_env();
+        let owner = Addr::unchecked("owner");
+        let carol = Addr::unchecked("carol");
+        let dave = Addr::unchecked("dave");
+        let erin = Addr::unchecked("erin");
+        CONFIG_ITEM
+            .save(&mut deps.storage, &test_state(&owner))
+            .unwrap();
+        let stake_for = |deps: DepsMut, from: &Addr, beneficiary: Option<Addr>| {
+            receive(
+                deps,
+                env.clone(),
+                &Addr::unchecked("staked"),
+                from,
+                from,
+                Uint128::from(10u128),
+                to_binary(&HandleReceiveMsg::ReceiveStake { beneficiary }).ok(),
+            )
+        };
+        let staked_amount = |deps: Deps, staker: &Addr| {
+            let staker_raw = deps.api.addr_canonicalize(staker.as_str()).unwrap();
+            STAKED_STORE
+                .get(deps.storage, &staker_raw)
+                .map_or(Uint128::zero(), |x| x.staked_amount)
+        };
+
+        // anyone can stake for others until an allow list is set
+        stake_for(deps.as_mut(), &carol, Some(dave.clone())).unwrap();
+        assert_eq!(staked_amount(deps.as_ref(), &dave), Uint128::from(10u128));
+        assert!(staked_amount(deps.as_ref(), &carol).is_zero());
+
+        assert!(
+            try_set_beneficiary_allow_list(deps.as_mut(), &carol, Some(vec![erin.clone()]))
+                .is_err()
+        );
+        try_set_beneficiary_allow_list(deps.as_mut(), &owner, Some(vec![erin.clone()])).unwrap();
+        assert!(stake_for(deps.as_mut(), &carol, Some(dave.clone())).is_err());
+        stake_for(deps.as_mut(), &erin, Some(dave.clone())).unwrap();
+        // staking for yourself is always allowed
+        stake_for(deps.as_mut(), &carol, Some(carol.clone())).unwrap();
+        assert_eq!(staked_amount(deps.as_ref(), &dave), Uint128::from(20u128));
+        assert_eq!(staked_amount(deps.as_ref(), &carol), Uint128::from(10u128));
+
+        try_set_beneficiary_allow_list(deps.as_mut(), &owner, None).unwrap();
+        stake_for(deps.as_mut(), &carol, Some(dave.clone())).unwrap();
+        assert_eq!(staked_amount(deps.as_ref(), &dave), Uint128::from(30u128));
+    }
+
+    #[test]
+    fn admin_actions_are_logged_without_keys() {
+        let mut deps = mock_dependencies();
+        let env = mock_env();
+        let owner = Addr::unchecked("owner");
+        CONFIG_ITEM
+            .save(&mut deps.storage, &test_state(&owner))
+            .unwrap();
+
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("owner", &[]),
+            ExecuteMsg::SetActiveState { is_active: false },
+        )
+        .unwrap();
+        // failed and non-admin actions aren't logged
+        assert!(execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            ExecuteMsg::SetActiveState { is_active: true },
+        )
+        .is_err());
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            ExecuteMsg::CreateViewingKey {
+                entropy: "alice".to_string(),
+            },
+        )
+        .unwrap();
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("owner", &[]),
+            ExecuteMsg::CreateViewingKey {
+                entropy: "owner".to_string(),
+            },
+        )
+        .unwrap();
+
+        let entries = ADMIN_LOG_STORE.iter(&deps.storage).unwrap();
+        let entries: Vec<AdminLogEntry> = entries.map(|x| x.unwrap()).collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].actor, owner);
+        assert_eq!(entries[0].action, "set_active_state");
+        assert_eq!(
+            entries[0].params,
+            r#"{"set_active_state":{"is_active":false}}"#
+        );
+        assert_eq!(entries[0].date, env.block.time.seconds());
+        assert_eq!(entries[1].action, "create_viewing_key");
+        assert_eq!(entries[1].params, "");
+        assert!(page_store(&deps.storage, &ADMIN_LOG_STORE, u32::MAX, 2)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn created_viewing_keys_are_random_and_authenticate() {
+        let mut deps = mock_dependencies();
+        let env = mock_env();
+        let alice = Addr::unchecked("alice");
+        CONFIG_ITEM
+            .save(&mut deps.storage, &test_state(&Addr::unchecked("owner")))
+            .unwrap();
+        let create_key = |deps: DepsMut| {
+            let response =
+                try_create_viewing_key(deps, env.clone(), &alice, "seed".to_string()).unwrap();
+            from_binary::<ViewingKeyResponse>(&response.data.unwrap())
+                .unwrap()
+                .key
+        };
+
+        let first = create_key(deps.as_mut());
+        // the seed rotates, so the same entropy never gives the same key twice
+        let key = create_key(deps.as_mut());
+        assert!(key.starts_with(VIEWING_KEY_PREFIX));
+        assert_ne!(first, key);
+
+        let viewer = |viewing_key: &str| {
+            Some(ViewerInfo {
+                address: alice.to_string(),
+                viewing_key: viewing_key.to_string(),
+            })
+        };
+        let contract = env.contract.address.clone();
+        let querier = get_querier(deps.as_ref(), None, viewer(&key), contract.clone()).unwrap();
+        assert_eq!(querier, deps.api.addr_canonicalize("alice").unwrap());
+        assert!(get_querier(deps.as_ref(), None, viewer(&first), contract.clone()).is_err());
+        // only a hash of the key is stored
+        let stored = USER_VIEWING_KEYS_STORE
+            .get(&deps.storage, &querier)
+            .unwrap();
+        assert_ne!(stored, key);
+
+        // a key that isn't the owner's doesn't unlock admin queries
+        assert!(check_admin_key(deps.as_ref(), viewer(&key).unwrap()).is_err());
+    }
+
+    #[test]
+    fn admin_keys_unlock_staker_queries_until_revoked() {
+        let mut deps = mock_dependencies();
+        let env = mock_env();
+        let owner = Addr::unchecked("owner");
+        let alice = Addr::unchecked("alice");
+        CONFIG_ITEM
+            .save(&mut deps.storage, &test_state(&owner))
+            .unwrap();
+        receive(
+            deps.as_mut(),
+            env.clone(),
+            &Addr::unchecked("staked"),
+            &alice,
+            &alice,
+            Uint128::from(25u128),
+            to_binary(&HandleReceiveMsg::ReceiveStake { beneficiary: None }).ok(),
+        )
+        .unwrap();
+        let mut create_key = |sender: &Addr| {
+            let response =
+                try_create_viewing_key(deps.as_mut(), env.clone(), sender, "seed".to_string())
+                    .unwrap();
+            let key = from_binary::<ViewingKeyResponse>(&response.data.unwrap())
+                .unwrap()
+                .key;
+            ViewerInfo {
+                address: sender.to_string(),
+                viewing_key: key,
+            }
+        };
+        let admin = create_key(&owner);
+        let user = create_key(&alice);
+
+        let info = query_staker_info(
+            deps.as_ref(),
+            env.clone(),
+            admin.clone(),
+            "alice".to_string(),
+        )
+        .unwrap();
+        assert_eq!(info.staked.staked_amount, Uint128::from(25u128));
+        let history =
+            query_staker_history(deps.as_ref(), admin.clone(), "alice".to_string(), 0, 10).unwrap();
+        assert_eq!(history.len(), 1);
+        assert!(query_staker_info(deps.as_ref(), env.clone(), user, "alice".to_string()).is_err());
+
+        let stakers =
+            query_list_stakers(deps.as_ref(), env.clone(), admin.clone(), None, None).unwrap();
+        assert_eq!(stakers.stakers.len(), 1);
+        let stakers = query_list_stakers(
+            deps.as_ref(),
+            env.clone(),
+            admin.clone(),
+            Some(u32::MAX),
+            None,
+        )
+        .unwrap();
+        assert!(stakers.stakers.is_empty() && stakers.next.is_none());
+
+        try_revoke_admin_viewing_keys(deps.as_mut(), &owner).unwrap();
+        assert!(query_staker_info(deps.as_ref(), env, admin, "alice".to_string()).is_err());
+    }
+
+    #[test]
+    fn transfer_position_keeps_totals_and_records_history() {
+        let mut deps = mock_dependencies();
+        let env = mock_env();
+        let alice = Addr::unchecked("alice");
+        let bob = Addr::unchecked("bob");
+        let mut state = test_state(&Addr::unchecked("owner"));
+        CONFIG_ITEM.save(&mut deps.storage, &state).unwrap();
+        receive(
+            deps.as_mut(),
+            env.clone(),
+            &Addr::unchecked("staked"),
+            &alice,
+            &alice,
+            Uint128::from(100u128),
+            to_binary(&HandleReceiveMsg::ReceiveStake { beneficiary: None }).ok(),
+        )
+        .unwrap();
+        let alice_raw = deps.api.addr_canonicalize(alice.as_str()).unwrap();
+        let bob_raw = deps.api.addr_canonicalize(bob.as_str()).unwrap();
+
+        assert!(try_transfer_position(
+            deps.as_mut(),
+            env.clone(),
+            &alice,
+            bob.clone(),
+            Uint128::from(101u128)
+        )
+        .is_err());
+        try_transfer_position(
+            deps.as_mut(),
+            env.clone(),
+            &alice,
+            bob.clone(),
+            Uint128::from(40u128),
+        )
+        .unwrap();
+        let staked = |storage: &dyn Storage, staker_raw: &CanonicalAddr| {
+            STAKED_STORE.get(storage, staker_raw).unwrap()
+        };
+        assert_eq!(
+            staked(&deps.storage, &alice_raw).staked_amount,
+            Uint128::from(60u128)
+        );
+        assert_eq!(
+            staked(&deps.storage, &bob_raw).staked_amount,
+            Uint128::from(40u128)
+        );
+        assert_eq!(
+            CONFIG_ITEM.load(&deps.storage).unwrap().total_staked_amount,
+            Uint128::from(100u128)
+        );
+        for (staker_raw, action) in [
+            (&alice_raw, HistoryAction::TransferOut),
+            (&bob_raw, HistoryAction::TransferIn),
+        ] {
+            let history = user_history_store(staker_raw);
+            let last = history
+                .get_at(&deps.storage, history.get_len(&deps.storage).unwrap() - 1)
+                .unwrap();
+            assert_eq!(last.action, action);
+            assert_eq!(last.amount, Uint128::from(40u128));
+        }
+
+        // a full transfer clears the sender's position
+        try_transfer_position(
+            deps.as_mut(),
+            env.clone(),
+            &alice,
+            bob.clone(),
+            Uint128::from(60u128),
+        )
+        .unwrap();
+        assert!(staked(&deps.storage, &alice_raw).staked_amount.is_zero());
+        assert_eq!(staked(&deps.storage, &alice_raw).last_staked_date, None);
+        assert_eq!(
+            staked(&deps.storage, &bob_raw).staked_amount,
+            Uint128::from(100u128)
+        );
+
+        // with a receipt token positions move with the receipts
+        state.total_staked_amount = Uint128::from(100u128);
+        state.receipt_contract = Some(state.staking_contract.clone());
+        CONFIG_ITEM.save(&mut deps.storage, &state).unwrap();
+        assert!(
+            try_transfer_position(deps.as_mut(), env, &bob, alice, Uint128::from(1u128)).is_err()
+        );
+    }
+
+    #[test]
+    fn receipts_mint_on_stake_and_redeem_for_holder() {
+        let mut deps = mock_dependencies();
+        let env = mock_env();
+        let owner = Addr::unchecked("owner");
+        let alice = Addr::unchecked("alice");
+        let bob = Addr::unchecked("bob");
+        let staking_token = Addr::unchecked("staked");
+        let receipt_token = Addr::unchecked("receipt");
+        let mut state = test_state(&owner);
+        state.receipt_contract = Some(ContractInfo {
+            code_hash: "".to_string(),
+            address: receipt_token.clone(),
+            name: "".to_string(),
+            stake_type: "".to_string(),
+        });
+        CONFIG_ITEM.save(&mut deps.storage, &state).unwrap();
+        let stake_msg = to_binary(&HandleReceiveMsg::ReceiveStake { beneficiary: None }).unwrap();
+        let redeem_msg = to_binary(&HandleReceiveMsg::Redeem {}).ok();
+        let alice_raw = deps.api.addr_canonicalize(alice.as_str()).unwrap();
+
+        let res = receive(
+            deps.as_mut(),
+            env.clone(),
+            &staking_token,
+            &alice,
+            &alice,
+            Uint128::from(100u128),
+            Some(stake_msg),
+        )
+        .unwrap();
+        let minted = mint_msg(
+            alice.to_string(),
+            Uint128::from(100u128),
+            None,
+            None,
+            BLOCK_SIZE,
+            "".to_string(),
+            receipt_token.to_string(),
+        )
+        .unwrap();
+        assert!(res.messages.iter().any(|x| x.msg == minted));
+
+        // the principal only leaves through the receipts
+        assert!(try_withdraw(deps.as_mut(), env.clone(), &alice).is_err());
+        assert!(receive(
+            deps.as_mut(),
+            env.clone(),
+            &staking_token,
+            &bob,
+            &bob,
+            Uint128::from(40u128),
+            redeem_msg.clone(),
+        )
+        .is_err());
+
+        // bob bought receipts from alice but can't unstake her position with them
+        assert!(receive(
+            deps.as_mut(),
+            env.clone(),
+            &receipt_token,
+            &bob,
+            &bob,
+            Uint128::from(40u128),
+            Some(Binary::from(br#"{"redeem":{"staker":"alice"}}"#.to_vec())),
+        )
+        .is_err());
+        let staked = STAKED_STORE.get(&deps.storage, &alice_raw).unwrap();
+        assert_eq!(staked.staked_amount, Uint128::from(100u128));
+
+        let res = receive(
+            deps.as_mut(),
+            env.clone(),
+            &receipt_token,
+            &alice,
+            &alice,
+            Uint128::from(40u128),
+            redeem_msg.clone(),
+        )
+        .unwrap();
+        let burned = burn_msg(
+            Uint128::from(40u128),
+            None,
+            None,
+            BLOCK_SIZE,
+            "".to_string(),
+            receipt_token.to_string(),
+        )
+        .unwrap();
+        assert_eq!(res.messages[0].msg, burned);
+        assert_eq!(res.messages.len(), 2);
+        let staked = STAKED_STORE.get(&deps.storage, &alice_raw).unwrap();
+        assert_eq!(staked.staked_amount, Uint128::from(60u128));
+        let state = CONFIG_ITEM.load(&deps.storage).unwrap();
+        assert_eq!(state.total_staked_amount, Uint128::from(60u128));
+
+        // an ejected stake stays redeemable by whoever holds its receipts
+        let res = try_eject(deps.as_mut(), env.clone(), &owner, &alice).unwrap();
+        assert!(res.messages.is_empty());
+        assert_eq!(
+            REDEEMABLE_ITEM.load(&deps.storage).unwrap(),
+            Uint128::from(60u128)
+        );
+        receive(
+            deps.as_mut(),
+            env.clone(),
+            &receipt_token,
+            &bob,
+            &bob,
+            Uint128::from(60u128),
+            redeem_msg.clone(),
+        )
+        .unwrap();
+        assert_eq!(
+            REDEEMABLE_ITEM.load(&deps.storage).unwrap(),
+            Uint128::zero()
+        );
+        assert!(receive(
+            deps.as_mut(),
+            env,
+            &receipt_token,
+            &alice,
+            &alice,
+            Uint128::from(1u128),
+            redeem_msg,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn transfer_position_needs_the_accrued_rewards_covered() {
+        let mut deps = mock_dependencies();
+        let mut env = mock_env();
+        let alice = Addr::unchecked("alice");
+        let bob = Addr::unchecked("bob");
+        let mut state = test_state(&Addr::unchecked("owner"));
+        // one reward a second
+        state.reward_contract.rewards_per_day = Uint128::from(86400u128);
+        CONFIG_ITEM.save(&mut deps.storage, &state).unwrap();
+        receive(
+            deps.as_mut(),
+            env.clone(),
+            &Addr::unchecked("staked"),
+            &alice,
+            &alice,
+            Uint128::from(100u128),
+            to_binary(&HandleReceiveMsg::ReceiveStake { beneficiary: None }).ok(),
+        )
+        .unwrap();
+        let alice_raw = deps.api.addr_canonicalize(alice.as_str()).unwrap();
+        let staked_at = env.block.time.seconds();
+        env.block.time = env.block.time.plus_seconds(100);
+
+        let mut state = CONFIG_ITEM.load(&deps.storage).unwrap();
+        state.total_rewards = Uint128::from(99u128);
+        CONFIG_ITEM.save(&mut deps.storage, &state).unwrap();
+        assert!(try_transfer_position(
+            deps.as_mut(),
+            env.clone(),
+            &alice,
+            bob.clone(),
+            Uint128::from(40u128)
+        )
+        .is_err());
+        let staked = STAKED_STORE.get(&deps.storage, &alice_raw).unwrap();
+        assert_eq!(staked.staked_amount, Uint128::from(100u128));
+        assert_eq!(staked.last_claimed_date.unwrap_or(staked_at), staked_at);
+
+        // a pool holding exactly what's owed pays it out
+        state.total_rewards = Uint128::from(100u128);
+        CONFIG_ITEM.save(&mut deps.storage, &state).unwrap();
+        try_transfer_position(
+            deps.as_mut(),
+            env.clone(),
+            &alice,
+            bob.clone(),
+            Uint128::from(40u128),
+        )
+        .unwrap();
+        assert!(CONFIG_ITEM
+            .load(&deps.storage)
+            .unwrap()
+            .total_rewards
+            .is_zero());
+        let staked = STAKED_STORE.get(&deps.storage, &alice_raw).unwrap();
+        assert_eq!(staked.staked_amount, Uint128::from(60u128));
+        assert_eq!(staked.last_claimed_date, Some(env.block.time.seconds()));
+    }
+
+    fn test_state(owner: &Addr) -> State {
+        State {
+            owner: owner.clone(),
+            is_active: true,
+            staking_contract: ContractInfo {
+                code_hash: "".to_string(),
+                address: Addr::unchecked("staked"),
+                name: "".to_string(),
+                stake_type: "".to_string(),
+            },
+            reward_contract: RewardsContractInfo {
+                code_hash: "".to_string(),
+                address: Addr::unchecked("reward"),
+                rewards_per_day: Uint128::from(0u128),
+                name: "".to_string(),
+            },
+            viewing_key: None,
+            total_staked_amount: Uint128::from(0u128),
+            total_rewards: Uint128::from(0u128),
+            receipt_contract: None,
+        }
+    }
 }
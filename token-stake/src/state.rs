@@ -1,7 +1,11 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use crate::msg::{ContractInfo, History, RewardsContractInfo, Staked};
+use crate::msg::{
+    AdminLogEntry, ContractInfo, History, Incentive, Operator, PauseFlags, PendingConfigChange,
+    Raffle, RewardsContractInfo, Role, SponsorList, Staked, VestingConfig, VestingSchedule,
+    VestingTotal,
+};
 use cosmwasm_std::{Addr, CanonicalAddr, Uint128};
 use secret_toolkit::{
     snip721::ViewerInfo,
@@ -13,11 +17,87 @@ pub const PREFIX_REVOKED_PERMITS: &str = "revoke";
 pub const HISTORY_KEY: &[u8] = b"history";
 pub const STAKED_KEY: &[u8] = b"staked";
 pub const ADMIN_VIEWING_KEY: &[u8] = b"admin_viewing_key";
+pub const ADMIN_VIEWING_KEYS_KEY: &[u8] = b"admin_viewing_keys";
+pub const PAUSE_KEY: &[u8] = b"pause";
+pub const PENDING_OWNER_KEY: &[u8] = b"pending_owner";
+pub const ROLES_KEY: &[u8] = b"roles";
+pub const TIMELOCK_KEY: &[u8] = b"timelock";
+pub const CONFIG_CHANGES_KEY: &[u8] = b"config_changes";
+pub const CONFIG_CHANGE_ID_KEY: &[u8] = b"config_change_id";
+pub const ADMIN_LOG_KEY: &[u8] = b"admin_log";
+pub const PRNG_SEED_KEY: &[u8] = b"prng_seed";
+pub const USER_VIEWING_KEYS_KEY: &[u8] = b"user_viewing_keys";
+pub const CHECKPOINTS_KEY: &[u8] = b"checkpoints";
+pub const RAFFLES_KEY: &[u8] = b"raffles";
+pub const RAFFLE_TOKEN_PRIZES_KEY: &[u8] = b"raffle_token_prizes";
+pub const RAFFLE_NFT_PRIZES_KEY: &[u8] = b"raffle_nft_prizes";
+pub const DIVIDEND_POOLS_KEY: &[u8] = b"dividend_pools";
+pub const USER_DIVIDENDS_KEY: &[u8] = b"user_dividends";
+pub const INCENTIVES_KEY: &[u8] = b"incentives";
+pub const INCENTIVE_ID_KEY: &[u8] = b"incentive_id";
+pub const SPONSOR_LIST_KEY: &[u8] = b"sponsor_list";
+pub const BENEFICIARY_ALLOW_LIST_KEY: &[u8] = b"beneficiary_allow_list";
+pub const VESTING_KEY: &[u8] = b"vesting";
+pub const USER_VESTING_KEY: &[u8] = b"user_vesting";
+pub const OPERATORS_KEY: &[u8] = b"operators";
+pub const VESTING_TOTALS_KEY: &[u8] = b"vesting_totals";
+pub const AUTO_COMPOUND_KEY: &[u8] = b"auto_compound";
+pub const TOTAL_CHECKPOINTS_KEY: &[u8] = b"total_checkpoints";
+pub const MIGRATION_KEY: &[u8] = b"migration";
+pub const SHUTDOWN_KEY: &[u8] = b"shutdown";
+pub const REDEEMABLE_KEY: &[u8] = b"redeemable";
 
 pub static CONFIG_ITEM: Item<State> = Item::new(CONFIG_KEY);
+pub static LEGACY_CONFIG_ITEM: Item<LegacyState> = Item::new(CONFIG_KEY);
 pub static HISTORY_STORE: AppendStore<History> = AppendStore::new(HISTORY_KEY);
+pub static LEGACY_HISTORY_STORE: AppendStore<LegacyHistory> = AppendStore::new(HISTORY_KEY);
 pub static STAKED_STORE: Keymap<CanonicalAddr, Staked> = Keymap::new(STAKED_KEY);
+/// Single admin key used before several keys were supported, moved on migrate
 pub static ADMIN_VIEWING_KEY_ITEM: Item<ViewerInfo> = Item::new(ADMIN_VIEWING_KEY);
+/// Hashed admin viewing keys
+pub static ADMIN_VIEWING_KEYS_ITEM: Item<Vec<ViewerInfo>> = Item::new(ADMIN_VIEWING_KEYS_KEY);
+pub static PAUSE_ITEM: Item<PauseFlags> = Item::new(PAUSE_KEY);
+pub static PENDING_OWNER_ITEM: Item<Addr> = Item::new(PENDING_OWNER_KEY);
+pub static ROLES_STORE: Keymap<CanonicalAddr, Vec<Role>> = Keymap::new(ROLES_KEY);
+pub static TIMELOCK_ITEM: Item<u64> = Item::new(TIMELOCK_KEY);
+pub static CONFIG_CHANGES_STORE: Keymap<u64, PendingConfigChange> = Keymap::new(CONFIG_CHANGES_KEY);
+pub static CONFIG_CHANGE_ID_ITEM: Item<u64> = Item::new(CONFIG_CHANGE_ID_KEY);
+pub static ADMIN_LOG_STORE: AppendStore<AdminLogEntry> = AppendStore::new(ADMIN_LOG_KEY);
+pub static PRNG_SEED_ITEM: Item<Vec<u8>> = Item::new(PRNG_SEED_KEY);
+/// Hashed viewing keys by user
+pub static USER_VIEWING_KEYS_STORE: Keymap<CanonicalAddr, String> =
+    Keymap::new(USER_VIEWING_KEYS_KEY);
+pub static CHECKPOINTS_STORE: AppendStore<Checkpoint> = AppendStore::new(CHECKPOINTS_KEY);
+pub static RAFFLES_STORE: AppendStore<Raffle> = AppendStore::new(RAFFLES_KEY);
+/// Raffle prize tokens deposited and not yet raffled, by token
+pub static RAFFLE_TOKEN_PRIZES_STORE: Keymap<Addr, Uint128> = Keymap::new(RAFFLE_TOKEN_PRIZES_KEY);
+/// Raffle prize NFTs deposited and not yet raffled, by collection
+pub static RAFFLE_NFT_PRIZES_STORE: Keymap<Addr, Vec<String>> = Keymap::new(RAFFLE_NFT_PRIZES_KEY);
+pub static DIVIDEND_POOLS_ITEM: Item<Vec<DividendPool>> = Item::new(DIVIDEND_POOLS_KEY);
+pub static USER_DIVIDENDS_STORE: Keymap<CanonicalAddr, Vec<UserDividend>> =
+    Keymap::new(USER_DIVIDENDS_KEY);
+/// Sponsor incentives still streaming or waiting for a refund
+pub static INCENTIVES_ITEM: Item<Vec<Incentive>> = Item::new(INCENTIVES_KEY);
+pub static INCENTIVE_ID_ITEM: Item<u64> = Item::new(INCENTIVE_ID_KEY);
+pub static SPONSOR_LIST_ITEM: Item<SponsorList> = Item::new(SPONSOR_LIST_KEY);
+/// Who can stake on behalf of others, unset while anyone can
+pub static BENEFICIARY_ALLOW_LIST_ITEM: Item<Vec<Addr>> = Item::new(BENEFICIARY_ALLOW_LIST_KEY);
+/// Unset while claims are paid out right away
+pub static VESTING_ITEM: Item<VestingConfig> = Item::new(VESTING_KEY);
+pub static USER_VESTING_STORE: Keymap<CanonicalAddr, Vec<VestingSchedule>> =
+    Keymap::new(USER_VESTING_KEY);
+/// The operator each staker granted
+pub static OPERATORS_STORE: Keymap<CanonicalAddr, Operator> = Keymap::new(OPERATORS_KEY);
+pub static VESTING_TOTALS_ITEM: Item<Vec<VestingTotal>> = Item::new(VESTING_TOTALS_KEY);
+/// Stakers who opted into auto-compounding
+pub static AUTO_COMPOUND_STORE: Keymap<CanonicalAddr, bool> = Keymap::new(AUTO_COMPOUND_KEY);
+/// Pool total staked amount over time
+pub static TOTAL_CHECKPOINTS_STORE: AppendStore<Checkpoint> =
+    AppendStore::new(TOTAL_CHECKPOINTS_KEY);
+pub static SHUTDOWN_ITEM: Item<Shutdown> = Item::new(SHUTDOWN_KEY);
+pub static MIGRATION_ITEM: Item<Migration> = Item::new(MIGRATION_KEY);
+/// Principal of ejected or returned positions, held for whoever redeems the receipts
+pub static REDEEMABLE_ITEM: Item<Uint128> = Item::new(REDEEMABLE_KEY);
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
 pub struct State {
@@ -28,4 +108,78 @@ pub struct State {
     pub viewing_key: Option<String>,
     pub total_staked_amount: Uint128,
     pub total_rewards: Uint128,
+    /// Receipt token minted 1:1 on stake, the pool has to be one of its minters.
+    /// Receipts are the only way to get the principal back: holders send them back
+    /// with `Redeem`. Stakes ejected or returned by the admin stay redeemable.
+    pub receipt_contract: Option<ContractInfo>,
+}
+
+/// `State` as it was stored before receipt tokens.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct LegacyState {
+    pub owner: Addr,
+    pub is_active: bool,
+    pub staking_contract: ContractInfo,
+    pub reward_contract: RewardsContractInfo,
+    pub viewing_key: Option<String>,
+    pub total_staked_amount: Uint128,
+    pub total_rewards: Uint128,
+}
+
+/// Set once the owner triggers an emergency shutdown. Rewards stop accruing at
+/// `shutdown_time` and `cursor` is the next `STAKED_STORE` index to pay back.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct Shutdown {
+    pub shutdown_time: u64,
+    pub cursor: u32,
+}
+
+/// Set by `migrate`. Stakers are then migrated a page at a time through
+/// `MigrateHistory`, `cursor` is the next `STAKED_STORE` index to migrate.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct Migration {
+    pub time: u64,
+    pub cursor: u32,
+}
+
+/// A staked amount as of `time`, kept in time order so past balances can be looked up.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct Checkpoint {
+    pub time: u64,
+    pub amount: Uint128,
+}
+
+/// Dividends paid per staked unit in one reward token, scaled by `DIVIDEND_SCALE`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct DividendPool {
+    pub reward_token: Addr,
+    pub code_hash: String,
+    pub acc_per_share: Uint128,
+}
+
+/// A staker's dividends in one reward token. `paid_per_share` is the pool's
+/// `acc_per_share` when `owed` was last brought up to date.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct UserDividend {
+    pub reward_token: Addr,
+    pub paid_per_share: Uint128,
+    pub owed: Uint128,
+}
+
+/// `History` as it was stored before actions were typed, under the human address.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct LegacyHistory {
+    pub amount: Uint128,
+    pub date: u64,
+    pub action: String,
+}
+
+/// Each user's history is stored under their canonical address.
+pub fn user_history_store(user_raw: &CanonicalAddr) -> AppendStore<'static, History> {
+    HISTORY_STORE.add_suffix(user_raw.as_slice())
+}
+
+/// Each user's checkpoints are stored under their canonical address.
+pub fn user_checkpoint_store(user_raw: &CanonicalAddr) -> AppendStore<'static, Checkpoint> {
+    CHECKPOINTS_STORE.add_suffix(user_raw.as_slice())
 }